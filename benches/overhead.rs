@@ -0,0 +1,80 @@
+//! Measures the cost of entering and leaving scopes for different shapes of
+//! the scope tree.
+
+use coarse_prof::{profile, ScopeName};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// A single scope below the root, entered over and over.
+fn flat(c: &mut Criterion) {
+    coarse_prof::reset();
+    profile!("root");
+
+    c.bench_function("flat", |b| {
+        b.iter(|| {
+            profile!("scope");
+        })
+    });
+}
+
+/// A chain of eight nested scopes.
+fn deep(c: &mut Criterion) {
+    coarse_prof::reset();
+    profile!("root");
+
+    c.bench_function("deep", |b| {
+        b.iter(|| {
+            profile!("a");
+            profile!("b");
+            profile!("c");
+            profile!("d");
+            profile!("e");
+            profile!("f");
+            profile!("g");
+            profile!("h");
+        })
+    });
+}
+
+/// Many siblings with different names, as e.g. in a loop over systems.
+fn wide(c: &mut Criterion) {
+    let names: Vec<_> = (0..64)
+        .map(|i| ScopeName::from(format!("system_{}", i)))
+        .collect();
+
+    coarse_prof::reset();
+    profile!("root");
+
+    c.bench_function("wide", |b| {
+        b.iter(|| {
+            for &name in &names {
+                let _guard = coarse_prof::enter_name(name);
+            }
+        })
+    });
+}
+
+/// Root scopes, which are checked for periodic tasks between frames.
+fn roots(c: &mut Criterion) {
+    coarse_prof::reset();
+
+    c.bench_function("roots", |b| {
+        b.iter(|| {
+            profile!("frame");
+        })
+    });
+}
+
+/// Scopes whose names are only known at runtime.
+fn owned(c: &mut Criterion) {
+    coarse_prof::reset();
+    profile!("root");
+
+    c.bench_function("owned", |b| {
+        b.iter(|| {
+            let _guard = coarse_prof::enter_owned(format!("entity_{}", 7));
+        })
+    });
+}
+
+criterion_group!(benches, flat, deep, wide, roots, owned);
+criterion_main!(benches);
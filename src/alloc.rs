@@ -0,0 +1,83 @@
+//! Attribution of heap allocations to scopes.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local!(
+    // Note that these must not allocate or register destructors, since they
+    // are used inside of the allocator.
+    static NUM_ALLOCS: Cell<u64> = const { Cell::new(0) };
+    static NUM_BYTES: Cell<u64> = const { Cell::new(0) };
+);
+
+/// Has `CoarseProfAlloc` been used yet?
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// A global allocator that counts the allocations of every thread, so that
+/// they can be attributed to the active scope.
+///
+/// After enabling this with
+/// [`set_measure_allocs`](fn.set_measure_allocs.html), the number of
+/// allocations and the number of allocated bytes of every call of a scope are
+/// attached to the scope as the attributes `allocs` and
+/// `alloc_bytes`, see [`attr`](fn.attr.html). Allocations in child scopes are
+/// included in their parents. Deallocations are not tracked. The actual
+/// allocation is forwarded to the wrapped allocator, which is usually
+/// `std::alloc::System`.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::CoarseProfAlloc;
+///
+/// #[global_allocator]
+/// static GLOBAL: CoarseProfAlloc = CoarseProfAlloc(std::alloc::System);
+///
+/// coarse_prof::set_measure_allocs(true);
+/// ```
+pub struct CoarseProfAlloc<A = System>(pub A);
+
+impl<A> CoarseProfAlloc<A> {
+    fn count(&self, size: usize) {
+        // The thread-locals may already have been destroyed if this is
+        // called while the thread exits.
+        let _ = NUM_ALLOCS.try_with(|n| n.set(n.get() + 1));
+        let _ = NUM_BYTES.try_with(|n| n.set(n.get() + size as u64));
+
+        if !INSTALLED.load(Ordering::Relaxed) {
+            INSTALLED.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CoarseProfAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count(new_size);
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+/// Returns the number of allocations and allocated bytes of the current
+/// thread so far, or `None` if `CoarseProfAlloc` is not being used.
+pub fn sample() -> Option<(u64, u64)> {
+    if INSTALLED.load(Ordering::Relaxed) {
+        Some((NUM_ALLOCS.with(Cell::get), NUM_BYTES.with(Cell::get)))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,110 @@
+//! Command line tool for viewing profiles that have been saved with
+//! `ProfileSnapshot::write_json`. Profiles in the pprof format can be viewed
+//! with `go tool pprof` instead.
+//!
+//! Install with `cargo install coarse-prof --features cli`.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
+
+use coarse_prof::{ProfileSnapshot, ReportOptions, ScopeStats};
+
+const USAGE: &str = "\
+Usage:
+    coarse-prof show [--flat | --inverted] <profile.json>
+    coarse-prof flamegraph <profile.json> [out.svg]
+    coarse-prof diff <before.json> <after.json>
+
+Profiles are JSON files written by `ProfileSnapshot::write_json`.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["show", path] => show(path, |snapshot| snapshot),
+        ["show", "--flat", path] => show(path, |snapshot| snapshot.flat()),
+        ["show", "--inverted", path] => show(path, |snapshot| snapshot.inverted()),
+        ["flamegraph", path] => flamegraph(path, &mut io::stdout()),
+        ["flamegraph", path, out] => {
+            std::fs::File::create(out).and_then(|mut file| flamegraph(path, &mut file))
+        }
+        ["diff", before, after] => diff(before, after),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("coarse-prof: {}", error);
+        process::exit(1);
+    }
+}
+
+fn read(path: &str) -> io::Result<ProfileSnapshot> {
+    std::fs::read_to_string(path)
+        .and_then(|json| ProfileSnapshot::read_json(&json))
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path, error)))
+}
+
+fn show(path: &str, transform: fn(ProfileSnapshot) -> ProfileSnapshot) -> io::Result<()> {
+    let snapshot = transform(read(path)?);
+    let options = ReportOptions::new();
+    write!(io::stdout(), "{}", snapshot.report(&options))
+}
+
+fn flamegraph<W: Write>(path: &str, out: &mut W) -> io::Result<()> {
+    read(path)?.write_flamegraph_svg(out)
+}
+
+/// Print the mean duration of every scope path in both profiles, and how much
+/// it has changed.
+fn diff(before: &str, after: &str) -> io::Result<()> {
+    let mut means: BTreeMap<String, (Option<Duration>, Option<Duration>)> = BTreeMap::new();
+    collect_means(&read(before)?.roots, "", &mut |path, mean| {
+        means.entry(path).or_default().0 = Some(mean);
+    });
+    collect_means(&read(after)?.roots, "", &mut |path, mean| {
+        means.entry(path).or_default().1 = Some(mean);
+    });
+
+    let mut out = io::stdout();
+    for (path, means) in means {
+        match means {
+            // Scopes that have not been called before have no mean to compare
+            // with.
+            (Some(before), Some(after)) if before.is_zero() => {
+                writeln!(out, "{}: added -> {:.2?}", path, after)?
+            }
+            (Some(before), Some(after)) => {
+                let change = (after.as_secs_f64() / before.as_secs_f64() - 1.0) * 100.0;
+                writeln!(
+                    out,
+                    "{}: {:.2?} -> {:.2?} ({:+.2}%)",
+                    path, before, after, change
+                )?;
+            }
+            (Some(before), None) => writeln!(out, "{}: {:.2?} -> removed", path, before)?,
+            (None, Some(after)) => writeln!(out, "{}: added -> {:.2?}", path, after)?,
+            (None, None) => (),
+        }
+    }
+    Ok(())
+}
+
+fn collect_means(scopes: &[ScopeStats], prefix: &str, f: &mut impl FnMut(String, Duration)) {
+    for scope in scopes {
+        let path = if prefix.is_empty() {
+            scope.name.clone()
+        } else {
+            format!("{}/{}", prefix, scope.name)
+        };
+        collect_means(&scope.children, &path, f);
+        if scope.num_calls > 0 {
+            f(path, scope.duration_mean());
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Independent profilers on the same thread.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+use crate::error::{self, Error};
+use crate::{Guard, ProfileSnapshot, Profiler, ScopeName};
+
+thread_local!(
+    static CHANNELS: RefCell<HashMap<&'static str, Channel>> = RefCell::new(HashMap::new())
+);
+
+/// A named profiler of the current thread that is independent of the global
+/// thread-local profiler.
+///
+/// Every channel has its own scope tree, which is reset and reported
+/// separately, so that unrelated concerns that are instrumented in the same
+/// thread, such as gameplay and an editor, do not interleave in one report.
+/// Channels are created by [`channel`](fn.channel.html).
+///
+/// # Example
+///
+/// ```
+/// let editor = coarse_prof::channel("editor");
+///
+/// for _ in 0..10 {
+///     coarse_prof::profile!("frame");
+///
+///     {
+///         let _guard = editor.enter("gizmos");
+///         // ... draw gizmos ...
+///     }
+/// }
+///
+/// // Only contains `gizmos`.
+/// editor.write(&mut std::io::stdout()).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Channel {
+    profiler: Rc<RefCell<Profiler>>,
+}
+
+/// Returns the channel called `name` of the current thread, creating it if
+/// necessary.
+pub fn channel(name: &'static str) -> Channel {
+    CHANNELS.with(|channels| {
+        channels
+            .borrow_mut()
+            .entry(name)
+            .or_insert_with(|| Channel {
+                profiler: Rc::new(RefCell::new(Profiler::new())),
+            })
+            .clone()
+    })
+}
+
+impl Channel {
+    /// Enter a scope in this channel. The returned
+    /// [`Guard`](struct.Guard.html) should be dropped when leaving the scope.
+    pub fn enter(&self, name: &'static str) -> Guard {
+        self.enter_with(|p| p.enter(name))
+    }
+
+    /// Enter a scope with an interned name in this channel.
+    pub fn enter_name(&self, name: ScopeName) -> Guard {
+        self.enter_with(|p| p.enter_name(name))
+    }
+
+    /// Enter a scope with `enter`, and let the guard leave it in this
+    /// channel.
+    fn enter_with(&self, enter: impl FnOnce(&mut Profiler) -> Guard) -> Guard {
+        match self.with_profiler(enter) {
+            Some(mut guard) => {
+                guard.profiler = Some(self.profiler.clone());
+                guard
+            }
+            None => Guard::disabled(),
+        }
+    }
+
+    /// Write the report of this channel.
+    pub fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.with_profiler(|p| p.write(out)).unwrap_or(Ok(()))
+    }
+
+    /// Take a snapshot of the scope tree of this channel.
+    pub fn snapshot(&self) -> ProfileSnapshot {
+        self.with_profiler(|p| p.snapshot(false))
+            .unwrap_or_default()
+    }
+
+    /// Reset the profiling information of this channel.
+    pub fn reset(&self) {
+        self.with_profiler(|p| p.reset());
+    }
+
+    /// Call `f` with the profiler of this channel. Returns `None` if the
+    /// profiler is already in use, e.g. by a writer that enters a scope of
+    /// this channel while its report is being written.
+    fn with_profiler<R>(&self, f: impl FnOnce(&mut Profiler) -> R) -> Option<R> {
+        match self.profiler.try_borrow_mut() {
+            Ok(mut profiler) => Some(f(&mut profiler)),
+            Err(_) => {
+                error::misuse(Error::ProfilerBorrowed);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Selection of the clock used for measuring scopes.
+//!
+//! By default, `instant::Instant` is used, which is `std::time::Instant` on
+//! native targets and `performance.now()` on WASM targets. With the `quanta`
+//! feature, `quanta::Instant` is used instead, which reads TSC-calibrated
+//! timestamps and is significantly cheaper to query. With the `coarse-clock`
+//! feature, a coarse monotonic clock is read, see the `coarse_clock` module,
+//! and with the `cycles` feature, the CPU's cycle counter is read directly,
+//! see the `cycles` module. If more than one of these features is enabled,
+//! `cycles` takes precedence over `coarse-clock`, which takes precedence over
+//! `quanta`.
+//!
+//! With the `cpu-time` feature, the CPU time of the current thread can be
+//! measured in addition on Unix platforms.
+
+use std::time::Duration;
+
+#[cfg(not(any(feature = "quanta", feature = "coarse-clock", feature = "cycles")))]
+pub use instant::Instant;
+
+#[cfg(all(
+    feature = "quanta",
+    not(any(feature = "coarse-clock", feature = "cycles"))
+))]
+pub use quanta::Instant;
+
+#[cfg(all(feature = "coarse-clock", not(feature = "cycles")))]
+pub use crate::coarse_clock::Instant;
+
+#[cfg(feature = "cycles")]
+pub use crate::cycles::Instant;
+
+/// Does the clock only advance every few milliseconds? The cost of a few
+/// clock reads can not be measured with such a clock.
+pub const IS_COARSE: bool = cfg!(all(feature = "coarse-clock", not(feature = "cycles")));
+
+/// Returns the CPU time that the current thread has consumed so far, in
+/// nanoseconds, or `None` if this is not supported.
+#[cfg(all(feature = "cpu-time", unix))]
+pub fn thread_cpu_time_ns() -> Option<u64> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // Safety: `time` is a valid pointer to a `timespec`.
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+
+    if result == 0 {
+        Some(
+            (time.tv_sec as u64)
+                .saturating_mul(1_000_000_000)
+                .saturating_add(time.tv_nsec as u64),
+        )
+    } else {
+        None
+    }
+}
+
+/// Returns the CPU time that the current thread has consumed so far, in
+/// nanoseconds, or `None` if this is not supported.
+#[cfg(not(all(feature = "cpu-time", unix)))]
+pub fn thread_cpu_time_ns() -> Option<u64> {
+    None
+}
+
+/// Returns the number of nanoseconds in `duration`, saturating at `u64::MAX`.
+pub fn as_nanos(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(duration.subsec_nanos()))
+}
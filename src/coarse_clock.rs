@@ -0,0 +1,112 @@
+//! Timestamps read from a coarse monotonic clock.
+//!
+//! On Linux and Android, `CLOCK_MONOTONIC_COARSE` is read, which returns the
+//! time of the last timer tick without querying the hardware, and on Windows,
+//! `GetTickCount64` is used. Both are cheaper to read than the clocks behind
+//! `std::time::Instant`, but only advance every few milliseconds, so this is
+//! only suitable for scopes that take much longer than that. On other Unix
+//! platforms, `CLOCK_MONOTONIC` is read, and on the remaining targets, the
+//! system clock is used.
+
+use std::ops::Add;
+use std::time::Duration;
+
+/// A timestamp of the coarse clock in nanoseconds, with the same interface as
+/// the parts of `std::time::Instant` that the profiler uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(now_ns())
+    }
+
+    /// Returns the time since `earlier`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(crate::clock::as_nanos(duration)))
+    }
+}
+
+#[cfg(unix)]
+fn now_ns() -> u64 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC_COARSE;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // Safety: `time` is a valid pointer to a `timespec`. The monotonic clocks
+    // can not fail, so the result is ignored.
+    unsafe {
+        libc::clock_gettime(CLOCK, &mut time);
+    }
+
+    (time.tv_sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(time.tv_nsec as u64)
+}
+
+#[cfg(windows)]
+fn now_ns() -> u64 {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetTickCount64() -> u64;
+    }
+
+    // Safety: `GetTickCount64` has no preconditions.
+    unsafe { GetTickCount64() }.saturating_mul(1_000_000)
+}
+
+/// Without a coarse clock, we count nanoseconds of the system clock since the
+/// clock was first read.
+#[cfg(not(any(unix, windows)))]
+fn now_ns() -> u64 {
+    use std::sync::OnceLock;
+
+    static EPOCH: OnceLock<instant::Instant> = OnceLock::new();
+
+    crate::clock::as_nanos(EPOCH.get_or_init(instant::Instant::now).elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Instant;
+
+    #[test]
+    fn test_coarse_clock() {
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(50));
+        let elapsed = start.elapsed();
+
+        // The clock only advances with timer ticks, which may be up to 10ms
+        // apart.
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert_eq!(start.duration_since(Instant::now()), Duration::new(0, 0));
+        assert_eq!(
+            (start + Duration::from_millis(1)).duration_since(start),
+            Duration::from_millis(1)
+        );
+    }
+}
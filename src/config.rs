@@ -0,0 +1,75 @@
+//! Configuration of the thread-local profiler.
+
+use crate::report::{ReportOptions, Sort, Unit};
+use crate::Mode;
+
+/// Options of the thread-local profiler, which are applied with
+/// [`configure`](fn.configure.html).
+///
+/// This bundles the options of the report, which is written by
+/// [`write`](fn.write.html) and the reporting helpers, with the warmup and
+/// the mode of recording. The other options of recording are set with their
+/// individual functions.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub(crate) report_options: ReportOptions,
+    pub(crate) warmup: usize,
+    pub(crate) mode: Option<Mode>,
+}
+
+impl Config {
+    /// Create the default configuration.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Options of the report. See [`ReportOptions`](struct.ReportOptions.html)
+    /// for the defaults.
+    pub fn report_options(mut self, report_options: ReportOptions) -> Config {
+        self.report_options = report_options;
+        self
+    }
+
+    /// Order of sibling scopes in the report. See
+    /// [`ReportOptions::sort`](struct.ReportOptions.html#method.sort).
+    pub fn sort(mut self, sort: Sort) -> Config {
+        self.report_options = self.report_options.sort(sort);
+        self
+    }
+
+    /// Hide scopes below a percentage in the report. See
+    /// [`ReportOptions::min_percent`](struct.ReportOptions.html#method.min_percent).
+    pub fn min_percent(mut self, min_percent: f64) -> Config {
+        self.report_options = self.report_options.min_percent(min_percent);
+        self
+    }
+
+    /// Unit of durations in the report. See
+    /// [`ReportOptions::time_unit`](struct.ReportOptions.html#method.time_unit).
+    pub fn time_unit(mut self, time_unit: Unit) -> Config {
+        self.report_options = self.report_options.time_unit(time_unit);
+        self
+    }
+
+    /// Designate the root scope called `name` as the frame. See
+    /// [`ReportOptions::frame_scope`](struct.ReportOptions.html#method.frame_scope).
+    pub fn frame_scope<S: Into<String>>(mut self, name: S) -> Config {
+        self.report_options = self.report_options.frame_scope(name);
+        self
+    }
+
+    /// Exclude the first `num_calls` calls of every scope from the
+    /// statistics. See [`set_warmup`](fn.set_warmup.html). Defaults to zero.
+    pub fn warmup(mut self, num_calls: usize) -> Config {
+        self.warmup = num_calls;
+        self
+    }
+
+    /// What to record and print, see [`Mode`](enum.Mode.html). If this is not
+    /// set, `configure` keeps the current mode, which initially is given by
+    /// the `COARSE_PROF` environment variable.
+    pub fn mode(mut self, mode: Mode) -> Config {
+        self.mode = Some(mode);
+        self
+    }
+}
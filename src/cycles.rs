@@ -0,0 +1,171 @@
+//! Timestamps read from the CPU's cycle counter.
+//!
+//! On x86-64, the time stamp counter is read with `RDTSC`, and on AArch64,
+//! the virtual counter is read from `CNTVCT_EL0`. Reading these takes only a
+//! few nanoseconds and does not involve the operating system. Counts are
+//! converted to time with the frequency of the counter, which is read from
+//! `CNTFRQ_EL0` on AArch64 and calibrated against the system clock on x86-64.
+//! On other targets, the system clock is used.
+
+use std::ops::Add;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How long the frequency of the time stamp counter is calibrated for.
+#[cfg(target_arch = "x86_64")]
+const CALIBRATION_TIME: Duration = Duration::from_millis(10);
+
+/// A timestamp in cycles of the CPU's counter, with the same interface as the
+/// parts of `std::time::Instant` that the profiler uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(read_counter())
+    }
+
+    /// Returns the time since `earlier`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        cycles_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        let cycles = duration.as_nanos() * u128::from(cycles_per_second()) / 1_000_000_000;
+        Instant(
+            self.0
+                .saturating_add(cycles.min(u128::from(u64::MAX)) as u64),
+        )
+    }
+}
+
+/// Returns the frequency of the counter that is used for measuring scopes
+/// with the `cycles` feature, in cycles per second.
+///
+/// On x86-64, the frequency is calibrated against the system clock the first
+/// time it is needed, which takes about 10 milliseconds. Calling this at
+/// startup avoids the delay when the first scope is left. Cycle counts are
+/// only converted to time correctly if the counter runs at a constant rate
+/// and is synchronized between cores, which is the case for the invariant
+/// TSC of all recent x86-64 CPUs and for the AArch64 generic timer.
+///
+/// # Example
+///
+/// ```
+/// println!("counting {} cycles per second", coarse_prof::cycles_per_second());
+/// ```
+pub fn cycles_per_second() -> u64 {
+    static FREQUENCY: OnceLock<u64> = OnceLock::new();
+
+    *FREQUENCY.get_or_init(|| measure_frequency().max(1))
+}
+
+fn cycles_to_duration(cycles: u64) -> Duration {
+    let nanos = u128::from(cycles) * 1_000_000_000 / u128::from(cycles_per_second());
+    Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[allow(unused_unsafe)]
+fn read_counter() -> u64 {
+    // Safety: `RDTSC` is available on every x86-64 CPU.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn measure_frequency() -> u64 {
+    let start_time = std::time::Instant::now();
+    let start = read_counter();
+
+    // Spin instead of sleeping, so that the thread is not descheduled
+    // between reading the two clocks.
+    let mut elapsed = start_time.elapsed();
+    while elapsed < CALIBRATION_TIME {
+        elapsed = start_time.elapsed();
+    }
+    let cycles = read_counter().saturating_sub(start);
+
+    (u128::from(cycles) * 1_000_000_000 / elapsed.as_nanos().max(1)) as u64
+}
+
+#[cfg(target_arch = "aarch64")]
+fn read_counter() -> u64 {
+    let cycles: u64;
+    // Safety: `CNTVCT_EL0` is readable from user space on all common
+    // operating systems.
+    unsafe {
+        core::arch::asm!("mrs {}, cntvct_el0", out(reg) cycles, options(nomem, nostack));
+    }
+    cycles
+}
+
+#[cfg(target_arch = "aarch64")]
+fn measure_frequency() -> u64 {
+    let frequency: u64;
+    // Safety: `CNTFRQ_EL0` is readable from user space on all common
+    // operating systems.
+    unsafe {
+        core::arch::asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack));
+    }
+    frequency
+}
+
+/// Without a cycle counter, we count nanoseconds of the system clock since the
+/// counter was first read.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn read_counter() -> u64 {
+    static EPOCH: OnceLock<instant::Instant> = OnceLock::new();
+
+    crate::clock::as_nanos(EPOCH.get_or_init(instant::Instant::now).elapsed())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn measure_frequency() -> u64 {
+    1_000_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Instant;
+
+    #[test]
+    fn test_cycles() {
+        assert!(super::cycles_per_second() > 1_000);
+
+        let start = Instant::now();
+        let std_start = std::time::Instant::now();
+        std::thread::sleep(Duration::from_millis(20));
+        let elapsed = start.elapsed();
+        let std_elapsed = std_start.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(15), "{:?}", elapsed);
+        assert!(
+            elapsed <= std_elapsed + Duration::from_millis(5),
+            "{:?}",
+            elapsed
+        );
+        assert_eq!(start.duration_since(Instant::now()), Duration::new(0, 0));
+
+        let later = start + Duration::from_millis(1);
+        let difference = later.duration_since(start);
+        assert!(
+            difference.as_nanos().abs_diff(1_000_000) <= 1,
+            "{:?}",
+            difference
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Misuse of the profiler that is detected at runtime.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Misuse of the profiler that has been detected at runtime.
+///
+/// How misuse is handled depends on the
+/// [`MisusePolicy`](enum.MisusePolicy.html). Unless the policy is `Panic`,
+/// the profiler carries on, ignoring the offending call, so that a bug in the
+/// instrumentation can not crash a shipped program. Guards are not `Send`,
+/// so dropping a guard on a different thread than the one that created it
+/// is already ruled out at compile time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Error {
+    /// A scope has been left while no scope was active.
+    LeaveOutsideScope,
+
+    /// The profiler of the thread was already in use, e.g. because a scope
+    /// was entered or left by a writer that a report was being written to.
+    /// The scope is not recorded.
+    ProfilerBorrowed,
+
+    /// A section has been entered with [`Guard::lap`](struct.Guard.html#method.lap)
+    /// while a scope that was entered after the guard was still active. The
+    /// section is not recorded.
+    LapOutsideScope,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LeaveOutsideScope => write!(f, "left a scope while not in any scope"),
+            Error::ProfilerBorrowed => write!(
+                f,
+                "the profiler was already in use, e.g. by a writer, so a scope was not recorded"
+            ),
+            Error::LapOutsideScope => write!(
+                f,
+                "entered a section of a scope while another scope was active inside of it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How misuse of the profiler is handled, see
+/// [`set_misuse_policy`](fn.set_misuse_policy.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisusePolicy {
+    /// Panic with the error. This finds bugs in the instrumentation early,
+    /// e.g. in debug builds.
+    Panic,
+
+    /// Log every kind of error with `log::error!` the first time it occurs,
+    /// and ignore it afterwards.
+    LogOnce,
+
+    /// Silently ignore misuse.
+    Ignore,
+}
+
+/// The current `MisusePolicy`, as its index in `POLICIES`.
+static POLICY: AtomicU8 = AtomicU8::new(1);
+
+const POLICIES: [MisusePolicy; 3] = [
+    MisusePolicy::Panic,
+    MisusePolicy::LogOnce,
+    MisusePolicy::Ignore,
+];
+
+/// The kinds of errors that have been logged with `MisusePolicy::LogOnce`.
+static LOGGED: Mutex<Vec<Error>> = Mutex::new(Vec::new());
+
+/// Set how misuse of the profiler is handled in all threads.
+pub fn set_policy(policy: MisusePolicy) {
+    let index = POLICIES.iter().position(|&p| p == policy).unwrap_or(1);
+    POLICY.store(index as u8, Ordering::Relaxed);
+}
+
+/// Report misuse of the profiler, according to the current policy.
+pub fn misuse(error: Error) {
+    handle(POLICIES[POLICY.load(Ordering::Relaxed) as usize], error);
+}
+
+fn handle(policy: MisusePolicy, error: Error) {
+    match policy {
+        MisusePolicy::Panic => panic!("coarse_prof: {}", error),
+        MisusePolicy::LogOnce => {
+            let mut logged = LOGGED.lock().unwrap_or_else(PoisonError::into_inner);
+            if !logged.contains(&error) {
+                logged.push(error);
+                log::error!("coarse_prof: {}", error);
+            }
+        }
+        MisusePolicy::Ignore => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle, Error, MisusePolicy, LOGGED};
+
+    #[test]
+    fn test_log_once() {
+        handle(MisusePolicy::Ignore, Error::LeaveOutsideScope);
+        handle(MisusePolicy::LogOnce, Error::LeaveOutsideScope);
+        handle(MisusePolicy::LogOnce, Error::LeaveOutsideScope);
+
+        let logged = LOGGED.lock().unwrap();
+        assert_eq!(
+            logged
+                .iter()
+                .filter(|&&error| error == Error::LeaveOutsideScope)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "coarse_prof: left a scope while not in any scope")]
+    fn test_panic() {
+        handle(MisusePolicy::Panic, Error::LeaveOutsideScope);
+    }
+}
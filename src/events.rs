@@ -0,0 +1,148 @@
+//! Events that are reported to user callbacks.
+//!
+//! Events are queued while the profiler is borrowed, and dispatched by the
+//! guard after releasing the borrow, so that callbacks can use the profiler
+//! themselves.
+
+use std::time::Duration;
+
+use crate::ScopeStats;
+
+/// A scope whose recent mean duration deviates from its long-term mean.
+///
+/// Passed to the callback of [`on_anomaly`](fn.on_anomaly.html).
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    /// Path of the scope in the tree, with names separated by `/`.
+    pub path: String,
+
+    /// Mean duration of the calls in the most recent window.
+    pub recent_mean: Duration,
+
+    /// Mean duration of all calls before the most recent window.
+    pub long_term_mean: Duration,
+
+    /// Number of calls of the scope so far, including the recent window.
+    pub num_calls: usize,
+}
+
+/// A single call of a scope that took longer than a threshold.
+///
+/// Passed to the callback of [`on_hitch`](fn.on_hitch.html).
+#[derive(Debug, Clone)]
+pub struct Hitch {
+    /// Path of the scope in the tree, with names separated by `/`.
+    pub path: String,
+
+    /// Duration of the call.
+    pub duration: Duration,
+
+    /// Index of the frame in which the call happened, i.e. the number of
+    /// times that a root scope has been left before.
+    pub frame: u64,
+}
+
+type Callback<T> = Box<dyn FnMut(&T)>;
+
+pub(crate) enum Event {
+    Anomaly(Anomaly),
+    Hitch(Hitch),
+
+    /// Fresh statistics for the subscription with the given id.
+    Stats(u64, Box<ScopeStats>),
+}
+
+#[derive(Default)]
+pub(crate) struct Callbacks {
+    pub on_anomaly: Option<Callback<Anomaly>>,
+    pub on_hitch: Option<Callback<Hitch>>,
+    pub on_stats: Vec<(u64, Callback<ScopeStats>)>,
+
+    // Callbacks that have been removed while the others were taken for
+    // dispatching, so that `restore` does not put them back.
+    anomaly_removed: bool,
+    hitch_removed: bool,
+    stats_cleared: bool,
+}
+
+impl Callbacks {
+    pub fn remove_anomaly(&mut self) {
+        self.on_anomaly = None;
+        self.anomaly_removed = true;
+    }
+
+    pub fn remove_hitch(&mut self) {
+        self.on_hitch = None;
+        self.hitch_removed = true;
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.on_stats.clear();
+        self.stats_cleared = true;
+    }
+
+    /// Put back callbacks that have been taken for dispatching, unless they
+    /// have been replaced or removed in the meantime.
+    pub fn restore(&mut self, callbacks: Callbacks) {
+        if self.on_anomaly.is_none() && !self.anomaly_removed {
+            self.on_anomaly = callbacks.on_anomaly;
+        }
+        if self.on_hitch.is_none() && !self.hitch_removed {
+            self.on_hitch = callbacks.on_hitch;
+        }
+        if !self.stats_cleared {
+            let added = std::mem::replace(&mut self.on_stats, callbacks.on_stats);
+            self.on_stats.extend(added);
+        }
+
+        self.anomaly_removed = false;
+        self.hitch_removed = false;
+        self.stats_cleared = false;
+    }
+
+    pub fn dispatch(&mut self, events: Vec<Event>) {
+        for event in events {
+            match event {
+                Event::Anomaly(anomaly) => {
+                    if let Some(callback) = self.on_anomaly.as_mut() {
+                        callback(&anomaly);
+                    }
+                }
+                Event::Hitch(hitch) => {
+                    if let Some(callback) = self.on_hitch.as_mut() {
+                        callback(&hitch);
+                    }
+                }
+                Event::Stats(id, stats) => {
+                    if let Some((_, callback)) =
+                        self.on_stats.iter_mut().find(|(other, _)| *other == id)
+                    {
+                        callback(&stats);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Queued events together with the callbacks that handle them.
+#[derive(Default)]
+pub(crate) struct Events {
+    pub pending: Vec<Event>,
+    pub callbacks: Callbacks,
+}
+
+impl Events {
+    /// Take the pending events and the callbacks for dispatching them, if
+    /// there are any events.
+    pub fn take(&mut self) -> Option<(Vec<Event>, Callbacks)> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some((
+                std::mem::take(&mut self.pending),
+                std::mem::take(&mut self.callbacks),
+            ))
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Periodic reports written to rotating files.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{ProfileSnapshot, ReportOptions, Reporter};
+
+/// Prefix of the names of report files, so that other files in the directory
+/// are never deleted.
+const PREFIX: &str = "coarse_prof-";
+
+/// Extension of the names of report files.
+const EXTENSION: &str = ".txt";
+
+/// Writes every report to a new file in a directory, keeping only the most
+/// recent files.
+///
+/// Files are named after the time at which they were written, in
+/// milliseconds since the Unix epoch, e.g. `coarse_prof-1700000000000.txt`.
+/// Whenever a file is written, the oldest report files in the directory are
+/// deleted, so that at most `max_files` remain, including those left behind
+/// by previous runs. This allows overnight test runs to leave behind an
+/// inspectable history without filling the disk. Use with
+/// [`report_to_files_every`](fn.report_to_files_every.html) or
+/// [`add_reporter`](fn.add_reporter.html).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use coarse_prof::FileReporter;
+///
+/// let reporter = FileReporter::new("profiles", 100).unwrap();
+/// coarse_prof::report_to_files_every(Duration::from_secs(60), reporter);
+/// ```
+pub struct FileReporter {
+    dir: PathBuf,
+    max_files: usize,
+    options: ReportOptions,
+
+    /// Timestamp of the last report, and how many reports have been written
+    /// in the same millisecond before it.
+    last: (u128, u64),
+}
+
+impl FileReporter {
+    /// Create a reporter that writes to `dir`, creating the directory if
+    /// necessary.
+    pub fn new<P: Into<PathBuf>>(dir: P, max_files: usize) -> io::Result<FileReporter> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileReporter {
+            dir,
+            max_files,
+            options: ReportOptions::default(),
+            last: (0, 0),
+        })
+    }
+
+    /// Format the reports with `options` instead of the default options.
+    pub fn options(mut self, options: ReportOptions) -> FileReporter {
+        self.options = options;
+        self
+    }
+
+    /// Create the file for the next report and delete the oldest report
+    /// files beyond `max_files`.
+    fn next_file(&mut self) -> io::Result<File> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        // Reports can be written more than once per millisecond, e.g. in
+        // tests, so we count them and add a suffix.
+        let mut i = if self.last.0 == millis {
+            self.last.1 + 1
+        } else {
+            0
+        };
+        let path = loop {
+            let path = if i == 0 {
+                self.dir
+                    .join(format!("{}{:013}{}", PREFIX, millis, EXTENSION))
+            } else {
+                self.dir
+                    .join(format!("{}{:013}-{}{}", PREFIX, millis, i, EXTENSION))
+            };
+            if !path.exists() {
+                break path;
+            }
+            i += 1;
+        };
+        self.last = (millis, i);
+
+        let file = File::create(&path)?;
+        self.prune()?;
+
+        Ok(file)
+    }
+
+    /// Delete the oldest report files, so that at most `max_files` remain.
+    fn prune(&self) -> io::Result<()> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            if let Some(name) = name.to_str() {
+                if name.starts_with(PREFIX) && name.ends_with(EXTENSION) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        // Timestamps are padded, so sorting by name sorts by time. Suffixes
+        // for reports in the same millisecond sort after the first one.
+        names.sort_by(|a, b| {
+            let key = |name: &str| {
+                let stem = &name[PREFIX.len()..name.len() - EXTENSION.len()];
+                let mut parts = stem.splitn(2, '-');
+                let millis = parts.next().unwrap_or_default().to_string();
+                let i = parts
+                    .next()
+                    .and_then(|i| i.parse::<u64>().ok())
+                    .unwrap_or(0);
+                (millis, i)
+            };
+            key(a).cmp(&key(b))
+        });
+
+        let num_excess = names.len().saturating_sub(self.max_files);
+        for name in &names[..num_excess] {
+            fs::remove_file(self.dir.join(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Reporter for FileReporter {
+    fn report(&mut self, snapshot: &ProfileSnapshot) {
+        let result = self
+            .next_file()
+            .and_then(|mut file| write!(file, "{}", snapshot.report(&self.options)));
+        if let Err(err) = result {
+            log::warn!("coarse_prof: failed to write report file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use super::FileReporter;
+
+    #[test]
+    fn test_rotation() {
+        let dir =
+            std::env::temp_dir().join(format!("coarse_prof_test_rotation_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut reporter = FileReporter::new(&dir, 3).unwrap();
+        fs::write(dir.join("notes.txt"), "keep me").unwrap();
+
+        for i in 0..5 {
+            write!(reporter.next_file().unwrap(), "report {}", i).unwrap();
+        }
+
+        let mut contents: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(
+            contents,
+            vec!["keep me", "report 2", "report 3", "report 4"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Profiling of futures.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::clock::{self, Instant};
+
+/// An extension trait for profiling futures, analogous to `instrument` of the
+/// `tracing` crate.
+///
+/// Every poll of a profiled future enters its scope, so that the scope's
+/// duration is the time spent polling the future, and scopes entered while
+/// polling are nested below it. Time spent waiting for the future to be woken
+/// up is not included.
+///
+/// In order to separate compute from scheduling delay, the following
+/// attributes are attached to the scope, see [`attr`](fn.attr.html):
+/// - `first_poll_ms`: Time from calling `profiled` until the first poll, in
+///   milliseconds.
+/// - `polls`: Number of times the future has been polled until it completed.
+/// - `wakes`: Number of times the future has been woken up until it
+///   completed.
+///
+/// Note that the scope is entered in the thread-local profiler of the thread
+/// that polls the future, so futures that move between the threads of a
+/// multi-threaded executor are recorded by multiple profilers.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::FutureProfileExt;
+///
+/// async fn load_level() {
+///     // ... load the level ...
+/// }
+///
+/// let future = load_level().profiled("load_level");
+/// # drop(future);
+/// ```
+pub trait FutureProfileExt: Future + Sized {
+    /// Profile every poll of this future in a scope called `name`.
+    fn profiled(self, name: &'static str) -> Profiled<Self> {
+        Profiled {
+            inner: self,
+            name,
+            created: Instant::now(),
+            num_polls: 0,
+            num_wakes: Arc::new(AtomicUsize::new(0)),
+            waker: None,
+        }
+    }
+}
+
+impl<F: Future> FutureProfileExt for F {}
+
+/// A future that is profiled in a scope.
+///
+/// Created by [`FutureProfileExt::profiled`](trait.FutureProfileExt.html#method.profiled).
+pub struct Profiled<F> {
+    inner: F,
+    name: &'static str,
+    created: Instant,
+    num_polls: usize,
+    num_wakes: Arc<AtomicUsize>,
+
+    /// The waker of the executor and the waker that counts its wakes before
+    /// forwarding them.
+    waker: Option<(Waker, Waker)>,
+}
+
+/// Counts how often a future is woken up.
+struct CountingWaker {
+    inner: Waker,
+    num_wakes: Arc<AtomicUsize>,
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.num_wakes.fetch_add(1, Ordering::Relaxed);
+        self.inner.wake_by_ref();
+    }
+}
+
+impl<F: Future> Future for Profiled<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // Safety: `inner` is structurally pinned. It is never moved out of
+        // `self`, and `Profiled` does not implement `Drop` or `Unpin`
+        // manually.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+
+        let _guard = crate::enter(this.name);
+        if this.num_polls == 0 {
+            let first_poll_ns = clock::as_nanos(this.created.elapsed());
+            crate::attr("first_poll_ms", first_poll_ns as f64 * 1e-6);
+        }
+        this.num_polls += 1;
+
+        // Only wrap the waker again if the executor has given us a different
+        // one.
+        let waker = match this.waker.as_ref() {
+            Some((inner, waker)) if inner.will_wake(cx.waker()) => waker,
+            _ => {
+                let waker = Waker::from(Arc::new(CountingWaker {
+                    inner: cx.waker().clone(),
+                    num_wakes: this.num_wakes.clone(),
+                }));
+                &this.waker.insert((cx.waker().clone(), waker)).1
+            }
+        };
+
+        let poll = inner.poll(&mut Context::from_waker(waker));
+        if poll.is_ready() {
+            crate::attr("polls", this.num_polls as f64);
+            crate::attr("wakes", this.num_wakes.load(Ordering::Relaxed) as f64);
+        }
+
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use super::FutureProfileExt;
+    use crate::profile;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// A future that is pending until it has been polled `num_polls` times.
+    struct Countdown {
+        num_polls: usize,
+    }
+
+    impl Future for Countdown {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            profile!("step");
+
+            self.num_polls -= 1;
+            if self.num_polls == 0 {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_profiled() {
+        crate::reset();
+
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = Box::pin(Countdown { num_polls: 3 }.profiled("countdown"));
+        while future.as_mut().poll(&mut cx).is_pending() {}
+
+        let snapshot = crate::snapshot();
+        let countdown = &snapshot.roots[0];
+        assert_eq!(countdown.name, "countdown");
+        assert_eq!(countdown.num_calls, 3);
+        assert_eq!(countdown.children[0].name, "step");
+        assert_eq!(countdown.children[0].num_calls, 3);
+
+        let keys: Vec<_> = countdown
+            .attrs
+            .iter()
+            .map(|attr| attr.key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["first_poll_ms", "polls", "wakes"]);
+        assert_eq!(countdown.attrs[0].count, 1);
+        assert_eq!(countdown.attrs[1].sum, 3.0);
+        assert_eq!(countdown.attrs[2].sum, 2.0);
+    }
+}
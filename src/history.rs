@@ -0,0 +1,329 @@
+//! Time series of the durations of scopes.
+
+use std::io;
+use std::time::Duration;
+
+use crate::json;
+
+/// Totals of every scope per interval, recorded with
+/// [`record_history`](fn.record_history.html).
+#[derive(Debug, Clone)]
+pub struct History {
+    /// The requested length of the intervals.
+    pub interval: Duration,
+
+    /// The recorded intervals, starting with the oldest one.
+    pub samples: Vec<HistorySample>,
+}
+
+/// The totals of every scope in one interval of a [`History`](struct.History.html).
+#[derive(Debug, Clone)]
+pub struct HistorySample {
+    /// Start of the interval, relative to when recording began.
+    pub start: Duration,
+
+    /// Length of the interval. This can be longer than the requested
+    /// interval, since intervals only end between frames.
+    pub duration: Duration,
+
+    /// Scopes that have been left in the interval.
+    pub scopes: Vec<HistoryScope>,
+}
+
+/// The totals of one scope in a [`HistorySample`](struct.HistorySample.html).
+#[derive(Debug, Clone)]
+pub struct HistoryScope {
+    /// Path of the scope in the tree, with names separated by `/`, e.g.
+    /// `frame/physics`.
+    pub path: String,
+
+    /// Id of the scope, see
+    /// [`ScopeStats::id`](struct.ScopeStats.html#structfield.id).
+    pub id: u64,
+
+    /// Id of the parent of the scope, or `None` for root scopes.
+    pub parent_id: Option<u64>,
+
+    /// How often has the scope been left in the interval?
+    pub num_calls: usize,
+
+    /// How much time has been spent in the scope in the interval?
+    pub duration_sum: Duration,
+}
+
+impl History {
+    /// Write the history as JSON, e.g.:
+    /// ```text
+    /// {"interval_secs":1,"samples":[{"start_secs":0,"duration_secs":1.002,
+    ///  "scopes":{"frame":{"id":1,"num_calls":60,"duration_sum_secs":0.96},
+    ///  "frame/physics":{"id":2,"parent_id":1,...},...}},...]}
+    /// ```
+    pub fn write_json<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"interval_secs\":")?;
+        json::write_f64(out, self.interval.as_secs_f64())?;
+        write!(out, ",\"samples\":[")?;
+        for (i, sample) in self.samples.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{{\"start_secs\":")?;
+            json::write_f64(out, sample.start.as_secs_f64())?;
+            write!(out, ",\"duration_secs\":")?;
+            json::write_f64(out, sample.duration.as_secs_f64())?;
+            write!(out, ",\"scopes\":{{")?;
+            for (j, scope) in sample.scopes.iter().enumerate() {
+                if j > 0 {
+                    write!(out, ",")?;
+                }
+                json::write_str(out, &scope.path)?;
+                write!(out, ":{{\"id\":{}", scope.id)?;
+                if let Some(parent_id) = scope.parent_id {
+                    write!(out, ",\"parent_id\":{}", parent_id)?;
+                }
+                write!(
+                    out,
+                    ",\"num_calls\":{},\"duration_sum_secs\":",
+                    scope.num_calls
+                )?;
+                json::write_f64(out, scope.duration_sum.as_secs_f64())?;
+                write!(out, "}}")?;
+            }
+            write!(out, "}}}}")?;
+        }
+        write!(out, "]}}")
+    }
+
+    /// Write the history as CSV, with one row per scope and interval. The
+    /// parent id is empty for root scopes:
+    /// ```text
+    /// start_secs,duration_secs,path,id,parent_id,num_calls,duration_sum_secs
+    /// 0,1.002,frame,1,,60,0.96
+    /// 0,1.002,frame/physics,2,1,60,0.31
+    /// ```
+    pub fn write_csv<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "start_secs,duration_secs,path,id,parent_id,num_calls,duration_sum_secs"
+        )?;
+        for sample in &self.samples {
+            for scope in &sample.scopes {
+                write!(
+                    out,
+                    "{},{},",
+                    sample.start.as_secs_f64(),
+                    sample.duration.as_secs_f64(),
+                )?;
+                write_csv_field(out, &scope.path)?;
+                write!(out, ",{},", scope.id)?;
+                if let Some(parent_id) = scope.parent_id {
+                    write!(out, "{}", parent_id)?;
+                }
+                writeln!(
+                    out,
+                    ",{},{}",
+                    scope.num_calls,
+                    scope.duration_sum.as_secs_f64(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the time per frame of `frame_scope` as a PNG chart with
+    /// [plotters](https://github.com/plotters-rs/plotters).
+    ///
+    /// Every interval is plotted at its start. The line shows the mean
+    /// duration of the frame scope per call in the interval, and the stacked
+    /// areas below it show how much of that time each child of the frame
+    /// scope took. Intervals in which the frame scope has not been left are
+    /// plotted as zero.
+    #[cfg(feature = "plotters")]
+    pub fn write_frame_chart_png<P: AsRef<std::path::Path>>(
+        &self,
+        frame_scope: &str,
+        path: P,
+    ) -> io::Result<()> {
+        use plotters::prelude::*;
+
+        fn to_io<E: std::error::Error + Send + Sync>(error: DrawingAreaErrorKind<E>) -> io::Error {
+            io::Error::other(error.to_string())
+        }
+
+        let prefix = format!("{}/", frame_scope);
+        let mut children: Vec<&str> = Vec::new();
+        for scope in self.samples.iter().flat_map(|sample| &sample.scopes) {
+            if let Some(name) = scope.path.strip_prefix(&prefix) {
+                if !name.contains('/') && !children.contains(&name) {
+                    children.push(name);
+                }
+            }
+        }
+
+        // Milliseconds per frame of the frame scope, followed by the
+        // cumulative milliseconds per frame of its children, per interval.
+        let rows: Vec<(f64, Vec<f64>)> = self
+            .samples
+            .iter()
+            .map(|sample| {
+                let millis_per_frame = |path: &str| {
+                    let frames = sample
+                        .scopes
+                        .iter()
+                        .find(|scope| scope.path == frame_scope)
+                        .map_or(0, |scope| scope.num_calls);
+                    sample
+                        .scopes
+                        .iter()
+                        .find(|scope| scope.path == path)
+                        .filter(|_| frames > 0)
+                        .map_or(0.0, |scope| {
+                            scope.duration_sum.as_secs_f64() * 1000.0 / frames as f64
+                        })
+                };
+                let mut values = vec![millis_per_frame(frame_scope)];
+                let mut cumulative = 0.0;
+                for child in &children {
+                    cumulative += millis_per_frame(&format!("{}{}", prefix, child));
+                    values.push(cumulative);
+                }
+                (sample.start.as_secs_f64(), values)
+            })
+            .collect();
+
+        let end = self
+            .samples
+            .last()
+            .map_or(0.0, |sample| (sample.start + sample.duration).as_secs_f64());
+        let max_millis = rows
+            .iter()
+            .flat_map(|(_, values)| values.iter().copied())
+            .fold(0.0, f64::max);
+
+        let root = BitMapBackend::new(path.as_ref(), (1280, 720)).into_drawing_area();
+        root.fill(&WHITE).map_err(to_io)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(format!("{} over time", frame_scope), ("sans-serif", 24))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0.0..end.max(1e-3), 0.0..(max_millis * 1.1).max(1e-3))
+            .map_err(to_io)?;
+        chart
+            .configure_mesh()
+            .x_desc("time (s)")
+            .y_desc("ms per frame")
+            .draw()
+            .map_err(to_io)?;
+
+        // Draw the largest stack first, so that the smaller ones cover it.
+        for (i, child) in children.iter().enumerate().rev() {
+            let color = Palette99::pick(i);
+            chart
+                .draw_series(AreaSeries::new(
+                    rows.iter().map(|(start, values)| (*start, values[i + 1])),
+                    0.0,
+                    color.filled(),
+                ))
+                .map_err(to_io)?
+                .label(*child)
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled())
+                });
+        }
+        chart
+            .draw_series(LineSeries::new(
+                rows.iter().map(|(start, values)| (*start, values[0])),
+                BLACK.stroke_width(2),
+            ))
+            .map_err(to_io)?
+            .label(frame_scope)
+            .legend(|(x, y)| PathElement::new([(x, y), (x + 10, y)], BLACK.stroke_width(2)));
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .map_err(to_io)?;
+
+        root.present().map_err(to_io)
+    }
+}
+
+fn write_csv_field<W: io::Write>(out: &mut W, value: &str) -> io::Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(out, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        write!(out, "{}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{History, HistorySample, HistoryScope};
+
+    fn history() -> History {
+        History {
+            interval: Duration::from_secs(1),
+            samples: vec![HistorySample {
+                start: Duration::from_secs(0),
+                duration: Duration::from_millis(1500),
+                scopes: vec![
+                    HistoryScope {
+                        path: "frame".to_string(),
+                        id: 1,
+                        parent_id: None,
+                        num_calls: 2,
+                        duration_sum: Duration::from_millis(500),
+                    },
+                    HistoryScope {
+                        path: "frame/a,b".to_string(),
+                        id: 2,
+                        parent_id: Some(1),
+                        num_calls: 1,
+                        duration_sum: Duration::from_millis(250),
+                    },
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_write_json() {
+        let mut out = Vec::new();
+        history().write_json(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"interval_secs\":1,\"samples\":[{\"start_secs\":0,\"duration_secs\":1.5,\
+             \"scopes\":{\"frame\":{\"id\":1,\"num_calls\":2,\"duration_sum_secs\":0.5},\
+             \"frame/a,b\":{\"id\":2,\"parent_id\":1,\"num_calls\":1,\"duration_sum_secs\":0.25}}}]}"
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut out = Vec::new();
+        history().write_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "start_secs,duration_secs,path,id,parent_id,num_calls,duration_sum_secs\n\
+             0,1.5,frame,1,,2,0.5\n\
+             0,1.5,\"frame/a,b\",2,1,1,0.25\n"
+        );
+    }
+
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_write_frame_chart_png() {
+        let path = std::env::temp_dir().join(format!(
+            "coarse_prof_test_frame_chart_{}.png",
+            std::process::id()
+        ));
+        history().write_frame_chart_png("frame", &path).unwrap();
+
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&png[..4], b"\x89PNG");
+    }
+}
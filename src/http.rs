@@ -0,0 +1,266 @@
+//! A tiny HTTP server for inspecting the profiler of a running program.
+//!
+//! Since the profiler is thread-local, the server does not spawn a thread of
+//! its own. Instead, [`Server::poll`](struct.Server.html#method.poll) needs to
+//! be called regularly (e.g. once per frame) from the thread that is being
+//! profiled. It answers all requests that have fully arrived, without
+//! blocking on clients that are still sending theirs.
+//!
+//! The following paths are served, ignoring any query string:
+//! - `/profile`: The text report, as printed by [`write`](../fn.write.html).
+//! - `/profile.json`: The scope tree as JSON.
+//!
+//! # Example
+//!
+//! ```no_run
+//! let server = coarse_prof::http::Server::bind("127.0.0.1:8123").unwrap();
+//!
+//! loop {
+//!     coarse_prof::profile!("frame");
+//!
+//!     // ... run the frame ...
+//!
+//!     server.poll();
+//! }
+//! ```
+//!
+//! The report can then be inspected with `curl http://127.0.0.1:8123/profile`.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::PROFILER;
+
+/// How long we wait for a client to send its request before giving up.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long we wait for a client to accept the response before giving up.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Maximal size of a request that we are willing to read.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// A non-blocking HTTP server serving the thread-local profiler.
+pub struct Server {
+    listener: TcpListener,
+
+    /// Clients whose request has not fully arrived yet.
+    pending: RefCell<Vec<Client>>,
+}
+
+/// A connection whose request is being read.
+struct Client {
+    stream: TcpStream,
+    request: Vec<u8>,
+    accepted: Instant,
+}
+
+impl Server {
+    /// Start listening on the given address.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Server {
+            listener,
+            pending: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Returns the address that the server is listening on.
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answer all requests that have fully arrived.
+    ///
+    /// This must be called from the thread whose profiler should be served.
+    /// Clients that are still sending their request are kept until the next
+    /// call, and dropped if they take longer than a few seconds. Errors that
+    /// occur while answering individual requests are logged and otherwise
+    /// ignored.
+    pub fn poll(&self) {
+        let mut pending = self.pending.borrow_mut();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => pending.push(Client {
+                        stream,
+                        request: Vec::new(),
+                        accepted: Instant::now(),
+                    }),
+                    Err(err) => {
+                        log::warn!("coarse_prof: failed to accept HTTP connection: {}", err)
+                    }
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::warn!("coarse_prof: failed to accept HTTP connection: {}", err);
+                    break;
+                }
+            }
+        }
+
+        pending.retain_mut(|client| match read_request(client) {
+            Ok(true) => {
+                if let Err(err) = handle(&mut client.stream, &client.request) {
+                    log::warn!("coarse_prof: failed to answer HTTP request: {}", err);
+                }
+                false
+            }
+            Ok(false) => client.accepted.elapsed() < REQUEST_TIMEOUT,
+            Err(err) => {
+                log::warn!("coarse_prof: failed to read HTTP request: {}", err);
+                false
+            }
+        });
+    }
+}
+
+fn handle(stream: &mut TcpStream, request: &[u8]) -> io::Result<()> {
+    let request = String::from_utf8_lossy(request);
+    let target = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("");
+    let path = target.split('?').next().unwrap_or("");
+
+    let (status, content_type, body) = match path {
+        "/profile" => {
+            let mut body = Vec::new();
+            PROFILER.with(|p| p.borrow().write(&mut body))?;
+            ("200 OK", "text/plain; charset=utf-8", body)
+        }
+        "/profile.json" => {
+            let mut body = Vec::new();
+            PROFILER.with(|p| p.borrow().snapshot(false).write_json(&mut body))?;
+            ("200 OK", "application/json", body)
+        }
+        _ => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            b"Not Found\n".to_vec(),
+        ),
+    };
+
+    // The response is written in one go, so wait for the client to accept
+    // it, but not for long.
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len(),
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// Read what has arrived of the request of `client`. Returns whether the
+/// request is complete.
+fn read_request(client: &mut Client) -> io::Result<bool> {
+    let mut buf = [0; 1024];
+
+    while !client.request.ends_with(b"\r\n\r\n") && client.request.len() < MAX_REQUEST_LEN {
+        match client.stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => client.request.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+
+    use super::Server;
+    use crate::profile;
+
+    /// Poll `server` until it has answered the request that has been sent
+    /// on `stream`, and return the response.
+    fn response(server: &Server, stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        let mut response = Vec::new();
+        loop {
+            server.poll();
+            match stream.read_to_end(&mut response) {
+                Ok(_) => return String::from_utf8(response).unwrap(),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn get(server: &Server, path: &str) -> String {
+        let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+
+        response(server, &mut stream)
+    }
+
+    #[test]
+    fn test_serve_profile() {
+        crate::reset();
+
+        {
+            profile!("a");
+            profile!("b");
+        }
+
+        let server = Server::bind("127.0.0.1:0").unwrap();
+
+        let text = get(&server, "/profile");
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("\r\n\r\n# elapsed: "));
+        assert!(text.contains("\na: "));
+        assert!(text.contains("\n  b: "));
+
+        let json = get(&server, "/profile.json");
+        assert!(json.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(json.contains("\"scopes\":[{\"name\":\"a\",\"id\":"));
+        assert!(json.contains("\"children\":[{\"name\":\"b\",\"id\":"));
+        assert!(json.contains(",\"num_calls\":1,"));
+
+        let missing = get(&server, "/nope");
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let query = get(&server, "/profile?refresh=1");
+        assert!(query.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_idle_client() {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+
+        // A client that has not sent its request yet does not block others.
+        let mut idle = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let start = Instant::now();
+        server.poll();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(server.pending.borrow().len(), 1);
+
+        let missing = get(&server, "/nope");
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        // It is answered once its request has arrived.
+        write!(idle, "GET /nope HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response(&server, &mut idle).starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
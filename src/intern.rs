@@ -0,0 +1,171 @@
+//! Interning of scope names that are only known at runtime.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// An interned scope name.
+///
+/// Creating a `ScopeName` copies the name once per distinct name, after which
+/// it can be copied, compared and hashed as cheaply as a pointer. Keep
+/// `ScopeName`s around for scopes whose names are only known at runtime and
+/// enter them with [`enter_name`](fn.enter_name.html), instead of building
+/// the name anew for every call.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::ScopeName;
+///
+/// let systems: Vec<ScopeName> = ["physics", "ai"]
+///     .iter()
+///     .map(|system| ScopeName::from(format!("systems/{}", system)))
+///     .collect();
+///
+/// for _ in 0..10 {
+///     for &system in &systems {
+///         let _guard = coarse_prof::enter_name(system);
+///         // ... run the system ...
+///     }
+/// }
+///
+/// assert_eq!(systems[0], ScopeName::new("systems/physics"));
+/// ```
+#[derive(Clone, Copy)]
+pub struct ScopeName(&'static str);
+
+impl ScopeName {
+    /// Intern `name`.
+    pub fn new(name: &str) -> ScopeName {
+        ScopeName(intern(name))
+    }
+
+    /// Returns the name as a string.
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl From<&str> for ScopeName {
+    fn from(name: &str) -> ScopeName {
+        ScopeName::new(name)
+    }
+}
+
+impl From<String> for ScopeName {
+    fn from(name: String) -> ScopeName {
+        ScopeName::new(&name)
+    }
+}
+
+impl PartialEq for ScopeName {
+    fn eq(&self, other: &ScopeName) -> bool {
+        // Equal names are interned to the same string.
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for ScopeName {}
+
+impl Hash for ScopeName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state);
+    }
+}
+
+impl fmt::Debug for ScopeName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+impl fmt::Display for ScopeName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Returns a `'static` copy of `name`.
+///
+/// Every distinct name is leaked once and then reused, so the memory that is
+/// used grows with the number of distinct names, not with the number of
+/// calls.
+pub fn intern(name: &str) -> &'static str {
+    let mut names = names();
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// Returns whether `name` has been interned.
+#[cfg(test)]
+pub fn is_interned(name: &str) -> bool {
+    names().contains(name)
+}
+
+fn names() -> MutexGuard<'static, HashSet<&'static str>> {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the id of the scope called `name` below the scope with the id
+/// `parent`, or of the root scope called `name` if `parent` is `None`.
+///
+/// Ids are assigned on first use and are shared by all threads, so that the
+/// same path always has the same id for the lifetime of the process.
+pub fn scope_id(parent: Option<u64>, name: &'static str) -> u64 {
+    /// Ids by the id of the parent and the name of the scope.
+    type Ids = HashMap<(Option<u64>, &'static str), u64>;
+    static IDS: OnceLock<Mutex<Ids>> = OnceLock::new();
+
+    let mut ids = IDS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let next_id = ids.len() as u64 + 1;
+    *ids.entry((parent, name)).or_insert(next_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{intern, scope_id, ScopeName};
+
+    #[test]
+    fn test_intern() {
+        let a = intern(&format!("enemy_{}", 1));
+        let b = intern(&format!("enemy_{}", 1));
+        assert_eq!(a, "enemy_1");
+        assert!(std::ptr::eq(a, b));
+        assert!(!std::ptr::eq(a, intern("enemy_2")));
+    }
+
+    #[test]
+    fn test_scope_id() {
+        let a = scope_id(None, "test_scope_id");
+        let b = scope_id(Some(a), "test_scope_id");
+        assert_ne!(a, b);
+        assert_eq!(scope_id(None, "test_scope_id"), a);
+        assert_eq!(scope_id(Some(a), intern("test_scope_id")), b);
+    }
+
+    #[test]
+    fn test_scope_name() {
+        let a = ScopeName::from(format!("enemy_{}", 1));
+        assert_eq!(a, ScopeName::from("enemy_1"));
+        assert_ne!(a, ScopeName::from("enemy_2"));
+        assert_eq!(a.as_str(), "enemy_1");
+        assert_eq!(a.to_string(), "enemy_1");
+    }
+}
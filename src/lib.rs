@@ -52,28 +52,245 @@
 //!
 //! Example output:
 //! ```text
+//! # elapsed: 1.04s
 //! frame: 100.00%, 10.40ms/call @ 96.17Hz
 //!   physics: 3.04%, 3.16ms/call @ 9.62Hz
 //!     collisions: 33.85%, 1.07ms/call @ 9.62Hz
 //!   render: 96.84%, 10.07ms/call @ 96.17Hz
 //! ```
+//!
+//! # Features
+//!
+//! - `puffin`: Forward all scopes to [puffin](https://github.com/EmbarkStudios/puffin),
+//!   so that they can be inspected in `puffin_viewer` or `puffin_egui` in
+//!   addition to `coarse-prof`'s averaged report. Scopes are only forwarded
+//!   while `puffin::are_scopes_on()` returns `true`.
+//! - `tracy`: Emit a zone to the [Tracy](https://github.com/wolfpld/tracy)
+//!   profiler via [tracy-client](https://github.com/nagisa/rust_tracy_client)
+//!   for every scope, so that the same instrumentation can be used for
+//!   frame-capture analysis. Zones are only emitted while a
+//!   `tracy_client::Client` is running.
+//! - `quanta`: Measure time with [quanta](https://github.com/metrics-rs/quanta)
+//!   instead of `std::time::Instant`, reducing the overhead of profiling
+//!   scopes that take less than a microsecond.
+//! - `http`: Serve the report over HTTP with a tiny built-in server, see the
+//!   [`http`](http/index.html) module.
+//! - `cpu-time`: Allow measuring the CPU time of the thread in every scope on
+//!   Unix platforms, see [`set_measure_cpu_time`](fn.set_measure_cpu_time.html).
+//! - `perf`: Allow measuring hardware counters, such as retired instructions
+//!   and cache misses, in every scope on Linux, see
+//!   [`set_perf_counters`](fn.set_perf_counters.html).
+//! - `rusage`: Allow counting context switches and page faults in every scope
+//!   on Unix platforms, see [`set_measure_rusage`](fn.set_measure_rusage.html).
+//! - `signal`: Allow writing the report of a running process upon receiving
+//!   `SIGUSR1` on Unix platforms, see
+//!   [`write_on_signal`](fn.write_on_signal.html).
+//! - `alloc`: Attribute heap allocations to scopes with the global allocator
+//!   wrapper [`CoarseProfAlloc`](struct.CoarseProfAlloc.html).
+//! - `inferno`: Render the scope tree as an SVG flamegraph with
+//!   [inferno](https://github.com/jonhoo/inferno), see
+//!   [`write_flamegraph_svg`](fn.write_flamegraph_svg.html).
+//! - `metrics`: Allow publishing the durations of scopes to the
+//!   [metrics](https://github.com/metrics-rs/metrics) facade, see
+//!   [`set_publish_metrics`](fn.set_publish_metrics.html).
+//! - `backtrace`: Capture a backtrace whenever a scope records a new maximum
+//!   duration, see
+//!   [`ScopeStats::max_backtrace`](struct.ScopeStats.html#structfield.max_backtrace).
+//!   Capturing backtraces is slow, so this is meant for debugging.
+//! - `cycles`: Measure time by reading the CPU's cycle counter (`RDTSC` on
+//!   x86-64, `CNTVCT_EL0` on AArch64) instead of the system clock, for very
+//!   short and very hot scopes. Cycles are converted to time with a
+//!   calibrated frequency, see
+//!   [`cycles_per_second`](fn.cycles_per_second.html). Takes precedence over
+//!   `quanta` and `coarse-clock`.
+//! - `coarse-clock`: Measure time with a coarse monotonic clock
+//!   (`CLOCK_MONOTONIC_COARSE` on Linux, `GetTickCount64` on Windows), which
+//!   is the cheapest clock to read, but only advances every few milliseconds.
+//!   This is for programs that only profile multi-millisecond scopes and want
+//!   the lowest possible cost per scope. Takes precedence over `quanta`.
+//!   Since the cost of a scope is far below a tick, the overhead measured by
+//!   [`estimated_overhead`](fn.estimated_overhead.html) and
+//!   [`calibrate`](fn.calibrate.html) is zero with this clock.
+//! - `wasm`: On WASM targets, export `coarseProfGetJson` and
+//!   `coarseProfReset` to JavaScript with
+//!   [wasm-bindgen](https://github.com/rustwasm/wasm-bindgen), so that
+//!   dashboards written in JavaScript can pull the scope tree, see the `wasm`
+//!   module. Also makes `performance.now()` the clock.
+//! - `plotters`: Render the recorded history of frame times into a PNG chart
+//!   with [plotters](https://github.com/plotters-rs/plotters), see
+//!   [`write_frame_chart_png`](fn.write_frame_chart_png.html).
+//! - `cli`: Build the `coarse-prof` command line tool, which prints reports,
+//!   flamegraphs and diffs of profiles that have been saved with
+//!   [`ProfileSnapshot::write_json`](struct.ProfileSnapshot.html#method.write_json)
+//!   and are read back with
+//!   [`ProfileSnapshot::read_json`](struct.ProfileSnapshot.html#method.read_json).
+//!
+//! # Environment
+//!
+//! The environment variable `COARSE_PROF` controls the profiler without
+//! rebuilding the program. It is read when a thread first uses the profiler:
+//!
+//! - `on` (the default): Record scopes and print the full report.
+//! - `summary`: Record scopes, but only print the hottest path, see
+//!   [`hottest_path`](fn.hottest_path.html).
+//! - `off`: Do not record anything and do not print anything.
+//!
+//! See [`Mode`](enum.Mode.html) for details.
 
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::io;
+use std::marker::PhantomData;
+use std::ptr;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
-use instant::Instant;
+#[cfg(feature = "alloc")]
+mod alloc;
+mod channel;
+mod clock;
+#[cfg(all(feature = "coarse-clock", not(feature = "cycles")))]
+mod coarse_clock;
+mod config;
+#[cfg(feature = "cycles")]
+mod cycles;
+mod error;
+mod events;
+mod file_reporter;
+mod future;
+mod history;
+#[cfg(feature = "http")]
+pub mod http;
+mod intern;
+mod json;
+mod overhead;
+mod pattern;
+#[cfg(all(feature = "perf", target_os = "linux"))]
+mod perf;
+mod pprof;
+mod quantile;
+mod report;
+mod reporter;
+#[cfg(all(feature = "rusage", unix))]
+mod rusage;
+pub mod shared;
+#[cfg(all(feature = "signal", unix))]
+mod signal;
+mod snapshot;
+mod statsd;
+mod timeline;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+mod watchdog;
+
+#[cfg(feature = "alloc")]
+pub use alloc::CoarseProfAlloc;
+pub use channel::{channel, Channel};
+use clock::Instant;
+pub use config::Config;
+#[cfg(feature = "cycles")]
+pub use cycles::cycles_per_second;
+pub use error::{Error, MisusePolicy};
+pub use events::{Anomaly, Hitch};
+use events::{Event, Events};
+pub use file_reporter::FileReporter;
+pub use future::{FutureProfileExt, Profiled};
+pub use history::{History, HistorySample, HistoryScope};
+pub use intern::ScopeName;
+pub use overhead::{calibrate, estimated_overhead, Calibration};
+#[cfg(all(feature = "perf", target_os = "linux"))]
+pub use perf::PerfCounter;
+use quantile::P2Quantile;
+pub use report::{Notation, Report, ReportOptions, Sort, TreeStyle, Unit};
+pub use reporter::{Reporter, Schedule};
+pub use snapshot::{
+    AttrStats, FrameLows, HotPath, HotPathEntry, ProfileSnapshot, ScopeStats, FRAME_TIME_BUCKETS,
+    NUM_FRAME_TIME_BUCKETS,
+};
+use statsd::StatsdExporter;
+pub use timeline::{write_lanes_svg, Timeline, TimelineRow};
+pub use watchdog::StuckScope;
+use watchdog::Watched;
+
+/// Controls what the profiler records and what is printed by
+/// [`write`](fn.write.html) and the reporting helpers built on it, such as
+/// [`report_every`](fn.report_every.html),
+/// [`print_on_exit`](fn.print_on_exit.html) and
+/// [`install_panic_hook`](fn.install_panic_hook.html).
+///
+/// The initial mode is read from the `COARSE_PROF` environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Do not record any scopes and do not print anything.
+    Off,
+
+    /// Record scopes and print the full report.
+    On,
+
+    /// Record scopes, but only print the hottest path as a single line.
+    Summary,
+}
+
+impl Mode {
+    /// Read the mode from the `COARSE_PROF` environment variable, defaulting
+    /// to `On` if it is not set.
+    fn from_env() -> Mode {
+        let value = match std::env::var("COARSE_PROF") {
+            Ok(value) => value,
+            Err(_) => return Mode::On,
+        };
+
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "0" => Mode::Off,
+            "on" | "1" | "" => Mode::On,
+            "summary" => Mode::Summary,
+            _ => {
+                log::warn!(
+                    "coarse_prof: ignoring invalid value of COARSE_PROF: {:?}",
+                    value
+                );
+                Mode::On
+            }
+        }
+    }
+}
+
+/// Number of recent frames from which the lows of the frame scope are
+/// computed.
+const NUM_RECENT_FRAMES: usize = 10_000;
+
+/// Number of recent durations of root scopes that are kept for sparklines,
+/// see `ReportOptions::sparkline`.
+const NUM_RECENT_DURATIONS: usize = 256;
+
+/// Length of the window over which `fps` measures the frequency of root
+/// scopes.
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Length of the windows over which the call rate of scopes is measured, see
+/// `set_max_call_rate`.
+const CALL_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Name of the scope that the calls of runtime names beyond
+/// `max_dynamic_children` are folded into.
+const OTHER_SCOPE_NAME: &str = "<other>";
 
 thread_local!(
-    /// Global thread-local instance of the profiler.
-    pub static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new())
+    /// Global thread-local instance of the profiler, which is accessed
+    /// through the free functions of this crate.
+    ///
+    /// The profiler is reference-counted, so that guards created by
+    /// [`enter`](fn.enter.html) can leave their scope without accessing the
+    /// thread-local again.
+    pub(crate) static PROFILER: Rc<RefCell<Profiler>> = Rc::new(RefCell::new(Profiler::new()))
 );
 
 /// Print profiling scope tree.
 ///
 /// Example output:
 /// ```text
+/// # elapsed: 1.04s
 /// frame: 100.00%, 10.40ms/call @ 96.17Hz
 ///   physics: 3.04%, 3.16ms/call @ 9.62Hz
 ///     collisions: 33.85%, 1.07ms/call @ 9.62Hz
@@ -90,375 +307,6428 @@ pub fn write<W: io::Write>(out: &mut W) -> io::Result<()> {
     PROFILER.with(|p| p.borrow().write(out))
 }
 
-/// Reset profiling information.
-pub fn reset() {
-    PROFILER.with(|p| p.borrow_mut().reset());
+/// Apply `config` to the thread-local profiler.
+///
+/// This replaces the report options, including the frame scope, and the
+/// warmup, regardless of whether they have previously been set with
+/// `configure` or with individual functions such as
+/// [`set_warmup`](fn.set_warmup.html) and
+/// [`set_frame_scope`](fn.set_frame_scope.html). The mode is only replaced
+/// if `config` sets one. Other options of recording, such as
+/// [`set_half_life`](fn.set_half_life.html) or
+/// [`set_measure_cpu_time`](fn.set_measure_cpu_time.html), are kept.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::{Config, Sort, Unit};
+///
+/// coarse_prof::configure(
+///     Config::new()
+///         .sort(Sort::TotalTime)
+///         .min_percent(1.0)
+///         .time_unit(Unit::Micros),
+/// );
+/// ```
+pub fn configure(config: Config) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.report_options = config.report_options;
+        p.warmup = config.warmup;
+        if let Some(mode) = config.mode {
+            p.mode = mode;
+        }
+    });
 }
 
-/// Manually enter a scope.
+/// Print profiling scope tree, formatted with the given options.
 ///
-/// The returned instance of [`Guard`](struct.Guard.html) should be dropped
-/// when leaving the scope.
+/// See [`ReportOptions`](struct.ReportOptions.html) for the available options.
+pub fn write_with<W: io::Write>(out: &mut W, options: &ReportOptions) -> io::Result<()> {
+    PROFILER.with(|p| p.borrow().write_with(out, options))
+}
+
+/// Print a flat view of the profiling data, in which all scopes with the same
+/// name are aggregated into a single row, regardless of their position in the
+/// tree.
 ///
-/// Usually, it is more convenient to use the macro
-/// [`profile`](macro.profile.html) for including a scope in profiling, but in
-/// some special cases explicit entering/leaving can make sense.
-pub fn enter(name: &'static str) -> Guard {
-    PROFILER.with(|p| p.borrow_mut().enter(name))
+/// Percentages represent the amount of time taken relative to the total time.
+/// See [`ProfileSnapshot::flat`](struct.ProfileSnapshot.html#method.flat) for
+/// details.
+pub fn write_flat<W: io::Write>(out: &mut W) -> io::Result<()> {
+    write!(out, "{}", snapshot().flat())?;
+    out.flush()
 }
 
-/// Use this macro to add the current scope to profiling. In effect, the time
-/// taken from entering to leaving the scope will be measured.
+/// Print the inverted (bottom-up) scope tree, which is rooted at the scopes
+/// that spend time themselves and lists their callers as children.
 ///
-/// Internally, the scope is inserted in the scope tree of the global
-/// thread-local [`PROFILER`](constant.PROFILER.html).
+/// See [`ProfileSnapshot::inverted`](struct.ProfileSnapshot.html#method.inverted)
+/// for details.
+pub fn write_inverted<W: io::Write>(out: &mut W) -> io::Result<()> {
+    write!(out, "{}", snapshot().inverted())?;
+    out.flush()
+}
+
+/// Returns the report, as printed by [`write`](fn.write.html), as a string.
+pub fn to_string() -> String {
+    snapshot().to_string()
+}
+
+/// Returns a report covering only the activity since the previous call of
+/// this function (or since the profiler was created or reset).
+///
+/// In contrast to [`reset`](fn.reset.html), this keeps the lifetime
+/// statistics, so it can be used for printing e.g. per-second slices while
+/// still being able to write a report of the whole run at the end. Scopes that
+/// have not been left since the previous call are omitted.
+pub fn to_string_delta() -> String {
+    PROFILER.with(|p| p.borrow_mut().delta_snapshot().to_string())
+}
+
+/// Write the scope tree in the protobuf format of
+/// [pprof](https://github.com/google/pprof), so that it can be inspected with
+/// `pprof`'s tools and web UI.
+///
+/// See [`ProfileSnapshot::write_pprof`](struct.ProfileSnapshot.html#method.write_pprof)
+/// for details.
 ///
 /// # Example
 ///
-/// The following example will profile the scope `"foo"`, which has the scope
-/// `"bar"` as a child.
+/// ```no_run
+/// let mut file = std::fs::File::create("profile.pb").unwrap();
+/// coarse_prof::write_pprof(&mut file).unwrap();
+/// ```
+///
+/// The profile can then be viewed with `pprof -http=: profile.pb`.
+pub fn write_pprof<W: io::Write>(out: &mut W) -> io::Result<()> {
+    snapshot().write_pprof(out)?;
+    out.flush()
+}
+
+/// Write the scope tree in the folded stacks format that is read by flamegraph
+/// tools.
+///
+/// See [`ProfileSnapshot::write_folded`](struct.ProfileSnapshot.html#method.write_folded)
+/// for details.
+///
+/// # Example
+///
+/// ```no_run
+/// let mut file = std::fs::File::create("profile.folded").unwrap();
+/// coarse_prof::write_folded(&mut file).unwrap();
+/// ```
+///
+/// The output can then be turned into a flamegraph with e.g.
+/// `inferno-flamegraph profile.folded > profile.svg`.
+pub fn write_folded<W: io::Write>(out: &mut W) -> io::Result<()> {
+    snapshot().write_folded(out)?;
+    out.flush()
+}
+
+/// Write the scope tree in the folded stacks format exactly as
+/// [tracing-flame](https://docs.rs/tracing-flame) does, so that existing
+/// flamegraph pipelines accept the output unchanged.
+///
+/// Every stack is prefixed with the current thread in `tracing-flame`'s
+/// notation, i.e. its id followed by its name if it has one, e.g.
+/// `ThreadId(1)-main;frame;physics 4000000`. The counts are self times in
+/// nanoseconds. See
+/// [`ProfileSnapshot::write_folded_with_prefix`](struct.ProfileSnapshot.html#method.write_folded_with_prefix).
+pub fn write_tracing_flame<W: io::Write>(out: &mut W) -> io::Result<()> {
+    let thread = std::thread::current();
+    let mut prefix = format!("{:?}", thread.id());
+    if let Some(name) = thread.name() {
+        prefix += "-";
+        prefix += name;
+    }
+
+    snapshot().write_folded_with_prefix(&prefix, out)?;
+    out.flush()
+}
+
+/// Write the scope tree in the InfluxDB line protocol, with the current time
+/// as the timestamp.
+///
+/// The `thread` tag is the name of the current thread, or its id if it has no
+/// name. See
+/// [`ProfileSnapshot::write_influx`](struct.ProfileSnapshot.html#method.write_influx)
+/// for details.
+///
+/// # Example
+///
+/// ```no_run
+/// let mut influx = std::net::TcpStream::connect("127.0.0.1:8094").unwrap();
+/// coarse_prof::write_influx(&mut influx).unwrap();
+/// ```
+pub fn write_influx<W: io::Write>(out: &mut W) -> io::Result<()> {
+    let thread = std::thread::current();
+    let thread = match thread.name() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", thread.id()),
+    };
+
+    snapshot().write_influx(&thread, std::time::SystemTime::now(), out)?;
+    out.flush()
+}
+
+/// Render the scope tree as an SVG flamegraph.
+///
+/// Requires the `inferno` feature. See
+/// [`ProfileSnapshot::write_flamegraph_svg`](struct.ProfileSnapshot.html#method.write_flamegraph_svg)
+/// for details.
+#[cfg(feature = "inferno")]
+pub fn write_flamegraph_svg<W: io::Write>(out: &mut W) -> io::Result<()> {
+    snapshot().write_flamegraph_svg(out)?;
+    out.flush()
+}
+
+/// Returns the chain of scopes from a root to a leaf that accounts for the
+/// most time.
+///
+/// The result can be printed as a compact one-line summary, which is useful
+/// for quick triage at the end of a run:
+///
+/// ```
+/// println!("hot path: {}", coarse_prof::hottest_path());
+/// ```
+pub fn hottest_path() -> HotPath {
+    snapshot().hottest_path()
+}
+
+/// Returns the names of the scopes that are currently being visited, starting
+/// with the root scope and ending with the innermost scope.
+///
+/// This can be useful for finding out which scope is still open, e.g. when
+/// debugging a deadlock.
+pub fn active_path() -> Vec<&'static str> {
+    PROFILER.with(|p| p.borrow().active_path())
+}
+
+/// Returns the paths of all scopes in the tree of this thread, with names
+/// separated by `/`, e.g. `frame/physics`. Every scope is followed by its
+/// descendants.
+///
+/// This includes scopes that have been entered but not left yet, so that
+/// they have no calls, as well as scopes whose calls are excluded from the
+/// statistics, e.g. by [`set_warmup`](fn.set_warmup.html). Debug UIs can use
+/// the paths for offering completions, and tests can check that the expected
+/// instrumentation exists. Scopes are forgotten by
+/// [`reset`](fn.reset.html).
+///
+/// # Example
 ///
 /// ```
 /// use coarse_prof::profile;
 ///
 /// {
-///     profile!("foo");
+///     profile!("frame");
+///     profile!("physics");
+/// }
 ///
-///     {
-///         profile!("bar");
-///         // ... do something ...
-///     }
+/// assert_eq!(coarse_prof::all_scope_paths(), vec!["frame", "frame/physics"]);
+/// ```
+pub fn all_scope_paths() -> Vec<String> {
+    PROFILER.with(|p| p.borrow().all_scope_paths())
+}
+
+/// Call `f` as if the scopes at `path` were currently being visited, so that
+/// the scopes entered by `f` are attributed under that path.
 ///
-///     // ... do some more ...
+/// This is meant for work that is sent to other threads, e.g. jobs of a
+/// `rayon` thread pool. Capture the logical parent with
+/// [`active_path`](fn.active_path.html) before spawning the jobs, re-establish
+/// it within them, and combine the trees of the threads with
+/// [`ProfileSnapshot::merge`](struct.ProfileSnapshot.html#method.merge)
+/// afterwards. The scopes at `path` are not entered themselves, so their
+/// calls and durations are only counted by the thread that actually visits
+/// them.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::profile!("frame");
+/// coarse_prof::profile!("physics");
+///
+/// let path = coarse_prof::active_path();
+/// let worker = std::thread::spawn(move || {
+///     coarse_prof::in_scope_of(&path, || {
+///         coarse_prof::profile!("collisions");
+///     });
+///     coarse_prof::snapshot()
+/// });
+///
+/// let mut snapshot = coarse_prof::snapshot();
+/// snapshot.merge(&worker.join().unwrap());
+/// // `collisions` is shown below `frame/physics`.
+/// ```
+pub fn in_scope_of<R, F: FnOnce() -> R>(path: &[&'static str], f: F) -> R {
+    /// Restores the current scope, even if `f` panics.
+    struct Restore(Option<(Option<ScopeId>, u64)>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(previous) = self.0 {
+                PROFILER.with(|p| p.borrow_mut().restore_current(previous));
+            }
+        }
+    }
+
+    let _restore = Restore(PROFILER.with(|p| p.borrow_mut().set_current_path(path)));
+    f()
+}
+
+/// Attribute all scopes of the current thread to the parent scopes at `path`,
+/// which consists of scope names separated by `/`.
+///
+/// The root scopes of the thread are nested below `path` in its snapshots and
+/// reports, so that e.g. everything that an asset-loading thread does shows
+/// up below `background/asset_load` when its snapshot is combined with that
+/// of the main thread by
+/// [`ProfileSnapshot::merge`](struct.ProfileSnapshot.html#method.merge). The
+/// parent scopes themselves are not measured. An empty path removes the
+/// parent. The parent is kept when resetting the profiler.
+///
+/// # Example
+///
+/// ```
+/// let loader = std::thread::spawn(|| {
+///     coarse_prof::set_thread_parent("background/asset_load");
+///
+///     coarse_prof::profile!("decode_textures");
+///     // ... decode textures ...
+///
+///     coarse_prof::snapshot()
+/// });
+///
+/// let mut snapshot = coarse_prof::snapshot();
+/// snapshot.merge(&loader.join().unwrap());
+/// // `decode_textures` is shown below `background/asset_load`.
+/// ```
+pub fn set_thread_parent(path: &str) {
+    PROFILER.with(|p| {
+        p.borrow_mut().thread_parent = path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+    });
+}
+
+/// Take a snapshot of the profiling scope tree.
+///
+/// The snapshot is an owned copy of the current statistics, which can be
+/// formatted with `Display`, inspected programmatically, or sent to another
+/// thread.
+///
+/// # Example
+///
+/// ```
+/// let snapshot = coarse_prof::snapshot();
+///
+/// for root in &snapshot.roots {
+///     println!("{}", root);
 /// }
 /// ```
-#[macro_export]
-macro_rules! profile {
-    ($name:expr) => {
-        let _guard = $crate::PROFILER.with(|p| p.borrow_mut().enter($name));
+pub fn snapshot() -> ProfileSnapshot {
+    PROFILER.with(|p| p.borrow().snapshot(false))
+}
+
+/// Returns how often per second the root scope called `name` has been
+/// entered recently, or `None` if it has not been entered at least twice
+/// within the last second.
+///
+/// This allows driving an on-screen FPS counter straight from the profiler.
+///
+/// # Example
+///
+/// ```
+/// for _ in 0..3 {
+///     coarse_prof::profile!("frame");
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// }
+///
+/// let fps = coarse_prof::fps("frame").unwrap();
+/// assert!(fps > 0.0 && fps <= 100.0);
+/// ```
+pub fn fps(name: &str) -> Option<f64> {
+    PROFILER.with(|p| {
+        let p = p.borrow();
+        let root = &p.scopes[*p.roots_by_name.get(name)?];
+
+        // Entries are only trimmed when entering the root, so skip the ones
+        // that have left the window since then.
+        let now = Instant::now();
+        let mut recent = root
+            .recent_entries
+            .iter()
+            .skip_while(|&&entry| now.saturating_duration_since(entry) > FPS_WINDOW);
+        let first = *recent.next()?;
+        let (count, last) = recent.fold((1, first), |(count, _), &entry| (count + 1, entry));
+        let secs = last.duration_since(first).as_secs_f64();
+
+        if count >= 2 && secs > 0.0 {
+            Some((count - 1) as f64 / secs)
+        } else {
+            None
+        }
+    })
+}
+
+/// Attach a key-value pair of metadata to the profiler, such as a build hash,
+/// the name of the current map or the GPU model.
+///
+/// Metadata is included in the header of the report and in all exports. If
+/// `key` is already present, its value is replaced. Metadata is kept when
+/// calling [`reset`](fn.reset.html).
+pub fn set_metadata<K: Into<String>, V: Into<String>>(key: K, value: V) {
+    PROFILER.with(|p| p.borrow_mut().set_metadata(key.into(), value.into()));
+}
+
+/// Remove all metadata that has been attached to the profiler.
+pub fn clear_metadata() {
+    PROFILER.with(|p| p.borrow_mut().metadata.clear());
+}
+
+/// Designate the root scope called `name` as the frame.
+///
+/// Reports written by this profiler then additionally show the average cost
+/// per frame of all scopes below the frame. See
+/// [`ReportOptions::frame_scope`](struct.ReportOptions.html#method.frame_scope)
+/// for details.
+pub fn set_frame_scope(name: &'static str) {
+    PROFILER.with(|p| p.borrow_mut().report_options.frame_scope = Some(name.to_string()));
+}
+
+/// Change the [`Mode`](enum.Mode.html) of the profiler, overriding the
+/// `COARSE_PROF` environment variable.
+///
+/// Scopes that are active while switching the mode off are still left
+/// properly.
+pub fn set_mode(mode: Mode) {
+    PROFILER.with(|p| p.borrow_mut().mode = mode);
+}
+
+/// Set how misuse of the profiler, such as entering a scope from within a
+/// reporter, is handled in all threads. See [`Error`](enum.Error.html) for
+/// the kinds of misuse that are detected.
+///
+/// Defaults to [`MisusePolicy::LogOnce`](enum.MisusePolicy.html#variant.LogOnce),
+/// which reports every kind of misuse once and then carries on.
+///
+/// # Example
+///
+/// Panic in debug builds, but degrade gracefully for players:
+///
+/// ```
+/// use coarse_prof::MisusePolicy;
+///
+/// coarse_prof::set_misuse_policy(if cfg!(debug_assertions) {
+///     MisusePolicy::Panic
+/// } else {
+///     MisusePolicy::LogOnce
+/// });
+/// ```
+pub fn set_misuse_policy(policy: MisusePolicy) {
+    error::set_policy(policy);
+}
+
+/// Enable or disable all scopes whose path matches `pattern`.
+///
+/// The path of a scope consists of its name and the names of its ancestors,
+/// separated by `/`, e.g. `frame/ai/pathfinding`. In the pattern, `*` matches
+/// any part of a single name, and `**` matches any number of names. For
+/// example, `frame/ai/**` matches `frame/ai` and all of its descendants,
+/// while `**/debug_*` matches all scopes whose name starts with `debug_`.
+///
+/// Disabled scopes are not measured at all, skipping even reading the clock.
+/// Their children are not recorded either, regardless of other rules. If
+/// multiple rules match a scope, the one that has been set last wins. This is
+/// useful for muting fine-grained subsystems whose profiling overhead distorts
+/// the rest of the report.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::set_scope_enabled("frame/ai/**", false);
+/// ```
+pub fn set_scope_enabled(pattern: &str, enabled: bool) {
+    PROFILER.with(|p| p.borrow_mut().set_scope_enabled(pattern, enabled));
+}
+
+/// Call `callback` with the statistics of every scope whose path matches
+/// `pattern` after every `every`-th call of the scope.
+///
+/// The statistics include the scope's children, but not the rest of the tree,
+/// so that e.g. HUD widgets and telemetry can react to fresh numbers without
+/// polling and walking the whole tree. See
+/// [`set_scope_enabled`](fn.set_scope_enabled.html) for the syntax of
+/// patterns. Multiple callbacks can be subscribed at the same time.
+///
+/// The callback is called on the profiled thread when leaving the scope.
+/// Scopes that are entered within the callback are profiled, but do not
+/// trigger further callbacks.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::subscribe("frame", 60, |stats| {
+///     println!("Average frame time: {:?}", stats.duration_mean());
+/// });
+/// ```
+pub fn subscribe<F: FnMut(&ScopeStats) + 'static>(pattern: &str, every: usize, callback: F) {
+    PROFILER.with(|p| p.borrow_mut().subscribe(pattern, every, callback));
+}
+
+/// Remove all callbacks that have been subscribed with
+/// [`subscribe`](fn.subscribe.html).
+pub fn unsubscribe_all() {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.subscriptions.clear();
+        p.events.callbacks.clear_stats();
+        for scope in p.scopes.iter_mut() {
+            scope.subscriptions.clear();
+        }
+    });
+}
+
+/// Attach a key-value attribute to the current call of the innermost active
+/// scope.
+///
+/// Values are aggregated per scope and key, and shown as mean, minimum and
+/// maximum next to the scope's time in the report. This helps with
+/// correlating the cost of a scope with the size of its workload. Attributes
+/// are ignored if there is no active scope.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::profile;
+///
+/// let entities = vec![(); 42];
+///
+/// profile!("update");
+/// coarse_prof::attr("entities", entities.len() as f64);
+/// ```
+pub fn attr(key: &'static str, value: f64) {
+    PROFILER.with(|p| p.borrow_mut().attr(key, value));
+}
+
+/// Additionally compute averages of the durations in which older calls have
+/// less weight, or stop computing them with `None`.
+///
+/// The weight of every call halves with every `half_life` of wall time that
+/// passes, so the report shows the recent behavior of scopes next to their
+/// lifetime averages, e.g. `4.20ms decayed`. In contrast to resetting
+/// periodically, this tracks changes during long play sessions smoothly.
+/// Any previously computed decayed averages are discarded. Defaults to
+/// `None`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// coarse_prof::set_half_life(Some(Duration::from_secs(10)));
+/// ```
+pub fn set_half_life(half_life: Option<Duration>) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.half_life = half_life;
+        for scope in p.scopes.iter_mut() {
+            scope.decayed = None;
+        }
+    });
+}
+
+/// Measure the CPU time that the thread consumes in every scope, in addition
+/// to the elapsed wall time.
+///
+/// The report then shows the average CPU time per call next to the wall time,
+/// so that time spent blocking, e.g. waiting for I/O or locks, can be
+/// distinguished from actual computation. Since reading the CPU time is
+/// relatively expensive, this is off by default. It should be turned on before
+/// entering any scopes, or followed by a [`reset`](fn.reset.html), since the
+/// averages are computed over all calls.
+///
+/// This requires the `cpu-time` feature and a Unix platform, where
+/// `CLOCK_THREAD_CPUTIME_ID` is read. On other platforms, including Windows,
+/// no CPU time is measured.
+pub fn set_measure_cpu_time(measure_cpu_time: bool) {
+    PROFILER.with(|p| p.borrow_mut().measure_cpu_time = measure_cpu_time);
+}
+
+/// Measure the given hardware counters in every scope, such as the number of
+/// retired instructions or cache misses.
+///
+/// The counters of every call are attached to the scope as attributes, see
+/// [`attr`](fn.attr.html), so the report shows their average, minimum and
+/// maximum per call next to the time. Passing an empty slice stops measuring.
+///
+/// This requires the `perf` feature and Linux. Opening the counters fails if
+/// the kernel does not allow it, e.g. because of
+/// `/proc/sys/kernel/perf_event_paranoid`, or if more than three counters are
+/// given.
+///
+/// # Example
+///
+/// ```no_run
+/// use coarse_prof::PerfCounter;
+///
+/// coarse_prof::set_perf_counters(&[PerfCounter::Instructions, PerfCounter::CacheMisses])
+///     .expect("Failed to open perf counters");
+/// ```
+#[cfg(all(feature = "perf", target_os = "linux"))]
+pub fn set_perf_counters(counters: &[PerfCounter]) -> io::Result<()> {
+    let counters = if counters.is_empty() {
+        None
+    } else {
+        Some(perf::Counters::open(counters)?)
     };
+
+    PROFILER.with(|p| p.borrow_mut().perf_counters = counters);
+    Ok(())
+}
+
+/// Count voluntary and involuntary context switches as well as minor and
+/// major page faults in every scope, by sampling `getrusage` when entering and
+/// leaving scopes.
+///
+/// The counts of every call are attached to the scope as attributes, see
+/// [`attr`](fn.attr.html), which helps with diagnosing frames that have been
+/// ruined by the operating system rather than by the code. On Linux, the
+/// counts are specific to the thread, while on other platforms, they cover
+/// the whole process.
+///
+/// This requires the `rusage` feature and a Unix platform. Otherwise, nothing
+/// is counted.
+pub fn set_measure_rusage(measure_rusage: bool) {
+    PROFILER.with(|p| p.borrow_mut().measure_rusage = measure_rusage);
 }
 
-/// Internal representation of scopes as a tree.
-struct Scope {
-    /// Name of the scope.
-    name: &'static str,
+/// Estimate the median duration of every scope, which the report then shows
+/// next to the average, e.g. `4.20ms median`.
+///
+/// In typical frame data, the average is badly skewed by rare hitches, while
+/// the median reflects the common case. The median is estimated with the P²
+/// algorithm, which needs constant memory per scope, so it is approximate.
+/// It should be turned on before entering any scopes, or followed by a
+/// [`reset`](fn.reset.html), since earlier calls are not included. Defaults
+/// to `false`.
+pub fn set_estimate_median(estimate_median: bool) {
+    PROFILER.with(|p| p.borrow_mut().estimate_median = estimate_median);
+}
+
+/// Warn once per scope that is entered more than `max_calls_per_sec` times
+/// per second.
+///
+/// `coarse-prof` is meant for scopes that take at least a few microseconds.
+/// Scopes that are entered at a very high rate, e.g. in an inner loop, are
+/// dominated by the overhead of profiling and skew the durations of their
+/// ancestors, so it is usually better to move them out of the loop. The
+/// warning is emitted with `log::warn!` and names the path of the scope.
+/// The rate is measured over windows of one second. Defaults to `None`, i.e.
+/// no warnings.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::set_max_call_rate(Some(100_000.0));
+/// ```
+pub fn set_max_call_rate(max_calls_per_sec: Option<f64>) {
+    PROFILER.with(|p| p.borrow_mut().max_call_rate = max_calls_per_sec);
+}
+
+/// Limit the number of children with runtime names per scope, folding the
+/// calls of further names into a single `<other>` child.
+///
+/// Scopes named at runtime, e.g. with [`enter_owned`](fn.enter_owned.html)
+/// and a per-entity id, can add an unbounded number of scopes to the tree.
+/// With a limit, a scope keeps at most `limit` such children, and new names
+/// beyond that are entered as `<other>` instead, which also absorbs any path
+/// below the name. Names that already have a scope keep being recorded in
+/// it, and scopes with names known at compile time are never folded.
+/// Defaults to `None`, i.e. no limit.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::set_max_dynamic_children(Some(64));
+///
+/// for entity in 0..1000 {
+///     let _guard = coarse_prof::enter_owned(format!("entity_{}", entity));
+/// }
+///
+/// assert_eq!(coarse_prof::all_scope_paths().len(), 65);
+/// ```
+pub fn set_max_dynamic_children(limit: Option<usize>) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.max_dynamic_children = limit;
+
+        // Names that have been folded before may get a scope now.
+        p.name_cache.clear();
+    });
+}
+
+/// Measure the time spent in the bookkeeping of the profiler itself, i.e. in
+/// looking up, entering and leaving scopes, which the report then shows as a
+/// `<profiler>` row, e.g. `<profiler>: 0.12%, 1.20ms total`.
+///
+/// This shows whether the instrumentation distorts the measurements, e.g.
+/// because scopes are entered in a hot loop. Measuring the overhead reads
+/// the clock twice more per scope, which is itself included in the
+/// measurement. The time is counted from the next call on. Defaults to
+/// `false`.
+pub fn set_measure_overhead(measure_overhead: bool) {
+    PROFILER.with(|p| p.borrow_mut().measure_overhead = measure_overhead);
+}
+
+/// Count the allocations and allocated bytes of every scope.
+///
+/// This requires the `alloc` feature and using
+/// [`CoarseProfAlloc`](struct.CoarseProfAlloc.html) as the global allocator.
+/// Otherwise, nothing is counted.
+pub fn set_measure_allocs(measure_allocs: bool) {
+    PROFILER.with(|p| p.borrow_mut().measure_allocs = measure_allocs);
+}
+
+/// Publish the duration of every call of a scope to the
+/// [metrics](https://docs.rs/metrics) facade.
+///
+/// The durations are recorded in seconds with `metrics::histogram!`, using
+/// the path of the scope, e.g. `frame/physics`, as the metric name. This
+/// makes scope timings available to any exporter that has been installed as
+/// the global recorder, e.g. for Prometheus.
+///
+/// This requires the `metrics` feature. Otherwise, nothing is published.
+pub fn set_publish_metrics(publish_metrics: bool) {
+    PROFILER.with(|p| p.borrow_mut().publish_metrics = publish_metrics);
+}
+
+/// Record when every call of a scope starts and ends, keeping the last
+/// `capacity` calls of each scope. Pass zero to stop recording.
+///
+/// This allows rendering a Gantt chart of the last frame with
+/// [`write_timeline_svg`](fn.write_timeline_svg.html), showing exactly when
+/// each system ran within the frame.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::set_record_timeline(64);
+///
+/// for _ in 0..10 {
+///     coarse_prof::profile!("frame");
+///
+///     {
+///         coarse_prof::profile!("physics");
+///     }
+///     {
+///         coarse_prof::profile!("render");
+///     }
+/// }
+///
+/// let timeline = coarse_prof::timeline().unwrap();
+/// assert_eq!(timeline.rows.len(), 3);
+/// ```
+pub fn set_record_timeline(capacity: usize) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.timeline_capacity = capacity;
+        for scope in p.scopes.iter_mut() {
+            let excess = scope.timeline.len().saturating_sub(capacity);
+            scope.timeline.drain(..excess);
+            scope.timeline.shrink_to_fit();
+        }
+    });
+}
+
+/// Returns the timeline of the last frame, i.e. of the root scope that has
+/// been left most recently, or `None` if no timeline has been recorded.
+///
+/// See [`set_record_timeline`](fn.set_record_timeline.html).
+pub fn timeline() -> Option<Timeline> {
+    PROFILER.with(|p| p.borrow().timeline())
+}
+
+/// Render the timeline of the last frame as an SVG Gantt chart.
+///
+/// Returns an error if no timeline has been recorded, see
+/// [`set_record_timeline`](fn.set_record_timeline.html).
+pub fn write_timeline_svg<W: io::Write>(out: &mut W) -> io::Result<()> {
+    let timeline =
+        timeline().ok_or_else(|| io::Error::other("coarse_prof: no timeline has been recorded"))?;
+    timeline.write_svg(out)?;
+    out.flush()
+}
+
+/// Render the timeline of the last frame as an SVG swim-lane chart, with one
+/// lane per depth of the tree, see
+/// [`Timeline::write_lanes_svg`](struct.Timeline.html#method.write_lanes_svg).
+/// Use [`write_lanes_svg`](fn.write_lanes_svg.html) for showing the
+/// timelines of several threads.
+///
+/// Returns an error if no timeline has been recorded, see
+/// [`set_record_timeline`](fn.set_record_timeline.html).
+pub fn write_timeline_lanes_svg<W: io::Write>(out: &mut W) -> io::Result<()> {
+    let timeline =
+        timeline().ok_or_else(|| io::Error::other("coarse_prof: no timeline has been recorded"))?;
+    timeline.write_lanes_svg(out)?;
+    out.flush()
+}
+
+/// Call `callback` whenever the recent mean duration of a scope deviates from
+/// its long-term mean by more than `factor`, e.g. `1.5` for being 50% slower
+/// or faster, for automatically detecting gradual regressions in soak tests.
+///
+/// The calls of every scope are split into consecutive windows of `window`
+/// calls. Whenever a window is complete, its mean is compared to the mean of
+/// all previous calls, provided that there have been at least `window` of
+/// them. Any previously installed callback is replaced.
+///
+/// The callback is called on the profiled thread when leaving the scope.
+/// Scopes that are entered within the callback are profiled, but do not
+/// trigger further callbacks.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::on_anomaly(100, 1.5, |anomaly| {
+///     log::warn!(
+///         "{} took {:?} on average recently, up from {:?}",
+///         anomaly.path,
+///         anomaly.recent_mean,
+///         anomaly.long_term_mean,
+///     );
+/// });
+/// ```
+pub fn on_anomaly<F: FnMut(&Anomaly) + 'static>(window: usize, factor: f64, callback: F) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.anomaly_detector = Some(AnomalyDetector {
+            window: window.max(1),
+            factor,
+        });
+        p.events.callbacks.on_anomaly = Some(Box::new(callback));
+    });
+}
+
+/// Stop detecting anomalies and remove the callback that has been installed
+/// with [`on_anomaly`](fn.on_anomaly.html).
+pub fn stop_detecting_anomalies() {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.anomaly_detector = None;
+        p.events.callbacks.remove_anomaly();
+    });
+}
+
+/// Call `callback` whenever a single call of any scope takes at least
+/// `threshold`, passing the path of the scope, the duration of the call and
+/// the index of the frame.
+///
+/// This allows logging hitches without configuring budgets for every scope.
+/// Note that a hitch in a scope usually also is a hitch in its ancestors, so
+/// the callback is called for each of them, starting with the innermost
+/// scope. Any previously installed callback is replaced.
+///
+/// The callback is called on the profiled thread when leaving the scope.
+/// Scopes that are entered within the callback are profiled, but do not
+/// trigger further callbacks.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// coarse_prof::on_hitch(Duration::from_millis(33), |hitch| {
+///     log::warn!(
+///         "Hitch in frame {}: {} took {:?}",
+///         hitch.frame,
+///         hitch.path,
+///         hitch.duration,
+///     );
+/// });
+/// ```
+pub fn on_hitch<F: FnMut(&Hitch) + 'static>(threshold: Duration, callback: F) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.hitch_threshold = Some(threshold);
+        p.events.callbacks.on_hitch = Some(Box::new(callback));
+    });
+}
+
+/// Stop detecting hitches and remove the callback that has been installed
+/// with [`on_hitch`](fn.on_hitch.html).
+pub fn stop_detecting_hitches() {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        p.hitch_threshold = None;
+        p.events.callbacks.remove_hitch();
+    });
+}
+
+/// Watch the current thread with a helper thread that calls `callback` when
+/// a scope has been active for longer than `limit`.
+///
+/// For example, a `network/recv` scope that has been open for five seconds
+/// indicates a blocking bug. Only the innermost stuck scope is reported, and
+/// every call of a scope is reported at most once. Any previous watchdog of
+/// the current thread is stopped.
+///
+/// The callback is called on the helper thread while the watched thread is
+/// still stuck. While the watchdog is running, entering and leaving scopes is
+/// slightly more expensive.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// coarse_prof::start_watchdog(Duration::from_secs(5), |stuck| {
+///     log::error!(
+///         "Thread {} is stuck in {} for {:?}",
+///         stuck.thread,
+///         stuck.path,
+///         stuck.active_for,
+///     );
+/// });
+/// ```
+pub fn start_watchdog<F>(limit: Duration, callback: F)
+where
+    F: Fn(&StuckScope) + Send + 'static,
+{
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let active_path: Vec<_> = p
+            .active_path_ids()
+            .into_iter()
+            .filter_map(|id| Some((p.scopes[id].name, p.scopes[id].active_since?)))
+            .collect();
+        p.watched = Some(Watched::spawn(limit, callback, active_path.into_iter()));
+    });
+}
+
+/// Stop the watchdog of the current thread that has been started with
+/// [`start_watchdog`](fn.start_watchdog.html).
+pub fn stop_watchdog() {
+    PROFILER.with(|p| p.borrow_mut().watched = None);
+}
+
+/// Reset profiling information.
+pub fn reset() {
+    PROFILER.with(|p| p.borrow_mut().reset());
+}
+
+/// Reset the statistics of all scopes, but keep the scopes themselves.
+///
+/// In contrast to [`reset`](fn.reset.html), the tree does not need to be
+/// built up again afterwards, and scopes keep their order in the report
+/// between measurement windows. Scopes that are not visited again are shown
+/// without statistics. Settings of scopes, such as whether they are enabled,
+/// and their warm-up progress are kept as well. Scopes that are active are
+/// split, so that only the time after the reset is recorded for them.
+pub fn reset_stats() {
+    PROFILER.with(|p| p.borrow_mut().reset_stats());
+}
+
+/// Periodically write the report to `sink`.
+///
+/// The report is written whenever a root scope is entered and at least
+/// `interval` has passed since the last report (or since calling this
+/// function). Thus, reports are only written between frames and never while
+/// the thread is idle. Any previously installed periodic report is replaced.
+///
+/// Errors that occur while writing the report are logged and otherwise
+/// ignored.
+pub fn report_every<W: io::Write + 'static>(interval: Duration, sink: W) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let sink = PeriodicSink::Writer(Box::new(sink));
+        p.periodic = Some(Periodic::new(
+            Schedule::Interval(interval),
+            false,
+            sink,
+            p.num_frames,
+        ));
+    });
+}
+
+/// Periodically write the report to `sink` and reset profiling information
+/// afterwards.
+///
+/// This behaves like [`report_every`](fn.report_every.html), except that
+/// each report only covers the time since the previous report.
+pub fn report_and_reset_every<W: io::Write + 'static>(interval: Duration, sink: W) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let sink = PeriodicSink::Writer(Box::new(sink));
+        p.periodic = Some(Periodic::new(
+            Schedule::Interval(interval),
+            true,
+            sink,
+            p.num_frames,
+        ));
+    });
+}
+
+/// Write the report to `sink` whenever the process receives `SIGUSR1`, e.g.
+/// via `kill -USR1 <pid>`.
+///
+/// This is essential for diagnosing live processes, such as game servers,
+/// that can not be restarted. Since the profiler is local to each thread,
+/// the signal handler only sets a flag, and the report of the thread that
+/// called this function is written the next time that it enters a root
+/// scope. Calling this again on the same thread replaces the sink. If
+/// multiple threads call this, the report of only one of them is written
+/// per signal.
+///
+/// Requires the `signal` feature and a Unix platform. Returns an error if
+/// the signal handler could not be installed.
+///
+/// # Example
+///
+/// ```no_run
+/// coarse_prof::write_on_signal(std::io::stderr()).unwrap();
+/// ```
+#[cfg(all(feature = "signal", unix))]
+pub fn write_on_signal<W: io::Write + 'static>(sink: W) -> io::Result<()> {
+    signal::install()?;
+    PROFILER.with(|p| p.borrow_mut().signal_sink = Some(Box::new(sink)));
+    Ok(())
+}
+
+/// Periodically write the report to a new file with `reporter`, which keeps
+/// only the most recent files.
+///
+/// Reports are written at the same points in time as with
+/// [`report_every`](fn.report_every.html), which is replaced by this. They
+/// are formatted with the options of `reporter`, see
+/// [`FileReporter::options`](struct.FileReporter.html#method.options).
+pub fn report_to_files_every(interval: Duration, reporter: FileReporter) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let sink = PeriodicSink::Reporter(Some(Box::new(reporter)));
+        p.periodic = Some(Periodic::new(
+            Schedule::Interval(interval),
+            false,
+            sink,
+            p.num_frames,
+        ));
+    });
+}
+
+/// Stop writing periodic reports.
+///
+/// Reporters that have been added with [`add_reporter`](fn.add_reporter.html)
+/// are kept.
+pub fn stop_reporting() {
+    PROFILER.with(|p| p.borrow_mut().periodic = None);
+}
+
+/// Invoke `reporter` with a snapshot of the scope tree according to
+/// `schedule`.
+///
+/// Like periodic reports, reporters are only invoked between frames, i.e.
+/// when a root scope is entered. Multiple reporters can be added, each with
+/// its own schedule. See [`Reporter`](trait.Reporter.html) for an example.
+pub fn add_reporter<R: Reporter + 'static>(schedule: Schedule, reporter: R) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let sink = PeriodicSink::Reporter(Some(Box::new(reporter)));
+        let periodic = Periodic::new(schedule, false, sink, p.num_frames);
+        p.reporters.push(periodic);
+    });
+}
+
+/// Periodically take a snapshot of the scope tree and invoke `reporter` with
+/// it on a background thread.
+///
+/// Only taking the snapshot happens on the profiled thread, between frames,
+/// so that the cost of formatting or uploading the report does not affect
+/// the frames that are being measured. Snapshots are taken at the same
+/// points in time as with [`add_reporter`](fn.add_reporter.html). The
+/// background thread exits once the reporter has been removed with
+/// [`remove_reporters`](fn.remove_reporters.html) and all pending snapshots
+/// have been handled.
+///
+/// Returns an error if the thread can not be spawned, in which case the
+/// reporter is not added.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// coarse_prof::spawn_reporter(Duration::from_secs(10), |snapshot: &coarse_prof::ProfileSnapshot| {
+///     // ... upload the snapshot ...
+/// })
+/// .unwrap();
+/// ```
+pub fn spawn_reporter<R: Reporter + Send + 'static>(
+    interval: Duration,
+    mut reporter: R,
+) -> io::Result<std::thread::JoinHandle<()>> {
+    let (sender, receiver) = std::sync::mpsc::channel::<ProfileSnapshot>();
+
+    let handle = std::thread::Builder::new()
+        .name("coarse_prof reporter".to_string())
+        .spawn(move || {
+            for snapshot in receiver {
+                reporter.report(&snapshot);
+            }
+        })?;
+
+    add_reporter(
+        Schedule::Interval(interval),
+        move |snapshot: &ProfileSnapshot| {
+            // The thread only exits when we are removed, so sending can not
+            // fail.
+            let _ = sender.send(snapshot.clone());
+        },
+    );
+
+    Ok(handle)
+}
+
+/// Remove all reporters that have been added with
+/// [`add_reporter`](fn.add_reporter.html).
+pub fn remove_reporters() {
+    PROFILER.with(|p| p.borrow_mut().reporters.clear());
+}
+
+/// Write the durations of all scopes in every frame to `sink`, in the JSON
+/// Lines format.
+///
+/// A frame ends whenever a root scope is left. For every frame, a single line
+/// is written, containing the frame number, the time at which the frame ended
+/// (in seconds since the Unix epoch), and the time spent in the root scope and
+/// in every descendant that has been visited during the frame. Scopes are
+/// identified by their path in the tree, e.g.:
+/// ```text
+/// {"frame":0,"timestamp_secs":1700000000.5,"scopes":{"frame":0.0161,"frame/physics":0.0042}}
+/// ```
+///
+/// This is intended for ingestion by log pipelines and later aggregation. Any
+/// previously installed recorder is replaced. Errors that occur while writing
+/// are logged and otherwise ignored.
+pub fn record_frames<W: io::Write + 'static>(sink: W) {
+    PROFILER.with(|p| {
+        p.borrow_mut().frame_recorder = Some(FrameRecorder {
+            sink: Box::new(sink),
+            num_frames: 0,
+            last_sums_ns: Vec::new(),
+        })
+    });
+}
+
+/// Stop writing frames that have been requested with
+/// [`record_frames`](fn.record_frames.html).
+pub fn stop_recording_frames() {
+    PROFILER.with(|p| p.borrow_mut().frame_recorder = None);
+}
+
+/// Periodically send the durations of all scopes to the StatsD server at
+/// `addr` over UDP.
+///
+/// At the end of every `interval`, the mean duration in milliseconds of each
+/// scope that has been left in the interval is sent as a timing, and its
+/// number of calls as a counter. The path of the scope is used as the metric
+/// name, with the names separated by `.`, e.g.:
+/// ```text
+/// frame.physics:4.2|ms|#env:prod
+/// frame.physics.calls:60|c|#env:prod
+/// ```
+///
+/// `tags` are appended to every metric in the DogStatsD format, which is
+/// understood by e.g. Datadog. Pass an empty slice for plain StatsD. Like
+/// periodic reports, metrics are only sent between frames. Any previously
+/// installed exporter is replaced. Errors that occur while sending are logged
+/// and otherwise ignored.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// coarse_prof::send_statsd_every(
+///     Duration::from_secs(10),
+///     "127.0.0.1:8125",
+///     &[("env", "prod")],
+/// )
+/// .unwrap();
+/// ```
+pub fn send_statsd_every<A: std::net::ToSocketAddrs>(
+    interval: Duration,
+    addr: A,
+    tags: &[(&str, &str)],
+) -> io::Result<()> {
+    let exporter = StatsdExporter::new(interval, addr, tags)?;
+    PROFILER.with(|p| p.borrow_mut().statsd_exporter = Some(exporter));
+    Ok(())
+}
+
+/// Stop sending metrics that have been requested with
+/// [`send_statsd_every`](fn.send_statsd_every.html).
+pub fn stop_sending_statsd() {
+    PROFILER.with(|p| p.borrow_mut().statsd_exporter = None);
+}
+
+/// Keep a history of the number of calls and the total duration of every
+/// scope per `interval`, e.g. per second, retaining the last `capacity`
+/// intervals.
+///
+/// This allows charting how the cost of scopes develops over time, for
+/// example as a level fills up with entities. Intervals end between frames,
+/// so they can be slightly longer than requested. The history can be
+/// retrieved with [`history`](fn.history.html) and exported as JSON or CSV.
+/// Any previously recorded history is discarded.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// coarse_prof::record_history(Duration::from_secs(1), 3600);
+///
+/// // ... run the game ...
+///
+/// let mut file = std::fs::File::create("history.csv").unwrap();
+/// coarse_prof::history().write_csv(&mut file).unwrap();
+/// ```
+pub fn record_history(interval: Duration, capacity: usize) {
+    PROFILER
+        .with(|p| p.borrow_mut().history_recorder = Some(HistoryRecorder::new(interval, capacity)));
+}
+
+/// Stop recording the history that has been requested with
+/// [`record_history`](fn.record_history.html), discarding it.
+pub fn stop_recording_history() {
+    PROFILER.with(|p| p.borrow_mut().history_recorder = None);
+}
+
+/// Returns the history that has been recorded since calling
+/// [`record_history`](fn.record_history.html). The interval that is in
+/// progress is not included.
+pub fn history() -> History {
+    PROFILER.with(|p| match p.borrow().history_recorder.as_ref() {
+        Some(recorder) => History {
+            interval: recorder.interval,
+            samples: recorder.samples.iter().cloned().collect(),
+        },
+        None => History {
+            interval: Duration::new(0, 0),
+            samples: Vec::new(),
+        },
+    })
+}
+
+/// Render the time per frame of the history that has been recorded since
+/// calling [`record_history`](fn.record_history.html) as a PNG chart, so that
+/// e.g. playtest reports can include it.
+///
+/// The chart shows the frame scope that has been set with
+/// [`set_frame_scope`](fn.set_frame_scope.html), or otherwise the root scope
+/// that took the most time, with its children as stacked areas, see
+/// [`History::write_frame_chart_png`](struct.History.html#method.write_frame_chart_png).
+/// Returns an error if no history has been recorded.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// coarse_prof::record_history(Duration::from_secs(1), 600);
+///
+/// // ... run the game ...
+///
+/// coarse_prof::write_frame_chart_png("frames.png").unwrap();
+/// ```
+#[cfg(feature = "plotters")]
+pub fn write_frame_chart_png<P: AsRef<std::path::Path>>(path: P) -> io::Result<()> {
+    let history = history();
+    let frame_scope = PROFILER
+        .with(|p| p.borrow().report_options.frame_scope.clone())
+        .or_else(|| {
+            let mut roots: Vec<(&str, Duration)> = Vec::new();
+            for scope in history.samples.iter().flat_map(|sample| &sample.scopes) {
+                if scope.parent_id.is_none() {
+                    match roots.iter_mut().find(|(path, _)| *path == scope.path) {
+                        Some((_, sum)) => *sum += scope.duration_sum,
+                        None => roots.push((&scope.path, scope.duration_sum)),
+                    }
+                }
+            }
+            roots
+                .into_iter()
+                .max_by_key(|&(_, sum)| sum)
+                .map(|(path, _)| path.to_string())
+        })
+        .ok_or_else(|| io::Error::other("coarse_prof: no history has been recorded"))?;
+
+    history.write_frame_chart_png(&frame_scope, path)
+}
+
+/// Maintain the statistics of every scope per `window`, e.g. per 5 seconds,
+/// retaining the last `capacity` windows.
+///
+/// Windows expire automatically, so recent behavior can be compared with the
+/// lifetime statistics without resetting the profiler. While windows are
+/// being recorded, [`write`](fn.write.html) shows the average and maximum
+/// duration and the frequency of every scope in the last completed window
+/// next to its lifetime statistics, e.g. `last window: 4.20ms avg, 9.10ms max
+/// @ 60.00Hz`. The windows can be retrieved with
+/// [`windows`](fn.windows.html). Windows end between frames, so they can be
+/// slightly longer than requested. Any previously recorded windows are
+/// discarded, and the first window starts now.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// coarse_prof::record_windows(Duration::from_secs(5), 24);
+///
+/// // ... run the game ...
+///
+/// coarse_prof::write(&mut std::io::stdout()).unwrap();
+/// ```
+pub fn record_windows(window: Duration, capacity: usize) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        for scope in p.scopes.iter_mut() {
+            scope.window = Delta::new();
+        }
+        p.window_recorder = Some(WindowRecorder::new(window, capacity));
+    });
+}
+
+/// Stop recording the windows that have been requested with
+/// [`record_windows`](fn.record_windows.html), discarding them.
+pub fn stop_recording_windows() {
+    PROFILER.with(|p| p.borrow_mut().window_recorder = None);
+}
+
+/// Returns the statistics of the windows that have been completed since
+/// calling [`record_windows`](fn.record_windows.html), starting with the
+/// oldest one. The window that is in progress is not included.
+pub fn windows() -> Vec<ProfileSnapshot> {
+    PROFILER.with(|p| match p.borrow().window_recorder.as_ref() {
+        Some(recorder) => recorder.windows.iter().cloned().collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Exclude the first `num_calls` calls of every scope from the statistics.
+///
+/// This is useful for keeping one-time startup costs, such as shader
+/// compilation or cold caches, from polluting steady-state averages. The
+/// calls are counted separately for every node in the scope tree, starting
+/// from zero after [`reset`](fn.reset.html). Note that excluding calls of a
+/// scope, but not of its children, can make the percentages of the children
+/// inaccurate.
+///
+/// The default is zero. Per-scope settings can be made with
+/// [`set_scope_warmup`](fn.set_scope_warmup.html).
+pub fn set_warmup(num_calls: usize) {
+    PROFILER.with(|p| p.borrow_mut().warmup = num_calls);
+}
+
+/// Exclude the first `num_calls` calls of scopes called `name` from the
+/// statistics, overriding the global setting of
+/// [`set_warmup`](fn.set_warmup.html).
+pub fn set_scope_warmup(name: &'static str, num_calls: usize) {
+    PROFILER.with(|p| p.borrow_mut().scope_warmups.insert(name, num_calls));
+}
+
+/// Write the report to stderr when the returned guard is dropped.
+///
+/// This is meant to be called at the start of `main`, so that the final
+/// report is written when `main` returns. Since the guard is also dropped
+/// when unwinding, a program that panics still leaves behind its profile.
+///
+/// # Example
+///
+/// ```
+/// let _print_on_exit = coarse_prof::print_on_exit();
+///
+/// // ... run the program ...
+/// ```
+#[must_use = "the report is written when the guard is dropped"]
+pub fn print_on_exit() -> PrintOnExit {
+    print_on_exit_to(io::stderr())
+}
+
+/// Write the report to `sink` when the returned guard is dropped.
+///
+/// See [`print_on_exit`](fn.print_on_exit.html) for details.
+#[must_use = "the report is written when the guard is dropped"]
+pub fn print_on_exit_to<W: io::Write + 'static>(sink: W) -> PrintOnExit {
+    PrintOnExit {
+        sink: Box::new(sink),
+    }
+}
+
+/// Install a panic hook that writes the report to stderr.
+///
+/// The report is written when a thread panics, before its stack is unwound.
+/// Scopes that are active at that point are marked in the report, and the
+/// time spent in them so far is included in their statistics. The previously
+/// installed panic hook is called before writing the report.
+///
+/// Note that the report covers only the profiler of the thread that panics.
+pub fn install_panic_hook() {
+    let prev_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        prev_hook(info);
+
+        // The panic may have occurred while the profiler was borrowed, in
+        // which case we can not do anything.
+        let _ = PROFILER.try_with(|p| {
+            if let Ok(p) = p.try_borrow() {
+                use std::io::Write;
+
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "coarse_prof report at panic:");
+                let options = p.report_options.clone().show_active(true);
+                let _ = p.write_with(&mut stderr, &options);
+            }
+        });
+    }));
+}
+
+/// Manually enter a scope.
+///
+/// The returned instance of [`Guard`](struct.Guard.html) should be dropped
+/// when leaving the scope.
+///
+/// Usually, it is more convenient to use the macro
+/// [`profile`](macro.profile.html) for including a scope in profiling, but in
+/// some special cases explicit entering/leaving can make sense.
+pub fn enter(name: &'static str) -> Guard {
+    enter_with(|p| p.enter(name))
+}
+
+/// Manually enter a scope whose name is only known at runtime.
+///
+/// This behaves like [`enter`](fn.enter.html). The name is copied and kept
+/// for the lifetime of the program when a scope is first added for it, so the
+/// number of distinct names should be bounded, e.g. by the number of entity
+/// types rather than the number of entities. Entering a scope that is already
+/// in the tree does not copy the name, and neither do names that are folded
+/// into `<other>` by
+/// [`set_max_dynamic_children`](fn.set_max_dynamic_children.html).
+///
+/// # Example
+///
+/// ```
+/// for system in &["physics", "ai", "audio"] {
+///     let _guard = coarse_prof::enter_owned(format!("systems/{}", system));
+///     // ... run the system ...
+/// }
+/// ```
+pub fn enter_owned(name: String) -> Guard {
+    enter_with(|p| p.enter_owned(name))
+}
+
+/// Manually enter a scope with an interned name.
+///
+/// This behaves like [`enter`](fn.enter.html), but avoids copying or
+/// interning names that are only known at runtime on every call, see
+/// [`ScopeName`](struct.ScopeName.html).
+pub fn enter_name(name: ScopeName) -> Guard {
+    enter_with(|p| p.enter_name(name))
+}
+
+/// Enter a scope on behalf of the macro invocation at `callsite`.
+///
+/// This is what [`profile`](macro.profile.html) expands to. It is faster than
+/// [`enter`](fn.enter.html), since the scope that the invocation entered last
+/// is cached. See [`Callsite`](struct.Callsite.html) for using this in custom
+/// macros.
+pub fn enter_callsite(callsite: &Callsite, name: &'static str) -> Guard {
+    enter_with(|p| p.enter_callsite(callsite, name))
+}
+
+/// Enter a scope with `enter` in the thread-local profiler, returning a
+/// disabled guard if the profiler is already in use.
+fn enter_with(enter: impl FnOnce(&mut Profiler) -> Guard) -> Guard {
+    PROFILER.with(|p| {
+        let borrow = || {
+            let profiler = p.try_borrow_mut();
+            if profiler.is_err() {
+                error::misuse(Error::ProfilerBorrowed);
+            }
+            profiler.ok()
+        };
+
+        let mut profiler = match borrow() {
+            Some(profiler) => profiler,
+            None => return Guard::disabled(),
+        };
+
+        // Reporters are invoked after releasing the profiler, so that they
+        // can use it themselves.
+        if let Some(reports) = profiler.take_due_reports() {
+            drop(profiler);
+            reports.dispatch(p);
+            profiler = match borrow() {
+                Some(profiler) => profiler,
+                None => return Guard::disabled(),
+            };
+        }
+
+        let mut guard = enter(&mut profiler);
+        drop(profiler);
+        guard.profiler = Some(p.clone());
+        guard
+    })
+}
+
+/// Identifies an invocation of a macro such as `profile!` in the source code.
+///
+/// The profiler uses this to cache which scope the invocation entered last,
+/// so that looking up the scope by name can be skipped in the steady state.
+/// Custom macros can get the same benefit by declaring a `static` callsite
+/// and entering scopes with [`enter_callsite`](fn.enter_callsite.html):
+///
+/// ```
+/// macro_rules! profile_system {
+///     ($name:literal) => {
+///         let _guard = {
+///             static CALLSITE: coarse_prof::Callsite = coarse_prof::Callsite::new();
+///             coarse_prof::enter_callsite(&CALLSITE, concat!("systems/", $name))
+///         };
+///     };
+/// }
+///
+/// profile_system!("physics");
+/// ```
+pub struct Callsite {
+    /// Globally unique id of the callsite, plus one. Zero means that the id
+    /// has not been assigned yet.
+    id: AtomicUsize,
+}
+
+impl Callsite {
+    /// Create a callsite. This should be stored in a `static`, so that it is
+    /// the same for every execution of the invocation.
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Callsite {
+        Callsite {
+            id: AtomicUsize::new(0),
+        }
+    }
+
+    fn id(&self) -> usize {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        match self.id.load(Ordering::Relaxed) {
+            0 => {
+                let new_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+                // Another thread may have assigned an id in the meantime.
+                match self
+                    .id
+                    .compare_exchange(0, new_id, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => new_id - 1,
+                    Err(id) => id - 1,
+                }
+            }
+            id => id - 1,
+        }
+    }
+}
+
+/// Use this macro to add the current scope to profiling. In effect, the time
+/// taken from entering to leaving the scope will be measured.
+///
+/// Internally, the scope is inserted in the scope tree of the global
+/// thread-local profiler.
+///
+/// If the name is a path of names separated by `/`, such as
+/// `"render/shadows/cascade0"`, the whole chain of nested scopes is entered
+/// at once, and left again together at the end of the current scope. Paths
+/// that share a prefix, such as `"render/shadows/cascade1"`, count only one
+/// call of the shared scopes per call of the current scope.
+///
+/// # Example
+///
+/// The following example will profile the scope `"foo"`, which has the scope
+/// `"bar"` as a child.
+///
+/// ```
+/// use coarse_prof::profile;
+///
+/// {
+///     profile!("foo");
+///
+///     {
+///         profile!("bar");
+///         // ... do something ...
+///     }
+///
+///     // ... do some more ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! profile {
+    ($name:expr) => {
+        let _guard = {
+            static CALLSITE: $crate::Callsite = $crate::Callsite::new();
+            $crate::enter_callsite(&CALLSITE, $name)
+        };
+    };
+}
+
+/// Like [`profile`](macro.profile.html), but prefixes the scope name with the
+/// path of the current module.
+///
+/// This keeps scopes of different crates, which may use the same names, apart
+/// in the scope tree. Instead of the module path, a custom prefix can be
+/// given as the first argument. Both the prefix and the name need to be string
+/// literals.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::profile_scoped;
+///
+/// mod physics {
+///     pub fn step() {
+///         // Recorded as e.g. `my_crate::physics::step`.
+///         coarse_prof::profile_scoped!("step");
+///     }
+/// }
+///
+/// {
+///     // Recorded as `physics::integrate`.
+///     profile_scoped!("physics", "integrate");
+///     physics::step();
+/// }
+/// ```
+#[macro_export]
+macro_rules! profile_scoped {
+    ($name:literal) => {
+        $crate::profile!(concat!(module_path!(), "::", $name));
+    };
+    ($prefix:literal, $name:literal) => {
+        $crate::profile!(concat!($prefix, "::", $name));
+    };
+}
+
+/// Like [`profile`](macro.profile.html), but marks the scope as waiting
+/// instead of doing work, e.g. for vsync, a lock or I/O.
+///
+/// See [`Guard::wait`](struct.Guard.html#method.wait) for how waiting scopes
+/// are reported.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::{profile, profile_wait};
+///
+/// fn present() {
+///     profile_wait!("vsync");
+///     // ... wait for the display ...
+/// }
+///
+/// profile!("frame");
+/// present();
+/// ```
+#[macro_export]
+macro_rules! profile_wait {
+    ($name:expr) => {
+        let _guard = {
+            static CALLSITE: $crate::Callsite = $crate::Callsite::new();
+            $crate::enter_callsite(&CALLSITE, $name).wait()
+        };
+    };
+}
+
+/// Profile an expression, evaluating to a tuple of the expression's value and
+/// the time it took.
+///
+/// The expression is recorded as a scope in the scope tree of the global
+/// thread-local profiler, just like with the
+/// [`profile`](macro.profile.html) macro.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::measure;
+///
+/// let (sum, duration) = measure!("sum", (0..1000).sum::<u64>());
+///
+/// assert_eq!(sum, 499500);
+/// println!("Summing took {}us", duration.as_micros());
+/// ```
+#[macro_export]
+macro_rules! measure {
+    ($name:expr, $expr:expr) => {{
+        let guard = {
+            static CALLSITE: $crate::Callsite = $crate::Callsite::new();
+            $crate::enter_callsite(&CALLSITE, $name)
+        };
+        let value = $expr;
+        (value, guard.end())
+    }};
+}
+
+/// Index of a scope in the arena of a `Profiler`.
+type ScopeId = usize;
+
+/// Internal representation of scopes as a tree.
+///
+/// Scopes are stored in an arena, i.e. `Profiler::scopes`, and refer to each
+/// other by their index in the arena.
+struct Scope {
+    /// Name of the scope.
+    name: &'static str,
+
+    /// Parent scope in the tree. Root scopes have no parent.
+    pred: Option<ScopeId>,
+
+    /// Was the scope added for a name that is only known at runtime?
+    dynamic: bool,
+
+    /// Start and number of calls of the current window for measuring the
+    /// call rate, and has a warning about the rate been emitted?
+    rate_window: Option<(Instant, usize)>,
+    warned_call_rate: bool,
+
+    /// Id of the scope's path, which stays the same across resets.
+    stable_id: u64,
+
+    /// Child scopes in the tree, in the order in which they were added.
+    succs: Vec<ScopeId>,
+
+    /// Child scopes by name, for quickly finding existing children.
+    succs_by_name: HashMap<&'static str, ScopeId>,
+
+    /// How often has this scope been visited?
+    num_calls: usize,
+
+    /// How often has this scope been entered again while it was the current
+    /// scope?
+    recursions: usize,
+
+    /// Has this scope been marked as waiting with `Guard::wait`?
+    wait: bool,
+
+    /// How many calls have been excluded from the statistics for warm-up?
+    num_warmup_calls: usize,
+
+    /// In total, how many nanoseconds have been spent in this scope?
+    duration_sum_ns: u64,
+
+    /// Minimal number of nanoseconds spent in this scope.
+    duration_min_ns: u64,
+
+    /// Maximal number of nanoseconds spent in this scope.
+    duration_max_ns: u64,
+
+    /// If the scope is currently being visited, when did we enter it?
+    active_since: Option<Instant>,
+
+    /// How often has this scope been entered? Identifies the current visit
+    /// for the children that are entered as part of a path.
+    num_entries: u64,
+
+    /// If this scope has last been entered as an intermediate scope of a
+    /// path, the `num_entries` of its parent at that point.
+    path_entry: Option<u64>,
+
+    /// Is the current visit a continuation of the previous call, since the
+    /// scope has been entered as part of a path again during the same visit
+    /// of its parent? If so, leaving it does not count another call.
+    continued: bool,
+
+    /// Are this scope and all of its ancestors enabled?
+    enabled: bool,
+
+    /// Ids of the subscriptions whose pattern matches this scope.
+    subscriptions: Vec<u64>,
+
+    /// Running mean of the durations in nanoseconds, for computing the
+    /// variance with Welford's algorithm.
+    duration_mean_ns: f64,
+
+    /// Sum of squared differences from the mean, in nanoseconds squared.
+    duration_m2: f64,
+
+    /// In total, how many nanoseconds of CPU time has the thread consumed in
+    /// this scope? This is `None` until CPU time has been measured.
+    cpu_time_sum_ns: Option<u64>,
+
+    /// Attributes attached to calls of this scope.
+    attrs: Vec<AttrStats>,
+
+    /// For root scopes, how many calls fell into each bucket of
+    /// `FRAME_TIME_BUCKETS`.
+    frame_histogram: [usize; NUM_FRAME_TIME_BUCKETS + 1],
+
+    /// For root scopes, the durations of the recent calls in nanoseconds.
+    /// Up to `NUM_RECENT_FRAMES` calls are kept for computing the lows if
+    /// this is the frame scope, and `NUM_RECENT_DURATIONS` otherwise.
+    recent_ns: VecDeque<u64>,
+
+    /// Whether the last call has been recorded as a call of the frame scope.
+    is_frame_scope: bool,
+
+    /// For root scopes, when the scope has been entered within the last
+    /// `FPS_WINDOW`.
+    recent_entries: VecDeque<Instant>,
+
+    /// For root scopes, when was the scope left last?
+    last_left: Option<Instant>,
+
+    /// Exponentially decayed sum of the durations, if a half-life has been
+    /// set.
+    decayed: Option<Decayed>,
+
+    /// Estimator of the median duration, if it is estimated.
+    median: Option<P2Quantile>,
+
+    /// For root scopes, the total number of nanoseconds between leaving the
+    /// scope and entering it again.
+    idle_sum_ns: u64,
+
+    /// Number of calls and total duration in nanoseconds in the current
+    /// window of anomaly detection.
+    window_calls: usize,
+    window_sum_ns: u64,
+
+    /// Start and end of the last calls in nanoseconds since the profiler was
+    /// created or reset, if the timeline is being recorded.
+    timeline: VecDeque<(u64, u64)>,
+
+    /// Statistics since the last call of `to_string_delta`.
+    delta: Delta,
+
+    /// Statistics in the current window, see `record_windows`.
+    window: Delta,
+
+    /// Histogram that the durations are published to, once it has been
+    /// registered.
+    #[cfg(feature = "metrics")]
+    metrics_histogram: Option<metrics::Histogram>,
+
+    /// Backtrace of the call with the maximal duration.
+    #[cfg(feature = "backtrace")]
+    max_backtrace: Option<std::sync::Arc<std::backtrace::Backtrace>>,
+}
+
+/// Durations of a scope, weighted by how recent they are.
+struct Decayed {
+    /// Sum of the weighted durations.
+    sum_ns: f64,
+
+    /// Sum of the weights.
+    weight: f64,
+
+    /// When have the weights last been decayed?
+    last: Instant,
+}
+
+/// Statistics of a scope that are accumulated since the last delta report or
+/// the start of the current window.
+struct Delta {
+    num_calls: usize,
+    duration_sum_ns: u64,
+    duration_min_ns: u64,
+    duration_max_ns: u64,
+}
+
+impl Delta {
+    fn new() -> Delta {
+        Delta {
+            num_calls: 0,
+            duration_sum_ns: 0,
+            duration_min_ns: u64::MAX,
+            duration_max_ns: 0,
+        }
+    }
+
+    fn record(&mut self, duration_ns: u64) {
+        self.num_calls += 1;
+        self.duration_sum_ns = self.duration_sum_ns.saturating_add(duration_ns);
+        self.duration_min_ns = self.duration_min_ns.min(duration_ns);
+        self.duration_max_ns = self.duration_max_ns.max(duration_ns);
+    }
+}
+
+impl Scope {
+    fn new(name: &'static str, pred: Option<ScopeId>, stable_id: u64) -> Scope {
+        Scope {
+            name,
+            pred,
+            dynamic: false,
+            rate_window: None,
+            warned_call_rate: false,
+            stable_id,
+            succs: Vec::new(),
+            succs_by_name: HashMap::new(),
+            num_calls: 0,
+            recursions: 0,
+            wait: false,
+            num_warmup_calls: 0,
+            duration_sum_ns: 0,
+            duration_min_ns: u64::MAX,
+            duration_max_ns: 0,
+            active_since: None,
+            num_entries: 0,
+            path_entry: None,
+            continued: false,
+            enabled: true,
+            subscriptions: Vec::new(),
+            #[cfg(feature = "metrics")]
+            metrics_histogram: None,
+            #[cfg(feature = "backtrace")]
+            max_backtrace: None,
+            duration_mean_ns: 0.0,
+            duration_m2: 0.0,
+            cpu_time_sum_ns: None,
+            attrs: Vec::new(),
+            frame_histogram: [0; NUM_FRAME_TIME_BUCKETS + 1],
+            recent_ns: VecDeque::new(),
+            is_frame_scope: false,
+            recent_entries: VecDeque::new(),
+            last_left: None,
+            decayed: None,
+            median: None,
+            idle_sum_ns: 0,
+            window_calls: 0,
+            window_sum_ns: 0,
+            timeline: VecDeque::new(),
+            delta: Delta::new(),
+            window: Delta::new(),
+        }
+    }
+
+    /// Reset the statistics of this scope, keeping its place in the tree and
+    /// its settings.
+    fn reset_stats(&mut self) {
+        let fresh = Scope::new(self.name, self.pred, self.stable_id);
+
+        *self = Scope {
+            succs: std::mem::take(&mut self.succs),
+            succs_by_name: std::mem::take(&mut self.succs_by_name),
+            dynamic: self.dynamic,
+            warned_call_rate: self.warned_call_rate,
+            num_warmup_calls: self.num_warmup_calls,
+            active_since: self.active_since,
+            num_entries: self.num_entries,
+            path_entry: self.path_entry,
+            enabled: self.enabled,
+            subscriptions: std::mem::take(&mut self.subscriptions),
+            #[cfg(feature = "metrics")]
+            metrics_histogram: self.metrics_histogram.take(),
+            last_left: self.last_left,
+            ..fresh
+        };
+    }
+
+    /// Enter this scope. Returns a `Guard` instance that should be dropped
+    /// when leaving the scope.
+    fn enter(&mut self) -> Guard {
+        let now = Instant::now();
+        self.active_since = Some(now);
+        self.num_entries += 1;
+        self.path_entry = None;
+
+        Guard::enter(now)
+    }
+
+    /// Leave this scope. Called automatically by the `Guard` instance.
+    fn leave(&mut self, duration_ns: u64, cpu_time_ns: Option<u64>, warmup: usize) {
+        self.active_since = None;
+
+        if self.num_warmup_calls < warmup {
+            // Exclude this call from the statistics.
+            self.num_warmup_calls += 1;
+            return;
+        }
+
+        self.num_calls += 1;
+
+        // Overflowing would take centuries, but let's not panic or lose the
+        // accumulated data even then.
+        self.duration_sum_ns = self.duration_sum_ns.saturating_add(duration_ns);
+
+        self.duration_min_ns = self.duration_min_ns.min(duration_ns);
+        #[cfg(feature = "backtrace")]
+        if duration_ns > self.duration_max_ns || self.num_calls == 1 {
+            self.max_backtrace = Some(std::sync::Arc::new(
+                std::backtrace::Backtrace::force_capture(),
+            ));
+        }
+        self.duration_max_ns = self.duration_max_ns.max(duration_ns);
+
+        let delta = duration_ns as f64 - self.duration_mean_ns;
+        self.duration_mean_ns += delta / self.num_calls as f64;
+        self.duration_m2 += delta * (duration_ns as f64 - self.duration_mean_ns);
+
+        if let Some(cpu_time_ns) = cpu_time_ns {
+            self.cpu_time_sum_ns = Some(
+                self.cpu_time_sum_ns
+                    .unwrap_or(0)
+                    .saturating_add(cpu_time_ns),
+            );
+        }
+
+        self.delta.record(duration_ns);
+        self.window.record(duration_ns);
+    }
+
+    /// Leave this scope, adding the time to the previous call instead of
+    /// counting another one. See `continued`.
+    fn continue_call(&mut self, duration_ns: u64, cpu_time_ns: Option<u64>) {
+        self.active_since = None;
+
+        self.duration_sum_ns = self.duration_sum_ns.saturating_add(duration_ns);
+        if let Some(cpu_time_ns) = cpu_time_ns {
+            self.cpu_time_sum_ns = Some(
+                self.cpu_time_sum_ns
+                    .unwrap_or(0)
+                    .saturating_add(cpu_time_ns),
+            );
+        }
+
+        self.delta.duration_sum_ns = self.delta.duration_sum_ns.saturating_add(duration_ns);
+        self.window.duration_sum_ns = self.window.duration_sum_ns.saturating_add(duration_ns);
+    }
+
+    /// Add a call to the decayed averages, decaying the weights of previous
+    /// calls by the time that has passed since the last one.
+    fn record_decayed(&mut self, duration_ns: u64, half_life: Duration) {
+        let now = Instant::now();
+        let decayed = self.decayed.get_or_insert(Decayed {
+            sum_ns: 0.0,
+            weight: 0.0,
+            last: now,
+        });
+
+        let factor = if half_life > Duration::new(0, 0) {
+            let elapsed = now.duration_since(decayed.last);
+            0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+        } else {
+            0.0
+        };
+        decayed.sum_ns = decayed.sum_ns * factor + duration_ns as f64;
+        decayed.weight = decayed.weight * factor + 1.0;
+        decayed.last = now;
+    }
+
+    /// Returns the statistics of this scope and its children, which are
+    /// looked up in `scopes`.
+    ///
+    /// If `now` is given and the scope is currently active, the active call
+    /// and the time spent in it so far are included.
+    fn stats(&self, scopes: &[Scope], now: Option<Instant>) -> ScopeStats {
+        let active_for = match (now, self.active_since) {
+            (Some(now), Some(active_since)) => Some(now.duration_since(active_since)),
+            _ => None,
+        };
+
+        // The active call counts as a call that ends now.
+        let (mut duration_min, mut duration_max) = (
+            Duration::from_nanos(self.duration_min_ns),
+            Duration::from_nanos(self.duration_max_ns),
+        );
+        if let Some(active_for) = active_for {
+            duration_min = duration_min.min(active_for);
+            duration_max = duration_max.max(active_for);
+        }
+
+        ScopeStats {
+            name: self.name.to_string(),
+            num_calls: self.num_calls + active_for.map_or(0, |_| 1),
+            duration_sum: Duration::from_nanos(self.duration_sum_ns)
+                .saturating_add(active_for.unwrap_or_default()),
+            duration_min,
+            duration_max,
+            active_for,
+            duration_variance: if self.num_calls >= 2 {
+                Some(self.duration_m2 / (self.num_calls - 1) as f64 * 1e-18)
+            } else {
+                None
+            },
+            cpu_time_sum: self.cpu_time_sum_ns.map(Duration::from_nanos),
+            idle_sum: if self.pred.is_none() {
+                Some(Duration::from_nanos(self.idle_sum_ns))
+            } else {
+                None
+            },
+            frame_histogram: if self.pred.is_none() {
+                Some(self.frame_histogram)
+            } else {
+                None
+            },
+            frame_lows: if self.is_frame_scope && !self.recent_ns.is_empty() {
+                Some(FrameLows::new(self.recent_ns.iter().copied()))
+            } else {
+                None
+            },
+            decayed_mean: self
+                .decayed
+                .as_ref()
+                .map(|decayed| Duration::from_nanos((decayed.sum_ns / decayed.weight) as u64)),
+            id: Some(self.stable_id),
+            #[cfg(feature = "backtrace")]
+            max_backtrace: self.max_backtrace.clone(),
+            #[cfg(not(feature = "backtrace"))]
+            max_backtrace: None,
+            recursions: self.recursions,
+            recent_durations: self
+                .recent_ns
+                .iter()
+                .skip(self.recent_ns.len().saturating_sub(NUM_RECENT_DURATIONS))
+                .map(|&duration_ns| Duration::from_nanos(duration_ns))
+                .collect(),
+            wait: self.wait,
+            duration_median: self
+                .median
+                .as_ref()
+                .and_then(P2Quantile::estimate)
+                .map(|median_ns| Duration::from_nanos(median_ns as u64)),
+            attrs: self.attrs.clone(),
+            children: self
+                .succs
+                .iter()
+                .map(|&succ| scopes[succ].stats(scopes, now))
+                .collect(),
+        }
+    }
+
+    /// Returns the statistics of this scope and its children that have been
+    /// accumulated in the `Delta` selected by `delta`, or `None` if none of
+    /// them has been left since it has been cleared.
+    fn delta_stats(&self, scopes: &[Scope], delta: fn(&Scope) -> &Delta) -> Option<ScopeStats> {
+        let children: Vec<_> = self
+            .succs
+            .iter()
+            .filter_map(|&succ| scopes[succ].delta_stats(scopes, delta))
+            .collect();
+
+        let stats = delta(self);
+        if stats.num_calls == 0 && children.is_empty() {
+            return None;
+        }
+
+        Some(ScopeStats {
+            name: self.name.to_string(),
+            num_calls: stats.num_calls,
+            duration_sum: Duration::from_nanos(stats.duration_sum_ns),
+            duration_min: Duration::from_nanos(stats.duration_min_ns),
+            duration_max: Duration::from_nanos(stats.duration_max_ns),
+            active_for: None,
+            duration_variance: None,
+            cpu_time_sum: None,
+            idle_sum: None,
+            frame_histogram: None,
+            frame_lows: None,
+            decayed_mean: None,
+            duration_median: None,
+            max_backtrace: None,
+            recursions: 0,
+            recent_durations: Vec::new(),
+            wait: false,
+            id: Some(self.stable_id),
+            attrs: Vec::new(),
+            children,
+        })
+    }
+}
+
+/// A guard that is created when entering a scope and dropped when leaving it.
+///
+/// Guards may be stored and dropped in any order. Each guard leaves the scope
+/// that it has entered, even if scopes that have been entered later are
+/// still active.
+///
+/// Guards leave their scope in the profiler of the thread that created them,
+/// so they can not be sent to another thread:
+///
+/// ```compile_fail
+/// let guard = coarse_prof::enter("frame");
+/// std::thread::spawn(move || drop(guard));
+/// ```
+pub struct Guard {
+    enter_time: Instant,
+
+    /// The profiler that the scope belongs to. If this is `None`, the
+    /// thread-local profiler is used.
+    profiler: Option<Rc<RefCell<Profiler>>>,
+
+    /// Has the scope already been left through `Guard::end`?
+    left: bool,
+
+    /// The innermost scope that has been entered, and the generation of the
+    /// profiler at that time. This is `None` for guards that do not leave
+    /// any scope.
+    scope: Option<(ScopeId, u64)>,
+
+    /// How many scopes to leave. This is larger than one if a path of
+    /// nested scopes has been entered at once.
+    levels: usize,
+
+    /// Has the scope been muted with `set_scope_enabled`?
+    muted: bool,
+
+    /// The section that has been entered last with `Guard::lap`, which is
+    /// left before the scope of this guard.
+    lap: Option<Box<Guard>>,
+
+    /// CPU time of the thread when entering the scope, if it is measured.
+    cpu_time_start_ns: Option<u64>,
+
+    /// Values of the hardware counters when entering the scope, if they are
+    /// measured.
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf_start: Option<[u64; perf::MAX_COUNTERS]>,
+
+    /// Resource usage when entering the scope, if it is measured.
+    #[cfg(all(feature = "rusage", unix))]
+    rusage_start: Option<[u64; rusage::NUM_VALUES]>,
+
+    /// Number of allocations and allocated bytes of the thread when entering
+    /// the scope, if `CoarseProfAlloc` is used.
+    #[cfg(feature = "alloc")]
+    alloc_start: Option<(u64, u64)>,
+
+    /// Scope that is forwarded to `puffin`, if `puffin` profiling is on.
+    #[cfg(feature = "puffin")]
+    puffin_scope: Option<puffin::ProfilerScope>,
+
+    /// Zone that is emitted to Tracy, if a Tracy client is running.
+    #[cfg(feature = "tracy")]
+    tracy_span: Option<tracy_client::Span>,
+
+    /// The guard leaves the current scope of the thread that created it, so
+    /// it must not be sent to another thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Guard {
+    fn enter(enter_time: Instant) -> Self {
+        Self {
+            enter_time,
+            profiler: None,
+            left: false,
+            scope: None,
+            levels: 1,
+            muted: false,
+            lap: None,
+            cpu_time_start_ns: None,
+            #[cfg(all(feature = "perf", target_os = "linux"))]
+            perf_start: None,
+            #[cfg(all(feature = "rusage", unix))]
+            rusage_start: None,
+            #[cfg(feature = "alloc")]
+            alloc_start: None,
+            #[cfg(feature = "puffin")]
+            puffin_scope: None,
+            #[cfg(feature = "tracy")]
+            tracy_span: None,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns a guard that does not leave any scope, for when profiling is
+    /// off.
+    fn disabled() -> Self {
+        let mut guard = Self::enter(Instant::now());
+        guard.levels = 0;
+        guard
+    }
+
+    /// Leave the scope now, instead of when the guard goes out of scope.
+    /// Returns the duration that has been recorded for the scope.
+    ///
+    /// This allows closing a scope early without introducing an artificial
+    /// block, and using the measured duration directly.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let guard = coarse_prof::enter("save_game");
+    /// // ... save the game ...
+    /// let duration = guard.end();
+    ///
+    /// println!("Last save took {}ms", duration.as_millis());
+    /// ```
+    pub fn end(mut self) -> Duration {
+        self.leave()
+    }
+
+    /// Returns the time that has passed since entering the scope.
+    ///
+    /// Like [`end`](#method.end), this returns zero for scopes that have been
+    /// muted with [`set_scope_enabled`](fn.set_scope_enabled.html), since
+    /// they are not measured at all.
+    pub fn elapsed(&self) -> Duration {
+        if self.muted {
+            return Duration::new(0, 0);
+        }
+
+        self.enter_time.elapsed()
+    }
+
+    /// Mark the scope as waiting, e.g. for vsync, a lock or I/O, instead of
+    /// doing work. This is what [`profile_wait`](macro.profile_wait.html)
+    /// does.
+    ///
+    /// Waiting scopes are timed like any other scope, but flagged with
+    /// `[wait]` in the report. With
+    /// [`ReportOptions::exclude_wait`](struct.ReportOptions.html#method.exclude_wait),
+    /// their time is excluded from their ancestors, so that the report only
+    /// shows the time spent working. The mark applies to all calls of the
+    /// scope until the profiler is reset.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let _vsync = coarse_prof::enter("vsync").wait();
+    /// ```
+    pub fn wait(self) -> Guard {
+        if let Some((id, generation)) = self.scope {
+            self.with_profiler(|p| {
+                if generation == p.generation {
+                    p.scopes[id].wait = true;
+                }
+            });
+        }
+        self
+    }
+
+    /// Leave the previous section of the scope, if any, and enter a section
+    /// called `name`, which is left with the next call of `lap` or when the
+    /// scope is left.
+    ///
+    /// This splits a long, linear function into sequential phases, without
+    /// introducing a block for each of them. Sections are entered below the
+    /// scope of this guard, so it must be the innermost active scope. If
+    /// another scope that has been entered since is still active, this is
+    /// reported as [`Error::LapOutsideScope`](enum.Error.html) and no section
+    /// is entered.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut guard = coarse_prof::enter("load_level");
+    ///
+    /// guard.lap("parse");
+    /// // ... parse the level ...
+    ///
+    /// guard.lap("spawn");
+    /// // ... spawn the entities ...
+    ///
+    /// drop(guard);
+    ///
+    /// let snapshot = coarse_prof::snapshot();
+    /// assert_eq!(snapshot.roots[0].children.len(), 2);
+    /// ```
+    pub fn lap(&mut self, name: &'static str) {
+        drop(self.lap.take());
+
+        if self.left || self.levels == 0 {
+            return;
+        }
+
+        let scope = self.scope;
+        let lap = self.with_profiler(|p| match scope {
+            Some((id, generation)) if generation == p.generation => {
+                if p.current == Some(id) {
+                    Some(p.enter(name))
+                } else {
+                    error::misuse(Error::LapOutsideScope);
+                    None
+                }
+            }
+            // The scope is no longer in the tree after a reset.
+            _ => None,
+        });
+
+        if let Some(mut lap) = lap.flatten() {
+            lap.profiler = self.profiler.clone();
+            self.lap = Some(Box::new(lap));
+        }
+    }
+
+    fn leave(&mut self) -> Duration {
+        drop(self.lap.take());
+
+        if self.muted {
+            if !self.left {
+                self.left = true;
+                self.with_profiler(|p| p.muted_depth -= 1);
+            }
+
+            // Muted scopes are not measured at all.
+            return Duration::new(0, 0);
+        }
+
+        let left_time = Instant::now();
+        let duration = left_time.duration_since(self.enter_time);
+
+        // Disabled guards have nothing to record.
+        if !self.left && self.levels > 0 {
+            self.left = true;
+
+            let cpu_time_ns = self
+                .cpu_time_start_ns
+                .and_then(|start| clock::thread_cpu_time_ns().map(|end| end.saturating_sub(start)));
+            let scope = self.scope;
+            let levels = self.levels;
+            let enter_time = self.enter_time;
+            #[cfg(all(feature = "perf", target_os = "linux"))]
+            let perf_start = self.perf_start;
+            #[cfg(all(feature = "rusage", unix))]
+            let rusage_start = self.rusage_start;
+            #[cfg(feature = "alloc")]
+            let alloc_start = self.alloc_start.zip(alloc::sample());
+            let dispatch = self.with_profiler(|p| {
+                // The counters belong to the scope of this guard, which is not
+                // necessarily the current scope if guards are dropped out of
+                // order.
+                #[cfg(any(
+                    all(feature = "perf", target_os = "linux"),
+                    all(feature = "rusage", unix),
+                    feature = "alloc"
+                ))]
+                if let Some((id, _)) = scope.filter(|&(_, generation)| generation == p.generation) {
+                    #[cfg(all(feature = "perf", target_os = "linux"))]
+                    if let Some(perf_start) = perf_start {
+                        p.record_perf_counters(id, &perf_start);
+                    }
+                    #[cfg(all(feature = "rusage", unix))]
+                    if let Some(rusage_start) = rusage_start {
+                        let rusage_end = rusage::sample();
+                        for (i, name) in rusage::NAMES.iter().enumerate() {
+                            let delta = rusage_end[i].saturating_sub(rusage_start[i]);
+                            p.record_attr(id, name, delta as f64);
+                        }
+                    }
+                    #[cfg(feature = "alloc")]
+                    if let Some((start, end)) = alloc_start {
+                        p.record_attr(id, "allocs", end.0.saturating_sub(start.0) as f64);
+                        p.record_attr(id, "alloc_bytes", end.1.saturating_sub(start.1) as f64);
+                    }
+                }
+
+                // If the statistics have been reset since entering the scope,
+                // only the time after the reset is recorded. The CPU time can
+                // not be split, so it is not recorded at all.
+                let (duration, cpu_time_ns) = if enter_time < p.start_time {
+                    (left_time.saturating_duration_since(p.start_time), None)
+                } else {
+                    (duration, cpu_time_ns)
+                };
+
+                match scope {
+                    Some((id, generation)) if generation == p.generation => {
+                        p.leave_scope(id, levels, duration, cpu_time_ns)
+                    }
+                    _ => {
+                        // The tree has been reset since entering the scope, so
+                        // we can only assume that guards are dropped in order.
+                        for _ in 0..levels {
+                            p.leave_split(duration);
+                        }
+                    }
+                }
+
+                if p.measure_overhead {
+                    p.add_overhead(Some(left_time));
+                }
+                p.events.take()
+            });
+
+            if let Some((events, mut callbacks)) = dispatch.flatten() {
+                callbacks.dispatch(events);
+                self.with_profiler(|p| p.events.callbacks.restore(callbacks));
+            }
+        }
+
+        duration
+    }
+
+    /// Call `f` with the profiler of the guard. Returns `None` if the
+    /// profiler is already in use.
+    fn with_profiler<R>(&self, f: impl FnOnce(&mut Profiler) -> R) -> Option<R> {
+        let with = |p: &RefCell<Profiler>| match p.try_borrow_mut() {
+            Ok(mut profiler) => Some(f(&mut profiler)),
+            Err(_) => {
+                error::misuse(Error::ProfilerBorrowed);
+                None
+            }
+        };
+
+        match self.profiler.as_ref() {
+            Some(profiler) => with(profiler),
+            None => PROFILER.with(|p| with(p)),
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+/// A guard that writes the report when dropped.
+///
+/// Created by [`print_on_exit`](fn.print_on_exit.html) or
+/// [`print_on_exit_to`](fn.print_on_exit_to.html).
+pub struct PrintOnExit {
+    sink: Box<dyn io::Write>,
+}
+
+impl Drop for PrintOnExit {
+    fn drop(&mut self) {
+        // We may be unwinding here, so make sure not to panic again if the
+        // profiler is unavailable.
+        let result = PROFILER.try_with(|p| match p.try_borrow() {
+            Ok(p) => p.write(&mut self.sink),
+            Err(_) => Ok(()),
+        });
+
+        if let Ok(Err(err)) = result {
+            log::warn!("coarse_prof: failed to write report on exit: {}", err);
+        }
+    }
+}
+
+/// Configuration of periodic reporting.
+struct Periodic {
+    /// When to report.
+    schedule: Schedule,
+
+    /// Should we reset profiling information after reporting?
+    reset: bool,
+
+    /// When did we last write a report?
+    last_time: Instant,
+
+    /// How many frames had been completed when we last wrote a report?
+    last_frame: u64,
+
+    /// Where to write the reports to.
+    sink: PeriodicSink,
+}
+
+/// Where periodic reports are written to.
+enum PeriodicSink {
+    /// The report is written to a writer, formatted with the options of the
+    /// profiler.
+    Writer(Box<dyn io::Write>),
+
+    /// A snapshot is passed to a reporter. This is `None` while the reporter
+    /// is being invoked.
+    Reporter(Option<Box<dyn Reporter>>),
+}
+
+/// A reporter that has been taken out of the profiler for invoking it with a
+/// snapshot.
+type DueReport = (Box<dyn Reporter>, ProfileSnapshot);
+
+/// Snapshots for the reporters whose schedule is due, which are invoked after
+/// releasing the profiler. Created by `Profiler::take_due_reports`.
+struct DueReports {
+    /// The report for the sink of `Profiler::periodic`.
+    periodic: Option<DueReport>,
+
+    /// The reports for `Profiler::reporters`, in the order of their empty
+    /// slots.
+    reporters: Vec<DueReport>,
+}
+
+impl DueReports {
+    /// Invoke the reporters and put them back into `profiler`, unless they
+    /// have been removed in the meantime.
+    fn dispatch(mut self, profiler: &RefCell<Profiler>) {
+        for (reporter, snapshot) in self.periodic.iter_mut().chain(self.reporters.iter_mut()) {
+            reporter.report(snapshot);
+        }
+
+        match profiler.try_borrow_mut() {
+            Ok(mut profiler) => profiler.restore_reporters(self),
+            Err(_) => error::misuse(Error::ProfilerBorrowed),
+        }
+    }
+}
+
+impl Periodic {
+    fn new(schedule: Schedule, reset: bool, sink: PeriodicSink, num_frames: u64) -> Periodic {
+        Periodic {
+            schedule,
+            reset,
+            last_time: Instant::now(),
+            last_frame: num_frames,
+            sink,
+        }
+    }
+
+    /// Is the next report due, given that `num_frames` frames have been
+    /// completed so far?
+    fn is_due(&self, num_frames: u64) -> bool {
+        match self.schedule {
+            Schedule::Interval(interval) => self.last_time.elapsed() >= interval,
+            Schedule::Frames(frames) => num_frames.saturating_sub(self.last_frame) >= frames,
+        }
+    }
+}
+
+/// Configuration of per-frame recording.
+struct FrameRecorder {
+    /// Where to write the frames to.
+    sink: Box<dyn io::Write>,
+
+    /// Number of frames that have been written so far.
+    num_frames: u64,
+
+    /// Total duration of every scope when the previous frame ended, indexed
+    /// by scope id.
+    last_sums_ns: Vec<u64>,
+}
+
+impl FrameRecorder {
+    /// Write the durations of the scopes below `root` in the frame that has
+    /// just ended.
+    fn record(&mut self, scopes: &[Scope], root: ScopeId) -> io::Result<()> {
+        use std::io::Write;
+
+        self.last_sums_ns.resize(scopes.len(), 0);
+        let frame = self.num_frames;
+        self.num_frames += 1;
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        // Assemble the line first, so that we never write partial lines.
+        let mut line = Vec::new();
+        write!(line, "{{\"frame\":{},\"timestamp_secs\":", frame)?;
+        json::write_f64(&mut line, timestamp.as_secs_f64())?;
+        write!(line, ",\"scopes\":{{")?;
+
+        let mut stack = vec![(root, scopes[root].name.to_string())];
+        let mut first = true;
+        while let Some((id, path)) = stack.pop() {
+            let scope = &scopes[id];
+            let delta_ns = scope.duration_sum_ns.saturating_sub(self.last_sums_ns[id]);
+            self.last_sums_ns[id] = scope.duration_sum_ns;
+
+            if delta_ns == 0 {
+                // The scope has not been visited in this frame, so neither
+                // have its children.
+                continue;
+            }
+
+            if !first {
+                write!(line, ",")?;
+            }
+            first = false;
+            json::write_str(&mut line, &path)?;
+            write!(line, ":")?;
+            json::write_f64(&mut line, Duration::from_nanos(delta_ns).as_secs_f64())?;
+
+            for &succ in scope.succs.iter().rev() {
+                stack.push((succ, format!("{}/{}", path, scopes[succ].name)));
+            }
+        }
+
+        writeln!(line, "}}}}")?;
+        self.sink.write_all(&line)?;
+        self.sink.flush()
+    }
+}
+
+/// A callback that has been subscribed to the statistics of scopes.
+struct Subscription {
+    id: u64,
+    pattern: String,
+
+    /// After how many calls of a scope are the statistics passed on?
+    every: usize,
+}
+
+/// Configuration of anomaly detection.
+#[derive(Clone, Copy)]
+struct AnomalyDetector {
+    /// Number of calls in every window.
+    window: usize,
+
+    /// By which factor must the mean of a window deviate from the long-term
+    /// mean to be reported?
+    factor: f64,
+}
+
+/// Records the totals of all scopes per interval.
+struct HistoryRecorder {
+    interval: Duration,
+
+    /// Maximal number of samples to keep.
+    capacity: usize,
+
+    /// When did recording begin?
+    start_time: Instant,
+
+    /// When did the current interval begin?
+    interval_start_time: Instant,
+
+    /// Number of calls and total duration of every scope when the previous
+    /// interval ended, indexed by scope id.
+    last_totals: Vec<(usize, u64)>,
+
+    samples: VecDeque<HistorySample>,
+}
+
+impl HistoryRecorder {
+    fn new(interval: Duration, capacity: usize) -> HistoryRecorder {
+        let now = Instant::now();
+
+        HistoryRecorder {
+            interval,
+            capacity,
+            start_time: now,
+            interval_start_time: now,
+            last_totals: Vec::new(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// End the current interval if it is due, adding the totals of the scopes
+    /// in `roots` to the history. Returns whether the interval has ended.
+    fn record_if_due(&mut self, scopes: &[Scope], roots: &[ScopeId]) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.interval_start_time) < self.interval {
+            return false;
+        }
+
+        self.last_totals.resize(scopes.len(), (0, 0));
+
+        let mut sample = HistorySample {
+            start: self.interval_start_time.duration_since(self.start_time),
+            duration: now.duration_since(self.interval_start_time),
+            scopes: Vec::new(),
+        };
+
+        let mut stack: Vec<_> = roots
+            .iter()
+            .rev()
+            .map(|&root| (root, scopes[root].name.to_string()))
+            .collect();
+        while let Some((id, path)) = stack.pop() {
+            let scope = &scopes[id];
+            let (last_calls, last_sum_ns) = self.last_totals[id];
+            self.last_totals[id] = (scope.num_calls, scope.duration_sum_ns);
+
+            let num_calls = scope.num_calls.saturating_sub(last_calls);
+            if num_calls > 0 {
+                sample.scopes.push(HistoryScope {
+                    path: path.clone(),
+                    id: scope.stable_id,
+                    parent_id: scope.pred.map(|pred| scopes[pred].stable_id),
+                    num_calls,
+                    duration_sum: Duration::from_nanos(
+                        scope.duration_sum_ns.saturating_sub(last_sum_ns),
+                    ),
+                });
+            }
+
+            // Children may have been left in the interval even if the scope
+            // itself has not, e.g. when it is still active.
+            for &succ in scope.succs.iter().rev() {
+                stack.push((succ, format!("{}/{}", path, scopes[succ].name)));
+            }
+        }
+
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        if self.capacity > 0 {
+            self.samples.push_back(sample);
+        }
+        self.interval_start_time = now;
+
+        true
+    }
+}
+
+/// Records the statistics of all scopes per window.
+struct WindowRecorder {
+    window: Duration,
+
+    /// Maximal number of windows to keep.
+    capacity: usize,
+
+    /// When did the current window begin?
+    window_start_time: Instant,
+
+    windows: VecDeque<ProfileSnapshot>,
+}
+
+impl WindowRecorder {
+    fn new(window: Duration, capacity: usize) -> WindowRecorder {
+        WindowRecorder {
+            window,
+            capacity,
+            window_start_time: Instant::now(),
+            windows: VecDeque::new(),
+        }
+    }
+}
+
+/// A `Profiler` stores the scope tree and keeps track of the currently active
+/// scope.
+///
+/// There is a global thread-local instance of `Profiler`, which is accessed
+/// through the free functions of this crate, and one for every `Channel`.
+pub(crate) struct Profiler {
+    /// Arena of all scopes in the tree, indexed by `ScopeId`. Scopes are
+    /// never removed from the tree, so every slot stays reachable until
+    /// `reset` drops the whole arena. The scopes that are active at that
+    /// point are copied into the new tree, instead of being kept detached.
+    scopes: Vec<Scope>,
+    roots: Vec<ScopeId>,
+    roots_by_name: HashMap<&'static str, ScopeId>,
+    current: Option<ScopeId>,
+
+    /// For every `profile!` callsite, the parent and child scope that the
+    /// callsite last entered.
+    callsite_cache: Vec<Option<(Option<ScopeId>, ScopeId, &'static str)>>,
+
+    /// The scope that `enter_name` enters for a name below a parent scope.
+    /// If this is the parent itself, entering the name is a recursive call.
+    name_cache: HashMap<(Option<ScopeId>, ScopeName), ScopeId>,
+
+    start_time: Instant,
+
+    /// When was the last delta report taken?
+    delta_start_time: Instant,
+
+    periodic: Option<Periodic>,
+    reporters: Vec<Periodic>,
+
+    /// Where to write the report upon receiving `SIGUSR1`.
+    #[cfg(all(feature = "signal", unix))]
+    signal_sink: Option<Box<dyn io::Write>>,
+    frame_recorder: Option<FrameRecorder>,
+    history_recorder: Option<HistoryRecorder>,
+    window_recorder: Option<WindowRecorder>,
+
+    /// Sends the durations of scopes to StatsD, if requested.
+    statsd_exporter: Option<StatsdExporter>,
+    warmup: usize,
+    scope_warmups: HashMap<&'static str, usize>,
+    metadata: Vec<(String, String)>,
+    report_options: ReportOptions,
+    mode: Mode,
+
+    /// Rules for enabling and disabling scopes by their path, in the order
+    /// in which they have been set.
+    scope_rules: Vec<(String, bool)>,
+
+    /// Names of the scopes that the root scopes are nested below in
+    /// snapshots.
+    thread_parent: Vec<String>,
+
+    /// Should the CPU time of the thread be measured for every scope?
+    measure_cpu_time: bool,
+
+    /// Hardware counters that are measured for every scope.
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf_counters: Option<perf::Counters>,
+
+    /// Should context switches and page faults be counted for every scope?
+    measure_rusage: bool,
+
+    /// Should the median duration of every scope be estimated?
+    estimate_median: bool,
+
+    /// Above how many calls per second is a warning emitted for a scope?
+    max_call_rate: Option<f64>,
+
+    /// How many children with runtime names may a scope have before further
+    /// names are folded into `<other>`?
+    max_dynamic_children: Option<usize>,
+
+    /// Should the time spent in the profiler itself be measured?
+    measure_overhead: bool,
+
+    /// Time spent in the profiler itself while entering and leaving scopes,
+    /// if it is measured.
+    overhead_ns: u64,
+
+    /// Should allocations be counted for every scope?
+    measure_allocs: bool,
+
+    /// Should the durations of scopes be published to `metrics`?
+    publish_metrics: bool,
+
+    /// How many calls of every scope are kept in its timeline? Zero if the
+    /// timeline is not being recorded.
+    timeline_capacity: usize,
+
+    anomaly_detector: Option<AnomalyDetector>,
+
+    /// Minimal duration of calls that are reported as hitches.
+    hitch_threshold: Option<Duration>,
+
+    /// Half-life of the weights of calls in the decayed averages, if they
+    /// are computed.
+    half_life: Option<Duration>,
+
+    /// Number of times that a root scope has been left.
+    num_frames: u64,
+
+    /// Incremented whenever the tree is reset, invalidating the scope ids
+    /// that guards hold.
+    generation: u64,
+
+    /// Events that are waiting to be passed to user callbacks.
+    events: Events,
+
+    subscriptions: Vec<Subscription>,
+    next_subscription_id: u64,
+
+    /// Publishes the active scopes to the watchdog thread, if any.
+    watched: Option<Watched>,
+
+    /// Number of scopes that have been entered since entering a disabled
+    /// scope, including the disabled scope itself.
+    muted_depth: usize,
+
+    /// Scope ids that have been registered with `puffin` on this thread.
+    #[cfg(feature = "puffin")]
+    puffin_scope_ids: HashMap<&'static str, puffin::ScopeId>,
+}
+
+impl Profiler {
+    fn new() -> Profiler {
+        Profiler {
+            scopes: Vec::new(),
+            roots: Vec::new(),
+            roots_by_name: HashMap::new(),
+            current: None,
+            callsite_cache: Vec::new(),
+            name_cache: HashMap::new(),
+            start_time: Instant::now(),
+            delta_start_time: Instant::now(),
+            periodic: None,
+            reporters: Vec::new(),
+            #[cfg(all(feature = "signal", unix))]
+            signal_sink: None,
+            frame_recorder: None,
+            history_recorder: None,
+            window_recorder: None,
+            statsd_exporter: None,
+            warmup: 0,
+            scope_warmups: HashMap::new(),
+            metadata: Vec::new(),
+            report_options: ReportOptions::default(),
+            mode: Mode::from_env(),
+            scope_rules: Vec::new(),
+            thread_parent: Vec::new(),
+            measure_cpu_time: false,
+            #[cfg(all(feature = "perf", target_os = "linux"))]
+            perf_counters: None,
+            measure_rusage: false,
+            estimate_median: false,
+            max_dynamic_children: None,
+            max_call_rate: None,
+            measure_overhead: false,
+            overhead_ns: 0,
+            measure_allocs: false,
+            publish_metrics: false,
+            timeline_capacity: 0,
+            anomaly_detector: None,
+            hitch_threshold: None,
+            half_life: None,
+            num_frames: 0,
+            generation: 0,
+            events: Events::default(),
+            subscriptions: Vec::new(),
+            next_subscription_id: 0,
+            watched: None,
+            muted_depth: 0,
+            #[cfg(feature = "puffin")]
+            puffin_scope_ids: HashMap::new(),
+        }
+    }
+
+    /// Enter a scope. Returns a `Guard` that should be dropped upon leaving
+    /// the scope.
+    pub(crate) fn enter(&mut self, name: &'static str) -> Guard {
+        if self.mode == Mode::Off {
+            return Guard::disabled();
+        }
+        if self.muted_depth > 0 {
+            return self.enter_muted();
+        }
+        if let Some(current) = self.recursive_scope(name) {
+            self.scopes[current].recursions += 1;
+            return Guard::disabled();
+        }
+        let start = self.measure_overhead.then(Instant::now);
+        self.before_enter();
+
+        let succ = self.find_or_add_path(name);
+        let guard = self.enter_path(succ);
+        self.add_overhead(start);
+        guard
+    }
+
+    /// Enter a scope whose name is only known at runtime. See the free
+    /// function `enter_owned`.
+    pub(crate) fn enter_owned(&mut self, name: String) -> Guard {
+        if self.mode == Mode::Off {
+            return Guard::disabled();
+        }
+        if self.muted_depth > 0 {
+            return self.enter_muted();
+        }
+        let start = self.measure_overhead.then(Instant::now);
+
+        let succ = self.resolve_name(&name, intern::intern);
+        self.enter_resolved(succ, start)
+    }
+
+    /// Enter a scope with an interned name. See the free function
+    /// `enter_name`.
+    pub(crate) fn enter_name(&mut self, name: ScopeName) -> Guard {
+        if self.mode == Mode::Off {
+            return Guard::disabled();
+        }
+        if self.muted_depth > 0 {
+            return self.enter_muted();
+        }
+        let start = self.measure_overhead.then(Instant::now);
+
+        // Interned names can be hashed by their pointer, so in the steady
+        // state, entering does not look at the name at all.
+        let key = (self.current, name);
+        let succ = match self.name_cache.get(&key) {
+            Some(&succ) => succ,
+            None => {
+                let succ = self.resolve_name(name.as_str(), |_| name.as_str());
+                self.name_cache.insert(key, succ);
+                succ
+            }
+        };
+        self.enter_resolved(succ, start)
+    }
+
+    /// Returns the scope at the path `name` below the current scope, or the
+    /// current scope itself if entering `name` would be a recursive call.
+    ///
+    /// New children of the current scope are limited by
+    /// `max_dynamic_children`. The name is only converted with `to_static`
+    /// if a scope needs to be added for it.
+    fn resolve_name(
+        &mut self,
+        name: &str,
+        to_static: impl FnOnce(&str) -> &'static str,
+    ) -> ScopeId {
+        if let Some(current) = self.recursive_scope(name) {
+            return current;
+        }
+        if let Some(succ) = self.find_path(name) {
+            return succ;
+        }
+
+        let limit = match self.max_dynamic_children {
+            Some(limit) => limit,
+            None => return self.find_or_add_path(to_static(name)),
+        };
+
+        let first = name.split('/').find(|name| !name.is_empty());
+        let siblings = match self.current {
+            Some(current) => &self.scopes[current].succs,
+            None => &self.roots,
+        };
+        let is_new =
+            first.is_some_and(|first| siblings.iter().all(|&id| self.scopes[id].name != first));
+        if !is_new {
+            return self.find_or_add_path(to_static(name));
+        }
+
+        let num_dynamic = siblings
+            .iter()
+            .filter(|&&id| self.scopes[id].dynamic)
+            .count();
+        if num_dynamic >= limit {
+            return self
+                .recursive_scope(OTHER_SCOPE_NAME)
+                .unwrap_or_else(|| self.find_or_add_succ(self.current, OTHER_SCOPE_NAME));
+        }
+
+        let succ = self.find_or_add_path(to_static(name));
+        let mut id = succ;
+        while self.scopes[id].pred != self.current {
+            id = self.scopes[id]
+                .pred
+                .expect("scope must be below the current scope");
+        }
+        self.scopes[id].dynamic = true;
+        succ
+    }
+
+    /// Enter `succ`, which has been resolved with `resolve_name`, counting a
+    /// recursive call if it is the current scope.
+    fn enter_resolved(&mut self, succ: ScopeId, start: Option<Instant>) -> Guard {
+        if self.current == Some(succ) {
+            self.scopes[succ].recursions += 1;
+            return Guard::disabled();
+        }
+        self.before_enter();
+
+        let guard = self.enter_path(succ);
+        self.add_overhead(start);
+        guard
+    }
+
+    /// Enter a scope, using the cache of the `profile!` invocation at
+    /// `callsite` for finding the scope in the tree.
+    pub(crate) fn enter_callsite(&mut self, callsite: &Callsite, name: &'static str) -> Guard {
+        if self.mode == Mode::Off {
+            return Guard::disabled();
+        }
+        if self.muted_depth > 0 {
+            return self.enter_muted();
+        }
+        if let Some(current) = self.recursive_scope(name) {
+            self.scopes[current].recursions += 1;
+            return Guard::disabled();
+        }
+        let start = self.measure_overhead.then(Instant::now);
+        self.before_enter();
+
+        let callsite_id = callsite.id();
+        let cached = self.callsite_cache.get(callsite_id).copied().flatten();
+
+        let succ = match cached {
+            // Comparing pointers is enough, since the name at a callsite
+            // almost always is a literal. Otherwise we fall back to a lookup.
+            Some((pred, succ, cached_name))
+                if pred == self.current && ptr::eq(cached_name, name) =>
+            {
+                succ
+            }
+            _ => {
+                let succ = self.find_or_add_path(name);
+
+                if self.callsite_cache.len() <= callsite_id {
+                    self.callsite_cache.resize(callsite_id + 1, None);
+                }
+                self.callsite_cache[callsite_id] = Some((self.current, succ, name));
+
+                succ
+            }
+        };
+
+        let guard = self.enter_path(succ);
+        self.add_overhead(start);
+        guard
+    }
+
+    /// Add the time since `start` to the time spent in the profiler itself,
+    /// if it is measured.
+    fn add_overhead(&mut self, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.overhead_ns = self
+                .overhead_ns
+                .saturating_add(clock::as_nanos(start.elapsed()));
+        }
+    }
+
+    /// Make the scope at `path` the current scope without entering it.
+    /// Returns the previous current scope and the generation of the tree, or
+    /// `None` if profiling is off.
+    fn set_current_path(&mut self, path: &[&'static str]) -> Option<(Option<ScopeId>, u64)> {
+        if self.mode == Mode::Off || self.muted_depth > 0 {
+            return None;
+        }
+
+        let previous = (self.current, self.generation);
+
+        let mut current = None;
+        for name in path {
+            current = Some(self.find_or_add_succ(current, name));
+        }
+        self.current = current;
+
+        Some(previous)
+    }
+
+    /// Undo `set_current_path`.
+    fn restore_current(&mut self, (current, generation): (Option<ScopeId>, u64)) {
+        // If the tree has been reset in the meantime, the previous scope no
+        // longer exists.
+        self.current = if generation == self.generation {
+            current
+        } else {
+            None
+        };
+    }
+
+    fn before_enter(&mut self) {
+        if self.current.is_none() {
+            // We are between frames, which is a good time for reporting.
+            // Reporters are handled by `take_due_reports`.
+            #[cfg(all(feature = "signal", unix))]
+            self.write_if_signaled();
+
+            if let Some(recorder) = self.history_recorder.as_mut() {
+                recorder.record_if_due(&self.scopes, &self.roots);
+            }
+            self.record_window_if_due();
+            if let Some(exporter) = self.statsd_exporter.as_mut() {
+                exporter.send_if_due(&self.scopes, &self.roots);
+            }
+        }
+    }
+
+    /// Returns the descendant of the current scope at `path`, adding it and
+    /// its ancestors to the tree if necessary.
+    ///
+    /// The path consists of scope names separated by `/`. Usually, it is just
+    /// the name of a child of the current scope.
+    fn find_or_add_path(&mut self, path: &'static str) -> ScopeId {
+        if !path.contains('/') {
+            return self.find_or_add_succ(self.current, path);
+        }
+
+        let mut pred = self.current;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            pred = Some(self.find_or_add_succ(pred, name));
+        }
+
+        pred.unwrap_or_else(|| self.find_or_add_succ(self.current, path))
+    }
+
+    /// Returns the descendant of the current scope at `path`, if it is
+    /// already in the tree. See `find_or_add_path`.
+    fn find_path(&self, path: &str) -> Option<ScopeId> {
+        if !path.contains('/') {
+            return self.find_succ(self.current, path);
+        }
+
+        let mut pred = self.current;
+        let mut found = false;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            pred = Some(self.find_succ(pred, name)?);
+            found = true;
+        }
+
+        if found {
+            pred
+        } else {
+            self.find_succ(self.current, path)
+        }
+    }
+
+    /// Returns the child of `pred` called `name`, if it is already in the
+    /// tree.
+    fn find_succ(&self, pred: Option<ScopeId>, name: &str) -> Option<ScopeId> {
+        let siblings = match pred {
+            // We are currently in some scope.
+            Some(pred) => &self.scopes[pred].succs_by_name,
+            // We are currently not within any scope.
+            None => &self.roots_by_name,
+        };
+        siblings.get(name).copied()
+    }
+
+    /// Returns the child of `pred` called `name`, adding it to the tree if
+    /// necessary.
+    fn find_or_add_succ(&mut self, pred: Option<ScopeId>, name: &'static str) -> ScopeId {
+        self.find_succ(pred, name)
+            .unwrap_or_else(|| self.add_scope(name, pred))
+    }
+
+    /// Enter `succ`, which must be a descendant of the current scope, and all
+    /// scopes in between.
+    fn enter_path(&mut self, succ: ScopeId) -> Guard {
+        if !self.scopes[succ].enabled {
+            return self.enter_muted();
+        }
+
+        let mut path = Vec::new();
+        let mut pred = self.scopes[succ].pred;
+        while pred != self.current {
+            let id = pred.expect("scope must be a descendant of the current scope");
+            path.push(id);
+            pred = self.scopes[id].pred;
+        }
+
+        // Scopes in between are entered without being forwarded to `puffin`
+        // or Tracy, and are all left by the guard of `succ`.
+        let now = Instant::now();
+
+        if self.current.is_none() {
+            let root = &mut self.scopes[path.last().copied().unwrap_or(succ)];
+            if let Some(last_left) = root.last_left {
+                let idle_ns = clock::as_nanos(now.saturating_duration_since(last_left));
+                root.idle_sum_ns = root.idle_sum_ns.saturating_add(idle_ns);
+            }
+
+            while root
+                .recent_entries
+                .front()
+                .is_some_and(|&entry| now.saturating_duration_since(entry) > FPS_WINDOW)
+            {
+                root.recent_entries.pop_front();
+            }
+            root.recent_entries.push_back(now);
+        }
+
+        for &id in path.iter().rev() {
+            // Entering a path again during the same visit of the parent
+            // continues the previous call of the intermediate scope.
+            let parent_entry = self.current.map(|pred| self.scopes[pred].num_entries);
+            let scope = &mut self.scopes[id];
+            scope.continued = parent_entry.is_some() && scope.path_entry == parent_entry;
+            scope.path_entry = parent_entry;
+            scope.num_entries += 1;
+            scope.active_since = Some(now);
+            self.current = Some(id);
+
+            if let Some(watched) = self.watched.as_ref() {
+                watched.enter(self.scopes[id].name, now);
+            }
+        }
+
+        let mut guard = self.enter_scope(succ);
+        guard.levels += path.len();
+        guard
+    }
+
+    /// Returns the current scope if it is called `name`, i.e. if entering
+    /// `name` now would be a recursive call.
+    fn recursive_scope(&self, name: &str) -> Option<ScopeId> {
+        self.current
+            .filter(|&current| self.scopes[current].name == name)
+    }
+
+    /// Enter a scope that has been disabled or is below a disabled scope,
+    /// without reading the clock.
+    fn enter_muted(&mut self) -> Guard {
+        self.muted_depth += 1;
+
+        let mut guard = Guard::enter(self.start_time);
+        guard.muted = true;
+        guard
+    }
+
+    /// Returns whether the scope at `path` is enabled according to the
+    /// rules, ignoring its ancestors.
+    fn is_enabled(&self, path: &str) -> bool {
+        self.scope_rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern::matches(pattern, path))
+            .map_or(true, |&(_, enabled)| enabled)
+    }
+
+    /// Update whether the scope `id` is enabled, assuming that its parent is
+    /// already up to date.
+    fn update_enabled(&mut self, id: ScopeId) {
+        let pred_enabled = self.scopes[id]
+            .pred
+            .map_or(true, |pred| self.scopes[pred].enabled);
+        self.scopes[id].enabled =
+            pred_enabled && (self.scope_rules.is_empty() || self.is_enabled(&self.path(id)));
+    }
+
+    /// Returns the path of the scope `id`, i.e. the names of the scope and its
+    /// ancestors, separated by `/`.
+    fn path(&self, id: ScopeId) -> String {
+        let mut names = Vec::new();
+        let mut scope = Some(id);
+        while let Some(id) = scope {
+            names.push(self.scopes[id].name);
+            scope = self.scopes[id].pred;
+        }
+        names.reverse();
+        names.join("/")
+    }
+
+    fn all_scope_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut stack: Vec<_> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&root| (root, self.scopes[root].name.to_string()))
+            .collect();
+        while let Some((id, path)) = stack.pop() {
+            for &succ in self.scopes[id].succs.iter().rev() {
+                stack.push((succ, format!("{}/{}", path, self.scopes[succ].name)));
+            }
+            paths.push(path);
+        }
+
+        paths
+    }
+
+    fn attr(&mut self, key: &'static str, value: f64) {
+        match self.current {
+            Some(current) if self.muted_depth == 0 && self.mode != Mode::Off => {
+                self.record_attr(current, key, value)
+            }
+            _ => (),
+        }
+    }
+
+    /// Record a value of the attribute `key` for the scope `id`.
+    fn record_attr(&mut self, id: ScopeId, key: &'static str, value: f64) {
+        let attrs = &mut self.scopes[id].attrs;
+        let index = match attrs.iter().position(|attr| attr.key == key) {
+            Some(index) => index,
+            None => {
+                attrs.push(AttrStats::new(key));
+                attrs.len() - 1
+            }
+        };
+        attrs[index].record(value);
+    }
+
+    /// Attach the hardware counters that have accumulated since `start` to
+    /// the scope `id`.
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    fn record_perf_counters(&mut self, id: ScopeId, start: &[u64; perf::MAX_COUNTERS]) {
+        let counters = match self.perf_counters.as_ref() {
+            Some(counters) => counters,
+            None => return,
+        };
+
+        let end = counters.read();
+        let deltas: Vec<_> = counters
+            .iter()
+            .zip(start.iter().zip(end.iter()))
+            .map(|(counter, (start, end))| (counter.name(), end.saturating_sub(*start)))
+            .collect();
+        for (name, delta) in deltas {
+            self.record_attr(id, name, delta as f64);
+        }
+    }
+
+    fn set_scope_enabled(&mut self, pattern: &str, enabled: bool) {
+        self.scope_rules.retain(|(other, _)| other != pattern);
+        self.scope_rules.push((pattern.to_string(), enabled));
+
+        // Parents are always added before their children, so they are updated
+        // first.
+        for id in 0..self.scopes.len() {
+            self.update_enabled(id);
+        }
+    }
+
+    fn subscribe<F: FnMut(&ScopeStats) + 'static>(
+        &mut self,
+        pattern: &str,
+        every: usize,
+        callback: F,
+    ) {
+        let id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+
+        self.subscriptions.push(Subscription {
+            id,
+            pattern: pattern.to_string(),
+            every: every.max(1),
+        });
+        self.events
+            .callbacks
+            .on_stats
+            .push((id, Box::new(callback)));
+
+        for scope in 0..self.scopes.len() {
+            self.update_subscriptions(scope);
+        }
+    }
+
+    /// Update which subscriptions match the scope `id`.
+    fn update_subscriptions(&mut self, id: ScopeId) {
+        if self.subscriptions.is_empty() && self.scopes[id].subscriptions.is_empty() {
+            return;
+        }
+
+        let path = self.path(id);
+        self.scopes[id].subscriptions = self
+            .subscriptions
+            .iter()
+            .filter(|subscription| pattern::matches(&subscription.pattern, &path))
+            .map(|subscription| subscription.id)
+            .collect();
+    }
+
+    /// Enter `succ`, which must be a child of the current scope.
+    fn enter_scope(&mut self, succ: ScopeId) -> Guard {
+        #[allow(unused_mut)]
+        let mut guard = self.scopes[succ].enter();
+        guard.scope = Some((succ, self.generation));
+        if let Some(watched) = self.watched.as_ref() {
+            watched.enter(self.scopes[succ].name, guard.enter_time);
+        }
+        if self.measure_cpu_time {
+            guard.cpu_time_start_ns = clock::thread_cpu_time_ns();
+        }
+        #[cfg(all(feature = "perf", target_os = "linux"))]
+        {
+            guard.perf_start = self.perf_counters.as_ref().map(perf::Counters::read);
+        }
+        #[cfg(all(feature = "rusage", unix))]
+        if self.measure_rusage {
+            guard.rusage_start = Some(rusage::sample());
+        }
+        #[cfg(feature = "alloc")]
+        if self.measure_allocs {
+            guard.alloc_start = alloc::sample();
+        }
+
+        #[cfg(any(feature = "puffin", feature = "tracy"))]
+        let name = self.scopes[succ].name;
+
+        #[cfg(feature = "puffin")]
+        {
+            guard.puffin_scope = self.puffin_scope(name);
+        }
+
+        #[cfg(feature = "tracy")]
+        {
+            guard.tracy_span = tracy_client::Client::running()
+                .map(|client| client.span_alloc(Some(name), "", "", 0, 0));
+        }
+
+        self.current = Some(succ);
+
+        guard
+    }
+
+    /// Add a new scope to the tree, either as a child of `pred` or as a root.
+    fn add_scope(&mut self, name: &'static str, pred: Option<ScopeId>) -> ScopeId {
+        let id = self.scopes.len();
+        let parent_id = pred.map(|pred| self.scopes[pred].stable_id);
+        let stable_id = intern::scope_id(parent_id, name);
+        self.scopes.push(Scope::new(name, pred, stable_id));
+        self.update_enabled(id);
+        self.update_subscriptions(id);
+
+        match pred {
+            Some(pred) => {
+                self.scopes[pred].succs.push(id);
+                self.scopes[pred].succs_by_name.insert(name, id);
+            }
+            None => {
+                self.roots.push(id);
+                self.roots_by_name.insert(name, id);
+            }
+        }
+
+        id
+    }
+
+    /// Start a `puffin` scope for `name`, registering the name with `puffin`
+    /// on first use. Returns `None` if `puffin` profiling is off.
+    #[cfg(feature = "puffin")]
+    fn puffin_scope(&mut self, name: &'static str) -> Option<puffin::ProfilerScope> {
+        if !puffin::are_scopes_on() {
+            return None;
+        }
+
+        let scope_id = *self.puffin_scope_ids.entry(name).or_insert_with(|| {
+            puffin::ThreadProfiler::call(|tp| tp.register_named_scope(name, "", "", 0))
+        });
+
+        Some(puffin::ProfilerScope::new(scope_id, ""))
+    }
+
+    /// If we are between frames, write the periodic report and take
+    /// snapshots for the reporters whose schedule is due. The reporters are
+    /// taken out of the profiler until they are put back by
+    /// `restore_reporters`.
+    fn take_due_reports(&mut self) -> Option<DueReports> {
+        if self.current.is_some() || self.muted_depth > 0 || self.mode == Mode::Off {
+            return None;
+        }
+
+        let num_frames = self.num_frames;
+        let due = self
+            .periodic
+            .iter()
+            .chain(self.reporters.iter())
+            .any(|periodic| periodic.is_due(num_frames));
+
+        if !due {
+            return None;
+        }
+
+        // Temporarily take out the sinks, so that we can borrow `self` for
+        // writing the reports.
+        let mut periodic = self.periodic.take();
+        let mut reporters = std::mem::take(&mut self.reporters);
+
+        let due = DueReports {
+            periodic: periodic
+                .as_mut()
+                .and_then(|periodic| self.report_if_due(periodic)),
+            reporters: reporters
+                .iter_mut()
+                .filter_map(|periodic| self.report_if_due(periodic))
+                .collect(),
+        };
+
+        self.periodic = periodic;
+        self.reporters = reporters;
+
+        if due.periodic.is_none() && due.reporters.is_empty() {
+            None
+        } else {
+            Some(due)
+        }
+    }
+
+    /// Put back the reporters that have been invoked. Reporters whose slot
+    /// has been removed or replaced in the meantime are dropped.
+    fn restore_reporters(&mut self, due: DueReports) {
+        fn empty_slot(periodic: &mut Periodic) -> Option<&mut Option<Box<dyn Reporter>>> {
+            match &mut periodic.sink {
+                PeriodicSink::Reporter(slot @ None) => Some(slot),
+                _ => None,
+            }
+        }
+
+        if let Some((reporter, _)) = due.periodic {
+            if let Some(slot) = self.periodic.as_mut().and_then(empty_slot) {
+                *slot = Some(reporter);
+            }
+        }
+
+        let slots = self.reporters.iter_mut().filter_map(empty_slot);
+        for (slot, (reporter, _)) in slots.zip(due.reporters) {
+            *slot = Some(reporter);
+        }
+    }
+
+    /// Write the report to the sink of `periodic` if its schedule is due. If
+    /// the sink is a reporter, return it with a snapshot instead.
+    fn report_if_due(&mut self, periodic: &mut Periodic) -> Option<DueReport> {
+        let num_frames = self.num_frames;
+        if !periodic.is_due(num_frames) {
+            return None;
+        }
+
+        let report = match &mut periodic.sink {
+            PeriodicSink::Writer(sink) => {
+                if let Err(err) = self.write(sink) {
+                    log::warn!("coarse_prof: failed to write periodic report: {}", err);
+                }
+                None
+            }
+            PeriodicSink::Reporter(reporter) => reporter
+                .take()
+                .map(|reporter| (reporter, self.snapshot(self.report_options.show_active))),
+        };
+        if periodic.reset {
+            self.reset();
+        }
+
+        periodic.last_time = Instant::now();
+        periodic.last_frame = num_frames;
+        report
+    }
+
+    /// Write the report if `SIGUSR1` has been received.
+    #[cfg(all(feature = "signal", unix))]
+    fn write_if_signaled(&mut self) {
+        if self.signal_sink.is_none() || !signal::take_received() {
+            return;
+        }
+
+        if let Some(mut sink) = self.signal_sink.take() {
+            if let Err(err) = self.write(&mut sink) {
+                log::warn!("coarse_prof: failed to write report upon signal: {}", err);
+            }
+            self.signal_sink = Some(sink);
+        }
+    }
+
+    /// Record the frame that has just ended by leaving `root`, if frames
+    /// are being recorded.
+    fn record_frame(&mut self, root: ScopeId) {
+        if let Some(recorder) = self.frame_recorder.as_mut() {
+            if let Err(err) = recorder.record(&self.scopes, root) {
+                log::warn!("coarse_prof: failed to record frame: {}", err);
+            }
+        }
+    }
+
+    /// Completely reset profiling data.
+    fn reset(&mut self) {
+        // Note that we could now still be anywhere in the previous profiling
+        // tree, so we can not simply reset `self.current`. Instead, we split
+        // the active scopes at this point: they are added to the new tree as
+        // if they had just been entered, so that only the time after the
+        // reset is attributed to them when their guards are dropped.
+        let active_path = self.active_path_ids();
+        let old_scopes = std::mem::take(&mut self.scopes);
+
+        self.roots.clear();
+        self.roots_by_name.clear();
+        self.callsite_cache.clear();
+        self.name_cache.clear();
+        self.current = None;
+
+        let now = Instant::now();
+        for id in active_path {
+            let succ = self.find_or_add_succ(self.current, old_scopes[id].name);
+            self.scopes[succ].active_since = Some(now);
+            self.current = Some(succ);
+        }
+
+        self.generation += 1;
+        self.restart();
+    }
+
+    /// Reset the statistics, but keep the tree.
+    fn reset_stats(&mut self) {
+        for scope in self.scopes.iter_mut() {
+            scope.reset_stats();
+        }
+
+        self.restart();
+
+        // As in `reset`, the active scopes are split at this point, so that
+        // only the time after the reset is attributed to them, and the time
+        // between frames before the reset does not count as idle.
+        let now = self.start_time;
+        for id in self.active_path_ids() {
+            self.scopes[id].active_since = Some(now);
+        }
+        for &root in &self.roots {
+            if let Some(last_left) = self.scopes[root].last_left.as_mut() {
+                *last_left = now;
+            }
+        }
+    }
+
+    /// Restart measuring time, after the statistics have been reset.
+    fn restart(&mut self) {
+        self.start_time = Instant::now();
+        self.delta_start_time = self.start_time;
+        self.overhead_ns = 0;
+
+        // The totals of the scopes have been reset, so we need to start
+        // counting from zero.
+        if let Some(recorder) = self.frame_recorder.as_mut() {
+            recorder.last_sums_ns.clear();
+        }
+        if let Some(recorder) = self.history_recorder.as_mut() {
+            recorder.last_totals.clear();
+        }
+        if let Some(recorder) = self.window_recorder.as_mut() {
+            recorder.window_start_time = self.start_time;
+        }
+        if let Some(exporter) = self.statsd_exporter.as_mut() {
+            exporter.reset();
+        }
+    }
+
+    /// End the current window if it is due, adding its statistics to the
+    /// recorded windows.
+    fn record_window_if_due(&mut self) {
+        let now = Instant::now();
+        let window_start_time = match self.window_recorder.as_ref() {
+            Some(recorder) if now.duration_since(recorder.window_start_time) >= recorder.window => {
+                recorder.window_start_time
+            }
+            _ => return,
+        };
+
+        let window = ProfileSnapshot {
+            total_duration: now.duration_since(window_start_time),
+            metadata: self.metadata.clone(),
+            overhead: None,
+            roots: self
+                .roots
+                .iter()
+                .filter_map(|&root| {
+                    self.scopes[root].delta_stats(&self.scopes, |scope| &scope.window)
+                })
+                .collect(),
+        };
+
+        let recorder = self.window_recorder.as_mut().unwrap();
+        if recorder.windows.len() >= recorder.capacity {
+            recorder.windows.pop_front();
+        }
+        if recorder.capacity > 0 {
+            recorder.windows.push_back(window);
+        }
+        recorder.window_start_time = now;
+
+        for scope in self.scopes.iter_mut() {
+            scope.window = Delta::new();
+        }
+    }
+
+    fn set_metadata(&mut self, key: String, value: String) {
+        if let Some(entry) = self.metadata.iter_mut().find(|(k, _)| *k == key) {
+            entry.1 = value;
+        } else {
+            self.metadata.push((key, value));
+        }
+    }
+
+    /// Returns how many calls of scopes called `name` are excluded from the
+    /// statistics.
+    fn warmup_for(&self, name: &'static str) -> usize {
+        if self.scope_warmups.is_empty() {
+            self.warmup
+        } else {
+            self.scope_warmups.get(name).copied().unwrap_or(self.warmup)
+        }
+    }
+
+    /// Leave the current scope.
+    fn leave(&mut self, duration: Duration, cpu_time_ns: Option<u64>) {
+        self.current = if let Some(current) = self.current {
+            self.record_leave(current, duration, cpu_time_ns);
+            if let Some(watched) = self.watched.as_ref() {
+                watched.leave();
+            }
+
+            // Set current scope back to the parent node (if any).
+            self.scopes[current].pred
+        } else {
+            // This should not happen with proper usage.
+            error::misuse(Error::LeaveOutsideScope);
+
+            None
+        };
+    }
+
+    /// Leave the current scope, which has been split by a reset since it has
+    /// been entered. Only the part of `duration` after the reset is
+    /// recorded. The CPU time can not be split, so it is not recorded at all.
+    fn leave_split(&mut self, duration: Duration) {
+        let active_since = self
+            .current
+            .and_then(|current| self.scopes[current].active_since);
+        let duration = match active_since {
+            Some(active_since) => duration.min(active_since.elapsed()),
+            None => duration,
+        };
+
+        self.leave(duration, None);
+    }
+
+    /// Leave the scope `id` and the `levels - 1` ancestors that have been
+    /// entered together with it.
+    ///
+    /// Usually, `id` is the current scope. However, guards may also be
+    /// dropped out of order, e.g. when they are stored in structs. If `id` is
+    /// an ancestor of the current scope, the scopes in between stay active
+    /// until their guards are dropped, but new scopes are entered below the
+    /// parent of `id`. If `id` is not active anymore, because one of its
+    /// ancestors has already been left, only its statistics are updated.
+    fn leave_scope(
+        &mut self,
+        id: ScopeId,
+        levels: usize,
+        duration: Duration,
+        cpu_time_ns: Option<u64>,
+    ) {
+        if self.current == Some(id) {
+            for _ in 0..levels {
+                self.leave(duration, cpu_time_ns);
+            }
+            return;
+        }
+
+        let is_active = self.active_path_ids().contains(&id);
+
+        let mut outermost = id;
+        for level in 0..levels {
+            self.record_leave(outermost, duration, cpu_time_ns);
+            if level + 1 < levels {
+                outermost = self.scopes[outermost]
+                    .pred
+                    .expect("scopes entered by a guard must have a parent");
+            }
+        }
+
+        if is_active {
+            self.current = self.scopes[outermost].pred;
+            if let Some(watched) = self.watched.as_ref() {
+                watched.truncate(self.active_path_ids().len());
+            }
+        }
+    }
+
+    /// Update the statistics of the scope `id` for a call that is being left.
+    fn record_leave(&mut self, id: ScopeId, duration: Duration, cpu_time_ns: Option<u64>) {
+        let warmup = self.warmup_for(self.scopes[id].name);
+        self.record_timeline(id, duration);
+        if std::mem::take(&mut self.scopes[id].continued) {
+            self.scopes[id].continue_call(clock::as_nanos(duration), cpu_time_ns);
+            return;
+        }
+        let left_time = self.scopes[id]
+            .active_since
+            .map(|active_since| active_since + duration);
+        if self.scopes[id].pred.is_none() {
+            self.scopes[id].last_left = left_time;
+        }
+
+        let duration_ns = clock::as_nanos(duration);
+        let num_calls = self.scopes[id].num_calls;
+        self.scopes[id].leave(duration_ns, cpu_time_ns, warmup);
+        if self.scopes[id].num_calls > num_calls {
+            self.detect_anomaly(id, duration_ns);
+            if let (Some(max_call_rate), Some(left_time)) = (self.max_call_rate, left_time) {
+                self.check_call_rate(id, left_time, max_call_rate);
+            }
+            if let Some(half_life) = self.half_life {
+                self.scopes[id].record_decayed(duration_ns, half_life);
+            }
+            if self.estimate_median {
+                self.scopes[id]
+                    .median
+                    .get_or_insert_with(|| P2Quantile::new(0.5))
+                    .add(duration_ns as f64);
+            }
+            #[cfg(feature = "metrics")]
+            if self.publish_metrics {
+                self.publish_metric(id, duration);
+            }
+
+            for i in 0..self.scopes[id].subscriptions.len() {
+                let subscription_id = self.scopes[id].subscriptions[i];
+                let every = self
+                    .subscriptions
+                    .iter()
+                    .find(|subscription| subscription.id == subscription_id)
+                    .map_or(1, |subscription| subscription.every);
+                if self.scopes[id].num_calls % every == 0 {
+                    let stats = self.scopes[id].stats(&self.scopes, None);
+                    self.events
+                        .pending
+                        .push(Event::Stats(subscription_id, Box::new(stats)));
+                }
+            }
+
+            if self.scopes[id].pred.is_none() {
+                let bucket = FRAME_TIME_BUCKETS
+                    .iter()
+                    .position(|&bound| duration <= bound)
+                    .unwrap_or(NUM_FRAME_TIME_BUCKETS);
+                self.scopes[id].frame_histogram[bucket] += 1;
+
+                let is_frame_scope =
+                    self.report_options.frame_scope.as_deref() == Some(self.scopes[id].name);
+                let max_len = if is_frame_scope {
+                    NUM_RECENT_FRAMES
+                } else {
+                    NUM_RECENT_DURATIONS
+                };
+
+                let scope = &mut self.scopes[id];
+                scope.is_frame_scope = is_frame_scope;
+                let excess = (scope.recent_ns.len() + 1).saturating_sub(max_len);
+                scope.recent_ns.drain(..excess);
+                scope.recent_ns.push_back(duration_ns);
+            }
+
+            if self
+                .hitch_threshold
+                .is_some_and(|threshold| duration >= threshold)
+            {
+                self.events.pending.push(Event::Hitch(Hitch {
+                    path: self.path(id),
+                    duration,
+                    frame: self.num_frames,
+                }));
+            }
+        }
+
+        if self.scopes[id].pred.is_none() {
+            self.record_frame(id);
+            self.num_frames += 1;
+        }
+    }
+
+    /// Count a call of `id` that was left at `left_time` in the current
+    /// window of measuring the call rate, and warn once if the rate exceeds
+    /// `max_call_rate`.
+    fn check_call_rate(&mut self, id: ScopeId, left_time: Instant, max_call_rate: f64) {
+        let scope = &mut self.scopes[id];
+        if scope.warned_call_rate {
+            return;
+        }
+
+        let (start, calls) = scope.rate_window.get_or_insert((left_time, 0));
+        *calls += 1;
+        let elapsed = left_time.saturating_duration_since(*start);
+
+        // If more calls than allowed per window have been made before the
+        // window is over, the rate is exceeded already.
+        if *calls as f64 > max_call_rate * CALL_RATE_WINDOW.as_secs_f64() {
+            scope.warned_call_rate = true;
+            log::warn!(
+                "coarse_prof: scope `{}` is entered more than {} times per second, which is \
+                 too fine-grained for a coarse profiler and likely skews the results",
+                self.path(id),
+                max_call_rate,
+            );
+        } else if elapsed >= CALL_RATE_WINDOW {
+            scope.rate_window = None;
+        }
+    }
+
+    /// Record `duration` in the `metrics` histogram of the scope `id`.
+    #[cfg(feature = "metrics")]
+    fn publish_metric(&mut self, id: ScopeId, duration: Duration) {
+        if self.scopes[id].metrics_histogram.is_none() {
+            self.scopes[id].metrics_histogram = Some(metrics::histogram!(self.path(id)));
+        }
+
+        if let Some(histogram) = self.scopes[id].metrics_histogram.as_ref() {
+            histogram.record(duration.as_secs_f64());
+        }
+    }
+
+    /// Add a call of `id` to the current window of anomaly detection, and
+    /// queue an event if the window is complete and deviates from the
+    /// long-term mean.
+    fn detect_anomaly(&mut self, id: ScopeId, duration_ns: u64) {
+        let detector = match self.anomaly_detector {
+            Some(detector) => detector,
+            None => return,
+        };
+
+        let scope = &mut self.scopes[id];
+        scope.window_calls += 1;
+        scope.window_sum_ns = scope.window_sum_ns.saturating_add(duration_ns);
+        if scope.window_calls < detector.window {
+            return;
+        }
+
+        let window_calls = std::mem::take(&mut scope.window_calls);
+        let window_sum_ns = std::mem::take(&mut scope.window_sum_ns);
+        let past_calls = scope.num_calls.saturating_sub(window_calls);
+        if past_calls < detector.window {
+            return;
+        }
+
+        let recent_mean_ns = window_sum_ns as f64 / window_calls as f64;
+        let long_term_mean_ns =
+            scope.duration_sum_ns.saturating_sub(window_sum_ns) as f64 / past_calls as f64;
+        if recent_mean_ns > long_term_mean_ns * detector.factor
+            || recent_mean_ns * detector.factor < long_term_mean_ns
+        {
+            let num_calls = scope.num_calls;
+            self.events.pending.push(Event::Anomaly(Anomaly {
+                path: self.path(id),
+                recent_mean: Duration::from_nanos(recent_mean_ns as u64),
+                long_term_mean: Duration::from_nanos(long_term_mean_ns as u64),
+                num_calls,
+            }));
+        }
+    }
+
+    /// Add the call of `id` that is being left to its timeline, if the
+    /// timeline is being recorded.
+    fn record_timeline(&mut self, id: ScopeId, duration: Duration) {
+        if self.timeline_capacity == 0 {
+            return;
+        }
+
+        if let Some(active_since) = self.scopes[id].active_since {
+            let start_ns = clock::as_nanos(active_since.saturating_duration_since(self.start_time));
+            let end_ns = start_ns.saturating_add(clock::as_nanos(duration));
+
+            let timeline = &mut self.scopes[id].timeline;
+            if timeline.len() >= self.timeline_capacity {
+                timeline.pop_front();
+            }
+            timeline.push_back((start_ns, end_ns));
+        }
+    }
+
+    /// Returns the timeline of the root scope that has been left most
+    /// recently.
+    fn timeline(&self) -> Option<Timeline> {
+        fn add_rows(
+            scopes: &[Scope],
+            id: ScopeId,
+            depth: usize,
+            (frame_start, frame_end): (u64, u64),
+            rows: &mut Vec<TimelineRow>,
+        ) {
+            let spans: Vec<_> = scopes[id]
+                .timeline
+                .iter()
+                // Spans of zero length, e.g. with a coarse clock, are kept if
+                // they lie within the frame.
+                .filter(|&&(start, end)| {
+                    (start < frame_end && end > frame_start)
+                        || (start >= frame_start && end <= frame_end)
+                })
+                .map(|&(start, end)| {
+                    (
+                        Duration::from_nanos(start.max(frame_start) - frame_start),
+                        Duration::from_nanos(end.min(frame_end) - frame_start),
+                    )
+                })
+                .collect();
+            if spans.is_empty() {
+                return;
+            }
+
+            rows.push(TimelineRow {
+                name: scopes[id].name.to_string(),
+                depth,
+                spans,
+            });
+
+            for &succ in &scopes[id].succs {
+                add_rows(scopes, succ, depth + 1, (frame_start, frame_end), rows);
+            }
+        }
+
+        let (root, frame) = self
+            .roots
+            .iter()
+            .filter_map(|&root| Some((root, *self.scopes[root].timeline.back()?)))
+            .max_by_key(|&(_, (_, end))| end)?;
+
+        let mut rows = Vec::new();
+        add_rows(&self.scopes, root, 0, frame, &mut rows);
+
+        Some(Timeline {
+            duration: Duration::from_nanos(frame.1 - frame.0),
+            rows,
+        })
+    }
+
+    fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_with(out, &self.report_options)
+    }
+
+    fn write_with<W: io::Write>(&self, out: &mut W, options: &ReportOptions) -> io::Result<()> {
+        let snapshot = self.snapshot(options.show_active);
+        match self.mode {
+            Mode::Off => return Ok(()),
+            Mode::Summary => {
+                writeln!(out, "{}", snapshot.hottest_path())?;
+                return out.flush();
+            }
+            Mode::On => (),
+        }
+
+        let window = self
+            .window_recorder
+            .as_ref()
+            .and_then(|recorder| recorder.windows.back());
+        let options = match (&self.report_options.frame_scope, &options.frame_scope) {
+            (Some(frame_scope), None) => options.clone().frame_scope(frame_scope.clone()),
+            _ => options.clone(),
+        };
+        let mut report = snapshot.report(&options);
+        if let Some(window) = window {
+            report = report.window(window);
+        }
+        write!(out, "{}", report)?;
+        out.flush()
+    }
+
+    fn delta_snapshot(&mut self) -> ProfileSnapshot {
+        let now = Instant::now();
+
+        let snapshot = ProfileSnapshot {
+            total_duration: now.duration_since(self.delta_start_time),
+            metadata: self.metadata.clone(),
+            overhead: None,
+            roots: self.nest_in_thread_parent(
+                self.roots
+                    .iter()
+                    .filter_map(|&root| {
+                        self.scopes[root].delta_stats(&self.scopes, |scope| &scope.delta)
+                    })
+                    .collect(),
+            ),
+        };
+
+        for scope in self.scopes.iter_mut() {
+            scope.delta = Delta::new();
+        }
+        self.delta_start_time = now;
+
+        snapshot
+    }
+
+    /// Nest `roots` below the scopes set by `set_thread_parent`, which are
+    /// shown as not having been visited.
+    fn nest_in_thread_parent(&self, mut roots: Vec<ScopeStats>) -> Vec<ScopeStats> {
+        for name in self.thread_parent.iter().rev() {
+            roots = vec![ScopeStats {
+                name: name.clone(),
+                num_calls: 0,
+                duration_sum: Duration::new(0, 0),
+                duration_min: Duration::new(0, 0),
+                duration_max: Duration::new(0, 0),
+                active_for: None,
+                duration_variance: None,
+                cpu_time_sum: None,
+                idle_sum: None,
+                frame_histogram: None,
+                frame_lows: None,
+                decayed_mean: None,
+                duration_median: None,
+                max_backtrace: None,
+                recursions: 0,
+                recent_durations: Vec::new(),
+                wait: false,
+                id: None,
+                attrs: Vec::new(),
+                children: roots,
+            }];
+        }
+
+        roots
+    }
+
+    fn active_path(&self) -> Vec<&'static str> {
+        self.active_path_ids()
+            .into_iter()
+            .map(|id| self.scopes[id].name)
+            .collect()
+    }
+
+    /// Returns the scopes that are currently being visited, starting with the
+    /// root scope.
+    fn active_path_ids(&self) -> Vec<ScopeId> {
+        let mut path = Vec::new();
+        let mut scope = self.current;
+
+        while let Some(current) = scope {
+            path.push(current);
+            scope = self.scopes[current].pred;
+        }
+
+        path.reverse();
+        path
+    }
+
+    /// Take a snapshot of the scope tree. If `include_active` is true, the
+    /// time spent so far in scopes that are currently being visited is
+    /// included in their statistics.
+    fn snapshot(&self, include_active: bool) -> ProfileSnapshot {
+        let now = Instant::now();
+
+        ProfileSnapshot {
+            total_duration: now.duration_since(self.start_time),
+            metadata: self.metadata.clone(),
+            overhead: if self.measure_overhead {
+                Some(Duration::from_nanos(self.overhead_ns))
+            } else {
+                None
+            },
+            roots: self.nest_in_thread_parent(
+                self.roots
+                    .iter()
+                    .map(|&root| {
+                        self.scopes[root]
+                            .stats(&self.scopes, if include_active { Some(now) } else { None })
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    /// A sink that can be inspected after handing it to the profiler.
+    #[derive(Clone, Default)]
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedSink {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_multiple_roots() {
+        super::reset();
+
+        for i in 0..=5 {
+            if i == 5 {
+                profile!("a");
+            }
+            {
+                profile!("b");
+            }
+        }
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+
+            assert_eq!(p.roots.len(), 2);
+
+            for &root in p.roots.iter() {
+                assert!(p.scopes[root].pred.is_none());
+                assert!(p.scopes[root].succs.is_empty());
+            }
+
+            assert_eq!(p.scopes[p.roots[0]].name, "b");
+            assert_eq!(p.scopes[p.roots[1]].name, "a");
+
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 6);
+            assert_eq!(p.scopes[p.roots[1]].num_calls, 1);
+        });
+    }
+
+    #[test]
+    fn test_succ_reuse() {
+        super::reset();
+
+        for i in 0..=5 {
+            profile!("a");
+            if i > 2 {
+                profile!("b");
+            }
+        }
+
+        assert_eq!(super::PROFILER.with(|p| p.borrow().roots.len()), 1);
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+
+            assert_eq!(p.roots.len(), 1);
+
+            let root = &p.scopes[p.roots[0]];
+            assert_eq!(root.name, "a");
+            assert!(root.pred.is_none());
+            assert_eq!(root.succs.len(), 1);
+            assert_eq!(root.num_calls, 6);
+
+            let succ = &p.scopes[root.succs[0]];
+            assert_eq!(succ.name, "b");
+            assert_eq!(succ.pred, Some(p.roots[0]));
+            assert!(succ.succs.is_empty());
+            assert_eq!(succ.num_calls, 3);
+        });
+    }
+
+    #[test]
+    fn test_reset_during_frame() {
+        super::reset();
+
+        for i in 0..=5 {
+            profile!("a");
+            profile!("b");
+            {
+                profile!("c");
+                if i == 5 {
+                    super::reset();
+
+                    // Only the active scopes are kept in the arena.
+                    super::PROFILER.with(|p| assert_eq!(p.borrow().scopes.len(), 3));
+                }
+
+                assert!(super::PROFILER.with(|p| p.borrow().current.is_some()));
+
+                profile!("d");
+            }
+        }
+
+        super::PROFILER.with(|p| assert!(p.borrow().current.is_none()));
+
+        // The active scopes have been split at the reset, so the rest of the
+        // frame is attributed to the new tree.
+        let snapshot = super::snapshot();
+        assert_eq!(snapshot.roots.len(), 1);
+        let a = &snapshot.roots[0];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.num_calls, 1);
+        let b = &a.children[0];
+        assert_eq!(b.num_calls, 1);
+        let c = &b.children[0];
+        assert_eq!(c.num_calls, 1);
+        assert_eq!(c.children[0].name, "d");
+        assert_eq!(c.children[0].num_calls, 1);
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_reset_splits_active_scopes() {
+        use std::time::Duration;
+
+        super::reset();
+
+        {
+            profile!("frame");
+            std::thread::sleep(Duration::from_millis(50));
+            super::reset();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let snapshot = super::snapshot();
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.num_calls, 1);
+        assert!(frame.duration_sum >= Duration::from_millis(5));
+        assert!(frame.duration_sum < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        super::reset();
+
+        for i in 0..3 {
+            profile!("a");
+            if i == 2 {
+                profile!("b");
+            }
+        }
+        {
+            profile!("c");
+        }
+
+        let num_scopes = super::PROFILER.with(|p| p.borrow().scopes.len());
+        super::reset_stats();
+
+        {
+            profile!("c");
+        }
+        {
+            profile!("a");
+        }
+
+        let snapshot = super::snapshot();
+        let names: Vec<_> = snapshot
+            .roots
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["a", "c"]);
+        assert_eq!(snapshot.roots[0].num_calls, 1);
+        assert_eq!(snapshot.roots[0].children[0].num_calls, 0);
+        assert_eq!(snapshot.roots[1].num_calls, 1);
+
+        super::PROFILER.with(|p| assert_eq!(p.borrow().scopes.len(), num_scopes));
+    }
+
+    #[test]
+    fn test_reset_stats_splits_active_scopes() {
+        use std::time::Duration;
+
+        super::reset();
+        {
+            profile!("frame");
+            {
+                profile!("physics");
+                std::thread::sleep(Duration::from_millis(20));
+                super::reset_stats();
+            }
+        }
+
+        let snapshot = super::snapshot();
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.num_calls, 1);
+        assert_eq!(frame.children[0].num_calls, 1);
+        assert!(frame.duration_sum <= snapshot.total_duration);
+        assert!(frame.children[0].duration_sum < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_to_string_delta() {
+        super::reset();
+
+        for _ in 0..3 {
+            profile!("a");
+            profile!("b");
+        }
+
+        let delta = super::to_string_delta();
+        assert!(delta.starts_with("# elapsed: "));
+        assert!(delta.contains("\na: "));
+        assert!(delta.contains("\n  b: "));
+
+        {
+            profile!("c");
+        }
+
+        let delta = super::to_string_delta();
+        assert!(delta.contains("\nc: "));
+        assert!(!delta.contains("a: "));
+        assert_eq!(super::to_string_delta().lines().count(), 1);
+
+        // The lifetime statistics are kept.
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 3);
+            assert_eq!(p.scopes[p.roots[1]].num_calls, 1);
+        });
+        assert!(super::to_string().contains("\nc: "));
+    }
+
+    #[test]
+    fn test_frame_scope() {
+        super::reset();
+        super::set_frame_scope("frame");
+
+        for i in 0..4 {
+            profile!("frame");
+            if i % 2 == 0 {
+                profile!("physics");
+            }
+        }
+
+        let mut out = Vec::new();
+        super::write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<_> = out.lines().skip(1).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(!lines[0].contains("/frame"));
+        assert!(lines[1].starts_with("  physics: "));
+        assert!(lines[1].ends_with("ms/frame"));
+        assert!(
+            lines[2].starts_with(
+                "frames: 4 <=8.3ms, 0 <=16.7ms, 0 <=33.3ms, 0 <=50ms, 0 <=100ms, 0 >100ms, \
+                 1% low: "
+            ),
+            "{}",
+            lines[2]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_idle() {
+        use std::time::Duration;
+
+        super::reset();
+
+        for _ in 0..3 {
+            {
+                profile!("frame");
+                profile!("physics");
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let snapshot = super::snapshot();
+        let frame = &snapshot.roots[0];
+        let idle_sum = frame.idle_sum.unwrap();
+        assert!(idle_sum >= Duration::from_millis(10), "{:?}", idle_sum);
+        assert!(idle_sum < snapshot.total_duration);
+        assert_eq!(frame.children[0].idle_sum, None);
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_fps() {
+        use std::time::Duration;
+
+        super::reset();
+        assert_eq!(super::fps("frame"), None);
+
+        {
+            profile!("frame");
+        }
+        assert_eq!(super::fps("frame"), None);
+
+        for _ in 0..4 {
+            profile!("frame");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        // Five entries, at least 15ms apart in total.
+        let fps = super::fps("frame").unwrap();
+        assert!(fps > 0.0 && fps <= 4.0 / 0.015, "{}", fps);
+
+        // Entries leave the window even if the root is not entered again.
+        std::thread::sleep(super::FPS_WINDOW);
+        assert_eq!(super::fps("frame"), None);
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_record_frames() {
+        super::reset();
+
+        let sink = SharedSink::default();
+        super::record_frames(sink.clone());
+
+        for i in 0..3 {
+            profile!("frame");
+            if i == 1 {
+                profile!("physics");
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+        super::stop_recording_frames();
+
+        {
+            profile!("frame");
+        }
+
+        let contents = sink.contents();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (i, line) in lines.iter().enumerate() {
+            assert!(line.starts_with(&format!("{{\"frame\":{},\"timestamp_secs\":", i)));
+            assert!(line.contains(",\"scopes\":{\"frame\":"));
+            assert!(line.ends_with("}}"));
+        }
+        assert!(!lines[0].contains("frame/physics"));
+        assert!(lines[1].contains(",\"frame/physics\":"));
+        assert!(!lines[2].contains("frame/physics"));
+    }
+
+    #[test]
+    fn test_profile_scoped() {
+        super::reset();
+
+        {
+            profile_scoped!("a");
+            profile_scoped!("prefix", "b");
+        }
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
+            assert_eq!(root.name, "coarse_prof::tests::a");
+            assert_eq!(p.scopes[root.succs[0]].name, "prefix::b");
+        });
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_anomaly() {
+        use std::time::Duration;
+
+        super::reset();
+
+        let anomalies = Rc::new(RefCell::new(Vec::new()));
+        let anomalies_clone = anomalies.clone();
+        super::on_anomaly(2, 3.0, move |anomaly| {
+            // Profiling within the callback must not panic.
+            profile!("callback");
+            anomalies_clone.borrow_mut().push(anomaly.clone());
+        });
+
+        for millis in &[1, 1, 1, 1, 10, 10] {
+            profile!("frame");
+            profile!("physics");
+            std::thread::sleep(Duration::from_millis(*millis));
+        }
+
+        let anomalies = anomalies.borrow();
+        let paths: Vec<_> = anomalies.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths, vec!["frame/physics", "frame"]);
+        assert_eq!(anomalies[0].num_calls, 6);
+        assert!(anomalies[0].recent_mean >= Duration::from_millis(10));
+        assert!(anomalies[0].long_term_mean < Duration::from_millis(4));
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert!(p.roots_by_name.contains_key("callback"));
+        });
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_hitch() {
+        use std::time::Duration;
+
+        super::reset();
+
+        let hitches = Rc::new(RefCell::new(Vec::new()));
+        let hitches_clone = hitches.clone();
+        super::on_hitch(Duration::from_millis(5), move |hitch| {
+            hitches_clone
+                .borrow_mut()
+                .push((hitch.path.clone(), hitch.frame));
+        });
+
+        for i in 0..3 {
+            profile!("frame");
+            profile!("physics");
+            if i == 1 {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        assert_eq!(
+            *hitches.borrow(),
+            vec![("frame/physics".to_string(), 1), ("frame".to_string(), 1)]
+        );
+
+        // A callback that stops the detection is not put back.
+        let hitches_clone = hitches.clone();
+        super::on_hitch(Duration::ZERO, move |_| {
+            hitches_clone.borrow_mut().clear();
+            super::stop_detecting_hitches();
+        });
+        {
+            profile!("frame");
+        }
+        assert_eq!(Rc::strong_count(&hitches), 1);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        super::reset();
+
+        let stuck = Arc::new(Mutex::new(Vec::new()));
+        let stuck_clone = stuck.clone();
+        {
+            profile!("frame");
+            super::start_watchdog(Duration::from_millis(20), move |scope| {
+                stuck_clone.lock().unwrap().push(scope.path.clone());
+            });
+
+            profile!("network/recv");
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        {
+            profile!("frame");
+        }
+        super::stop_watchdog();
+
+        assert_eq!(
+            *stuck.lock().unwrap(),
+            vec!["frame/network/recv".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_in_scope_of() {
+        super::reset();
+
+        let worker = {
+            profile!("frame");
+            profile!("physics");
+
+            let path = super::active_path();
+            let worker = std::thread::spawn(move || {
+                super::in_scope_of(&path, || {
+                    profile!("collisions");
+                });
+                super::snapshot()
+            });
+
+            super::in_scope_of(&["frame", "ai"], || {
+                profile!("pathfinding");
+            });
+            assert_eq!(super::active_path(), vec!["frame", "physics"]);
+
+            worker.join().unwrap()
+        };
+
+        let mut snapshot = super::snapshot();
+        snapshot.merge(&worker);
+
+        assert_eq!(snapshot.roots.len(), 1);
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.num_calls, 1);
+
+        let names: Vec<_> = frame
+            .children
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["physics", "ai"]);
+
+        let physics = &frame.children[0];
+        assert_eq!(physics.num_calls, 1);
+        assert_eq!(physics.children[0].name, "collisions");
+        assert_eq!(physics.children[0].num_calls, 1);
+
+        let ai = &frame.children[1];
+        assert_eq!(ai.num_calls, 0);
+        assert_eq!(ai.children[0].name, "pathfinding");
+        assert_eq!(ai.children[0].num_calls, 1);
+    }
+
+    #[test]
+    fn test_thread_parent() {
+        super::reset();
+
+        let loader = std::thread::spawn(|| {
+            super::set_thread_parent("background/asset_load");
+            {
+                profile!("decode");
+            }
+            super::reset();
+            {
+                profile!("decode");
+            }
+            super::snapshot()
+        });
+
+        {
+            profile!("frame");
+        }
+
+        let mut snapshot = super::snapshot();
+        snapshot.merge(&loader.join().unwrap());
+
+        let names: Vec<_> = snapshot
+            .roots
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["frame", "background"]);
+
+        let background = &snapshot.roots[1];
+        assert_eq!(background.num_calls, 0);
+        assert_eq!(background.children[0].name, "asset_load");
+
+        let decode = &background.children[0].children[0];
+        assert_eq!(decode.name, "decode");
+        assert_eq!(decode.num_calls, 1);
+    }
+
+    #[test]
+    fn test_add_reporter() {
+        super::reset();
+
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let reports_clone = reports.clone();
+        super::add_reporter(
+            super::Schedule::Frames(2),
+            move |snapshot: &super::ProfileSnapshot| {
+                reports_clone.borrow_mut().push(snapshot.roots[0].num_calls);
+            },
+        );
+
+        for _ in 0..5 {
+            profile!("frame");
+        }
+
+        // Reporters are invoked when entering a root scope.
+        assert_eq!(*reports.borrow(), vec![2, 4]);
+
+        super::remove_reporters();
+        for _ in 0..5 {
+            profile!("frame");
+        }
+        assert_eq!(reports.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_reporter_enters_scope() {
+        super::reset();
+
+        // Reporters run after the profiler has been released, so their
+        // scopes are recorded like any other.
+        let reports = Rc::new(RefCell::new(0));
+        let reports_clone = reports.clone();
+        super::add_reporter(
+            super::Schedule::Frames(1),
+            move |_: &super::ProfileSnapshot| {
+                profile!("report");
+                *reports_clone.borrow_mut() += 1;
+            },
+        );
+
+        for _ in 0..3 {
+            profile!("frame");
+        }
+        super::remove_reporters();
+
+        let snapshot = super::snapshot();
+        let roots: Vec<_> = snapshot
+            .roots
+            .iter()
+            .map(|scope| (scope.name.as_str(), scope.num_calls))
+            .collect();
+        assert_eq!(roots, vec![("frame", 3), ("report", 2)]);
+        assert_eq!(*reports.borrow(), 2);
+
+        // Reporters that remove themselves are not put back.
+        super::add_reporter(super::Schedule::Frames(1), |_: &super::ProfileSnapshot| {
+            super::remove_reporters();
+        });
+        for _ in 0..3 {
+            profile!("frame");
+        }
+        super::PROFILER.with(|p| assert!(p.borrow().reporters.is_empty()));
+    }
+
+    #[test]
+    fn test_spawn_reporter() {
+        super::reset();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = super::spawn_reporter(
+            std::time::Duration::new(0, 0),
+            move |snapshot: &super::ProfileSnapshot| {
+                let thread = std::thread::current().name().map(str::to_string);
+                sender.send((thread, snapshot.roots.len())).unwrap();
+            },
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            profile!("frame");
+        }
+        super::remove_reporters();
+        handle.join().unwrap();
+
+        let reports: Vec<_> = receiver.iter().collect();
+        let thread = Some("coarse_prof reporter".to_string());
+        assert_eq!(
+            reports,
+            vec![(thread.clone(), 0), (thread.clone(), 1), (thread, 1)]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "signal", unix))]
+    fn test_write_on_signal() {
+        super::reset();
+
+        let sink = SharedSink::default();
+        super::write_on_signal(sink.clone()).unwrap();
+
+        {
+            profile!("frame");
+        }
+        assert!(sink.contents().is_empty());
+
+        unsafe {
+            libc::raise(libc::SIGUSR1);
+        }
+        {
+            profile!("frame");
+        }
+        assert!(sink.contents().contains("\nframe: "), "{}", sink.contents());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        super::reset();
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let calls_clone = calls.clone();
+        super::subscribe("frame/*", 2, move |stats| {
+            calls_clone
+                .borrow_mut()
+                .push((stats.name.clone(), stats.num_calls));
+        });
+
+        for _ in 0..4 {
+            profile!("frame");
+            profile!("physics");
+            profile!("collisions");
+        }
+
+        assert_eq!(
+            *calls.borrow(),
+            vec![("physics".to_string(), 2), ("physics".to_string(), 4)]
+        );
+
+        super::unsubscribe_all();
+        {
+            profile!("frame");
+            profile!("physics");
+        }
+        assert_eq!(calls.borrow().len(), 2);
+
+        // Callbacks that unsubscribe themselves are not put back.
+        let calls_clone = calls.clone();
+        super::subscribe("frame", 1, move |_| {
+            calls_clone.borrow_mut().clear();
+            super::unsubscribe_all();
+        });
+        {
+            profile!("frame");
+        }
+        assert!(calls.borrow().is_empty());
+        assert_eq!(Rc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_max_backtrace() {
+        fn slow() {
+            profile!("slow");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        super::reset();
+        {
+            profile!("slow");
+        }
+        slow();
+
+        let snapshot = super::snapshot();
+        let backtrace = snapshot.roots[0].max_backtrace.as_ref().unwrap();
+        assert!(backtrace.to_string().contains("test_max_backtrace::slow"));
+
+        let mut out = Vec::new();
+        snapshot.write_json(&mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("\"max_backtrace\":"));
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_measure_overhead() {
+        super::reset();
+        assert!(super::snapshot().overhead.is_none());
+
+        super::set_measure_overhead(true);
+        for _ in 0..10 {
+            profile!("a");
+            profile!("b");
+        }
+        let snapshot = super::snapshot();
+        let overhead = snapshot.overhead.unwrap();
+        assert!(overhead > std::time::Duration::new(0, 0));
+        assert!(overhead < snapshot.total_duration);
+
+        let report = super::to_string();
+        assert!(report.lines().last().unwrap().starts_with("<profiler>: "));
+
+        super::set_measure_overhead(false);
+        super::reset();
+    }
+
+    #[test]
+    fn test_all_scope_paths() {
+        super::reset();
+        super::set_scope_warmup("c", 1);
+        {
+            profile!("a");
+            {
+                profile!("b");
+            }
+            {
+                profile!("c");
+            }
+            profile!("d");
+            assert_eq!(super::all_scope_paths(), vec!["a", "a/b", "a/c", "a/d"]);
+        }
+        {
+            profile!("e");
+        }
+        assert_eq!(
+            super::all_scope_paths(),
+            vec!["a", "a/b", "a/c", "a/d", "e"]
+        );
+        super::set_scope_warmup("c", 0);
+
+        super::reset();
+        assert!(super::all_scope_paths().is_empty());
+    }
+
+    #[test]
+    fn test_stable_ids() {
+        super::reset();
+        {
+            profile!("a");
+            profile!("b");
+        }
+        let snapshot = super::snapshot();
+        let a = &snapshot.roots[0];
+        let (a_id, b_id) = (a.id.unwrap(), a.children[0].id.unwrap());
+        assert_ne!(a_id, b_id);
+
+        let mut out = Vec::new();
+        snapshot.write_json(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(&format!(
+            "\"name\":\"b\",\"id\":{},\"parent_id\":{},",
+            b_id, a_id
+        )));
+
+        // Ids stay the same across resets and threads.
+        super::reset();
+        {
+            profile!("c");
+            profile!("b");
+        }
+        {
+            profile!("a");
+            profile!("b");
+        }
+        let snapshot = super::snapshot();
+        assert_eq!(snapshot.roots[1].id, Some(a_id));
+        assert_eq!(snapshot.roots[1].children[0].id, Some(b_id));
+        assert_ne!(snapshot.roots[0].children[0].id, Some(b_id));
+
+        let ids = std::thread::spawn(|| {
+            {
+                profile!("a");
+                profile!("b");
+            }
+            let snapshot = super::snapshot();
+            (snapshot.roots[0].id, snapshot.roots[0].children[0].id)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(ids, (Some(a_id), Some(b_id)));
+    }
+
+    #[test]
+    fn test_estimate_median() {
+        super::reset();
+        super::set_estimate_median(true);
+        for _ in 0..10 {
+            profile!("a");
+        }
+        super::set_estimate_median(false);
+
+        let snapshot = super::snapshot();
+        let a = &snapshot.roots[0];
+        let median = a.duration_median.unwrap();
+        assert!(median >= a.duration_min && median <= a.duration_max);
+        assert!(super::to_string().contains(" median"));
+    }
+
+    #[test]
+    fn test_half_life() {
+        use std::time::Duration;
+
+        let mut scope = super::Scope::new("a", None, 0);
+        scope.record_decayed(1000, Duration::from_secs(3600));
+        scope.record_decayed(3000, Duration::from_secs(3600));
+        let decayed = scope.decayed.as_ref().unwrap();
+        assert!((decayed.sum_ns / decayed.weight - 2000.0).abs() < 1.0);
+
+        // Without a half-life, only the last call counts.
+        scope.record_decayed(5000, Duration::new(0, 0));
+        let decayed = scope.decayed.as_ref().unwrap();
+        assert_eq!(decayed.sum_ns / decayed.weight, 5000.0);
+
+        super::reset();
+        super::set_half_life(Some(Duration::from_secs(3600)));
+        {
+            profile!("a");
+        }
+        assert!(super::snapshot().roots[0].decayed_mean.is_some());
+        assert!(super::to_string().contains(" decayed"));
+
+        super::set_half_life(None);
+        {
+            profile!("a");
+        }
+        assert!(super::snapshot().roots[0].decayed_mean.is_none());
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_windows() {
+        use std::time::Duration;
+
+        super::reset();
+        super::record_windows(Duration::from_millis(5), 2);
+
+        for i in 0..5 {
+            profile!("frame");
+            if i < 2 {
+                profile!("physics");
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        {
+            profile!("frame");
+        }
+
+        let windows = super::windows();
+        assert_eq!(windows.len(), 2);
+
+        let window = &windows[1];
+        assert!(window.total_duration >= Duration::from_millis(5));
+        assert_eq!(window.roots.len(), 1);
+        assert_eq!(window.roots[0].num_calls, 1);
+        assert!(window.roots[0].children.is_empty());
+
+        let mut out = Vec::new();
+        super::write(&mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("\nframe: "), "{}", report);
+        assert!(
+            report.lines().nth(1).unwrap().contains(", last window: "),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("\n  physics: ") && report.ends_with(", last window: no calls\n"),
+            "{}",
+            report
+        );
+
+        super::stop_recording_windows();
+        assert!(super::windows().is_empty());
+        let mut out = Vec::new();
+        super::write(&mut out).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("last window"));
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_history() {
+        use std::time::Duration;
+
+        super::reset();
+        super::record_history(Duration::from_millis(5), 2);
+
+        for i in 0..5 {
+            profile!("frame");
+            if i % 2 == 0 {
+                profile!("physics");
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        {
+            profile!("frame");
+        }
+
+        let history = super::history();
+        assert_eq!(history.samples.len(), 2);
+
+        let sample = &history.samples[1];
+        assert!(sample.start >= Duration::from_millis(5));
+        assert!(sample.duration >= Duration::from_millis(5));
+        let scopes: Vec<_> = sample
+            .scopes
+            .iter()
+            .map(|scope| (scope.path.as_str(), scope.num_calls))
+            .collect();
+        assert_eq!(scopes, vec![("frame", 1), ("frame/physics", 1)]);
+
+        super::stop_recording_history();
+        assert!(super::history().samples.is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "rusage", unix))]
+    fn test_rusage_out_of_order() {
+        super::reset();
+        super::set_measure_rusage(true);
+
+        let a = super::enter("a");
+        let b = super::enter("b");
+        drop(a);
+        drop(b);
+        super::set_measure_rusage(false);
+
+        let snapshot = super::snapshot();
+        let a = &snapshot.roots[0];
+        let b = &a.children[0];
+        assert_eq!(a.attrs.len(), super::rusage::NAMES.len());
+        assert_eq!(b.attrs.len(), super::rusage::NAMES.len());
+        assert!(a.attrs.iter().chain(&b.attrs).all(|attr| attr.count == 1));
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_lap() {
+        use std::time::Duration;
+
+        super::reset();
+        for _ in 0..2 {
+            let mut guard = super::enter("load");
+            guard.lap("parse");
+            std::thread::sleep(Duration::from_millis(1));
+            guard.lap("spawn");
+            {
+                profile!("entity");
+            }
+            guard.lap("parse");
+            let duration = guard.end();
+            assert!(duration >= Duration::from_millis(1));
+        }
+
+        let snapshot = super::snapshot();
+        let load = &snapshot.roots[0];
+        let children: Vec<_> = load
+            .children
+            .iter()
+            .map(|child| (child.name.as_str(), child.num_calls, child.children.len()))
+            .collect();
+        assert_eq!(children, vec![("parse", 4, 0), ("spawn", 2, 1)]);
+        assert!(load.children[0].duration_sum >= Duration::from_millis(2));
+        assert!(load.duration_sum >= load.children[0].duration_sum + load.children[1].duration_sum);
+
+        // The guard has left all of its sections.
+        super::PROFILER.with(|p| assert!(p.borrow().current.is_none()));
+
+        // Sections can not be entered while an inner guard is active.
+        super::reset();
+        {
+            let mut outer = super::enter("outer");
+            let inner = super::enter("inner");
+            outer.lap("section");
+            drop(inner);
+            outer.lap("after");
+        }
+
+        let snapshot = super::snapshot();
+        let children: Vec<_> = snapshot.roots[0]
+            .children
+            .iter()
+            .map(|child| (child.name.as_str(), child.children.len()))
+            .collect();
+        assert_eq!(children, vec![("inner", 0), ("after", 0)]);
+    }
+
+    #[test]
+    fn test_profile_wait() {
+        super::reset();
+        for _ in 0..2 {
+            profile!("frame");
+            {
+                crate::profile_wait!("vsync");
+            }
+            profile!("render");
+        }
+
+        let snapshot = super::snapshot();
+        let waits: Vec<_> = snapshot.roots[0]
+            .children
+            .iter()
+            .map(|child| (child.name.as_str(), child.wait, child.num_calls))
+            .collect();
+        assert_eq!(waits, vec![("vsync", true, 2), ("render", false, 2)]);
+        assert!(!snapshot.roots[0].wait);
+
+        let without_wait = snapshot.without_wait();
+        assert_eq!(without_wait.roots[0].children.len(), 1);
+        assert_eq!(
+            without_wait.roots[0].duration_sum,
+            snapshot.roots[0].duration_sum - snapshot.roots[0].children[0].duration_sum
+        );
+    }
+
+    #[test]
+    fn test_recent_durations() {
+        super::reset();
+        for _ in 0..super::NUM_RECENT_DURATIONS + 10 {
+            profile!("frame");
+            profile!("physics");
+        }
+
+        let snapshot = super::snapshot();
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.recent_durations.len(), super::NUM_RECENT_DURATIONS);
+        assert!(frame.children[0].recent_durations.is_empty());
+
+        let options = super::ReportOptions::new().sparkline(16);
+        let report = snapshot.report(&options).to_string();
+        assert_eq!(
+            report.lines().nth(1).unwrap().chars().count(),
+            "frame: ".len() + 16
+        );
+        assert!(frame.frame_lows.is_none());
+
+        // The frame scope keeps more durations for the lows.
+        super::reset();
+        super::set_frame_scope("frame");
+        for _ in 0..super::NUM_RECENT_DURATIONS + 10 {
+            profile!("frame");
+        }
+
+        let frame = &super::snapshot().roots[0];
+        assert_eq!(frame.recent_durations.len(), super::NUM_RECENT_DURATIONS);
+        assert_eq!(
+            frame.frame_lows.unwrap().num_frames,
+            super::NUM_RECENT_DURATIONS + 10
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_timeline() {
+        use std::time::Duration;
+
+        super::reset();
+        super::set_record_timeline(2);
+
+        for _ in 0..3 {
+            profile!("frame");
+            {
+                profile!("physics");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            {
+                profile!("render");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert_eq!(p.scopes[p.roots[0]].timeline.len(), 2);
+        });
+
+        let timeline = super::timeline().unwrap();
+        let rows: Vec<_> = timeline
+            .rows
+            .iter()
+            .map(|row| (row.name.as_str(), row.depth, row.spans.len()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![("frame", 0, 1), ("physics", 1, 1), ("render", 1, 1)]
+        );
+
+        // Only the last frame is included, and render runs after physics.
+        let (_, physics_end) = timeline.rows[1].spans[0];
+        let (render_start, render_end) = timeline.rows[2].spans[0];
+        assert!(physics_end <= render_start);
+        assert!(render_end <= timeline.duration);
+
+        let mut svg = Vec::new();
+        super::write_timeline_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert_eq!(svg.matches("<rect").count(), 3);
+
+        let mut svg = Vec::new();
+        super::write_timeline_lanes_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert_eq!(svg.matches("<rect").count(), 3);
+        assert!(svg.contains(">depth 1</text>"));
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_tracing_flame() {
+        use std::time::Duration;
+
+        super::reset();
+
+        {
+            profile!("a");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut out = Vec::new();
+        super::write_tracing_flame(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // The test harness names threads after the tests.
+        let prefix = format!(
+            "{:?}-tests::test_tracing_flame;a ",
+            std::thread::current().id()
+        );
+        assert!(out.starts_with(&prefix), "{}", out);
+    }
+
+    #[test]
+    fn test_report_every() {
+        use std::time::Duration;
+
+        super::reset();
+
+        let sink = SharedSink::default();
+        super::report_every(Duration::from_secs(0), sink.clone());
+
+        {
+            profile!("a");
+        }
+        assert!(!sink.contents().contains("a: "));
+
+        {
+            profile!("b");
+        }
+        super::stop_reporting();
+
+        let contents = sink.contents();
+        assert!(contents.contains("\na: "));
+        assert!(!contents.contains("b: "));
+        assert_eq!(super::PROFILER.with(|p| p.borrow().roots.len()), 2);
+    }
+
+    #[test]
+    fn test_report_and_reset_every() {
+        use std::time::Duration;
+
+        super::reset();
+
+        let sink = SharedSink::default();
+        super::report_and_reset_every(Duration::from_secs(0), sink.clone());
+
+        for _ in 0..3 {
+            profile!("a");
+        }
+        super::stop_reporting();
+
+        assert_eq!(sink.contents().matches("a: ").count(), 2);
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+
+            assert_eq!(p.roots.len(), 1);
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 1);
+        });
+    }
+
+    #[test]
+    fn test_print_on_exit() {
+        super::reset();
+
+        let sink = SharedSink::default();
+        {
+            let _print_on_exit = super::print_on_exit_to(sink.clone());
+
+            profile!("a");
+        }
+
+        assert!(sink.contents().contains("\na: "));
+    }
+
+    #[test]
+    fn test_snapshot_active_first_call() {
+        super::reset();
+
+        profile!("frame");
+        let snapshot = super::PROFILER.with(|p| p.borrow().snapshot(true));
+        let frame = &snapshot.roots[0];
+        let active_for = frame.active_for.unwrap();
+
+        assert_eq!(frame.num_calls, 1);
+        assert_eq!(frame.duration_sum, active_for);
+        assert_eq!(frame.duration_min, active_for);
+        assert_eq!(frame.duration_max, active_for);
+    }
+
+    #[test]
+    fn test_report_active() {
+        super::reset();
+
+        {
+            profile!("a");
+            {
+                profile!("b");
+            }
+            profile!("c");
+
+            let mut report = Vec::new();
+            super::PROFILER
+                .with(|p| {
+                    p.borrow()
+                        .write_with(&mut report, &super::ReportOptions::new().show_active(true))
+                })
+                .unwrap();
+            let report = String::from_utf8(report).unwrap();
+            let lines: Vec<_> = report.lines().skip(1).collect();
+
+            assert_eq!(lines.len(), 3);
+            assert!(lines[0].starts_with("a: ") && lines[0].contains("[active for "));
+            assert!(lines[1].starts_with("  b: ") && !lines[1].contains("[active"));
+            assert!(lines[2].starts_with("  c: ") && lines[2].contains("[active for "));
+
+            assert_eq!(super::active_path(), vec!["a", "c"]);
+        }
+
+        assert!(super::active_path().is_empty());
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
+
+            assert!(root.active_since.is_none());
+            assert!(p.scopes[root.succs[1]].active_since.is_none());
+        });
+    }
+
+    #[test]
+    fn test_warmup() {
+        super::reset();
+        super::set_warmup(2);
+        super::set_scope_warmup("b", 0);
+
+        for _ in 0..5 {
+            profile!("a");
+            profile!("b");
+        }
+
+        super::set_warmup(0);
+        super::PROFILER.with(|p| {
+            let mut p = p.borrow_mut();
+            p.scope_warmups.clear();
+
+            let root = &p.scopes[p.roots[0]];
+            assert_eq!(root.num_calls, 3);
+            assert_eq!(root.num_warmup_calls, 2);
+            assert_eq!(p.scopes[root.succs[0]].num_calls, 5);
+        });
+    }
+
+    #[test]
+    fn test_metadata() {
+        super::reset();
+        super::set_metadata("map", "dust2");
+        super::set_metadata("build", "abc");
+        super::set_metadata("map", "inferno");
+
+        let snapshot = super::snapshot();
+        super::clear_metadata();
+
+        assert_eq!(
+            snapshot.metadata,
+            vec![
+                ("map".to_string(), "inferno".to_string()),
+                ("build".to_string(), "abc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guard_end() {
+        super::reset();
+
+        let a = super::enter("a");
+        let b = super::enter("b");
+        let duration = b.end();
+        assert_eq!(super::active_path(), vec!["a"]);
+        a.end();
+        assert!(super::active_path().is_empty());
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
+
+            assert_eq!(root.num_calls, 1);
+            assert_eq!(p.scopes[root.succs[0]].num_calls, 1);
+            assert_eq!(
+                p.scopes[root.succs[0]].duration_sum_ns,
+                duration.as_nanos() as u64
+            );
+        });
+    }
+
+    #[test]
+    fn test_guard_out_of_order() {
+        super::reset();
+
+        let a = super::enter("a");
+        let b = super::enter("b");
+        drop(a);
+        assert!(super::active_path().is_empty());
+
+        {
+            profile!("c");
+        }
+        drop(b);
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert_eq!(p.roots.len(), 2);
+            assert!(p.current.is_none());
+
+            let a = &p.scopes[p.roots[0]];
+            let b = &p.scopes[a.succs[0]];
+            assert_eq!((a.name, a.num_calls), ("a", 1));
+            assert_eq!((b.name, b.num_calls), ("b", 1));
+            assert!(b.active_since.is_none());
+            assert_eq!(p.scopes[p.roots[1]].name, "c");
+        });
+    }
+
+    #[test]
+    fn test_enter_owned() {
+        super::reset();
+
+        for i in 0..2 {
+            let _a = super::enter_owned(format!("enemy_{}", i % 2));
+            let _b = super::enter_owned(format!("enemy_{}", 0));
+        }
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let names: Vec<_> = p.roots.iter().map(|&id| p.scopes[id].name).collect();
+            assert_eq!(names, vec!["enemy_0", "enemy_1"]);
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 1);
+            assert_eq!(p.scopes[p.scopes[p.roots[1]].succs[0]].name, "enemy_0");
+        });
+    }
+
+    #[test]
+    fn test_enter_owned_copies_new_names_only() {
+        super::reset();
+        super::set_max_dynamic_children(Some(1));
+
+        {
+            profile!("owned_existing");
+        }
+        for _ in 0..2 {
+            let _existing = super::enter_owned("owned_existing".to_string());
+            let _new = super::enter_owned("owned_new".to_string());
+        }
+        {
+            let _existing = super::enter_owned("owned_existing".to_string());
+            let _folded = super::enter_owned("owned_folded".to_string());
+        }
+
+        assert!(!super::intern::is_interned("owned_existing"));
+        assert!(super::intern::is_interned("owned_new"));
+        assert!(!super::intern::is_interned("owned_folded"));
+
+        let snapshot = super::snapshot();
+        let names: Vec<_> = snapshot.roots[0]
+            .children
+            .iter()
+            .map(|scope| (scope.name.as_str(), scope.num_calls))
+            .collect();
+        assert_eq!(names, vec![("owned_new", 2), ("<other>", 1)]);
+    }
+
+    #[test]
+    fn test_enter_name_cache() {
+        super::reset();
+
+        let outer = super::ScopeName::new("cached_outer");
+        let inner = super::ScopeName::new("cached_inner");
+        for _ in 0..3 {
+            let _outer = super::enter_name(outer);
+            let _recursive = super::enter_name(outer);
+            let _inner = super::enter_name(inner);
+        }
+        {
+            let _inner = super::enter_name(inner);
+        }
+
+        let snapshot = super::snapshot();
+        let outer = &snapshot.roots[0];
+        assert_eq!((outer.name.as_str(), outer.num_calls), ("cached_outer", 3));
+        assert_eq!(outer.recursions, 3);
+        assert_eq!(outer.children[0].num_calls, 3);
+        assert_eq!(snapshot.roots[1].name, "cached_inner");
+
+        // Resetting clears the cache along with the scopes.
+        super::reset();
+        {
+            let _inner = super::enter_name(inner);
+        }
+        assert_eq!(super::snapshot().roots[0].name, "cached_inner");
+    }
+
+    #[test]
+    fn test_recursion() {
+        fn visit(depth: usize) {
+            profile!("visit");
+            {
+                profile!("leaf");
+            }
+            if depth > 0 {
+                visit(depth - 1);
+            }
+        }
+
+        super::reset();
+        visit(3);
+
+        let snapshot = super::snapshot();
+        let visit = &snapshot.roots[0];
+        assert_eq!(visit.name, "visit");
+        assert_eq!(visit.num_calls, 1);
+        assert_eq!(visit.recursions, 3);
+        assert_eq!(visit.children.len(), 1);
+        assert_eq!(visit.children[0].num_calls, 4);
+        assert!(super::to_string().contains(", 3 recursions"));
+    }
+
+    #[test]
+    fn test_max_call_rate() {
+        super::reset();
+        super::set_max_call_rate(Some(100.0));
+
+        {
+            profile!("frame");
+            for _ in 0..200 {
+                profile!("hot");
+            }
+            for _ in 0..10 {
+                profile!("cold");
+            }
+        }
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let warned: Vec<_> = p
+                .scopes
+                .iter()
+                .map(|scope| (scope.name, scope.warned_call_rate))
+                .collect();
+            assert_eq!(
+                warned,
+                vec![("frame", false), ("hot", true), ("cold", false)]
+            );
+        });
+    }
+
+    #[test]
+    fn test_max_dynamic_children() {
+        super::reset();
+        super::set_max_dynamic_children(Some(2));
+
+        for _ in 0..2 {
+            profile!("frame");
+            profile!("render");
+            for i in 0..4 {
+                let _guard = super::enter_owned(format!("entity_{}/update", i));
+            }
+            let _guard = super::enter_owned("entity_0".to_string());
+        }
+
+        let snapshot = super::snapshot();
+        let names: Vec<_> = snapshot.roots[0].children[0]
+            .children
+            .iter()
+            .map(|scope| (scope.name.as_str(), scope.num_calls))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("entity_0", 4), ("entity_1", 2), ("<other>", 4)]
+        );
+        assert!(snapshot.roots[0].children[0].children[2]
+            .children
+            .is_empty());
+    }
+
+    #[test]
+    fn test_channel() {
+        super::reset();
 
-    /// Parent scope in the tree. Root scopes have no parent.
-    pred: Option<Rc<RefCell<Scope>>>,
+        let editor = super::channel("editor");
+        {
+            profile!("frame");
+            let _gizmos = editor.enter("gizmos");
+            profile!("physics");
+        }
 
-    /// Child scopes in the tree.
-    succs: Vec<Rc<RefCell<Scope>>>,
+        let names = |snapshot: super::ProfileSnapshot| -> Vec<String> {
+            snapshot.roots.into_iter().map(|scope| scope.name).collect()
+        };
+        assert_eq!(names(super::snapshot()), vec!["frame"]);
+        assert_eq!(names(editor.snapshot()), vec!["gizmos"]);
+        assert_eq!(super::snapshot().roots[0].children[0].name, "physics");
 
-    /// How often has this scope been visited?
-    num_calls: usize,
+        // Channels are looked up by name.
+        super::channel("editor").reset();
+        assert!(editor.snapshot().roots.is_empty());
+        assert_eq!(names(super::snapshot()), vec!["frame"]);
 
-    /// In total, how much time has been spent in this scope?
-    duration_sum: Duration,
+        // Interned names are entered in the channel as well.
+        drop(editor.enter_name(super::ScopeName::new("picking")));
+        assert_eq!(names(editor.snapshot()), vec!["picking"]);
+        assert_eq!(names(super::snapshot()), vec!["frame"]);
+        editor.reset();
 
-    /// Minimal duration spent in this scope.
-    duration_min: Duration,
+        // Using a channel while it is in use is misuse, but does not panic.
+        struct EnteringWriter(super::Channel);
 
-    /// Maximal duration spent in this scope.
-    duration_max: Duration,
-}
+        impl io::Write for EnteringWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                drop(self.0.enter("write"));
+                Ok(buf.len())
+            }
 
-impl Scope {
-    fn new(name: &'static str, pred: Option<Rc<RefCell<Scope>>>) -> Scope {
-        Scope {
-            name,
-            pred,
-            succs: Vec::new(),
-            num_calls: 0,
-            duration_sum: Duration::new(0, 0),
-            duration_min: Duration::new(u64::MAX, u32::MIN),
-            duration_max: Duration::new(0, 0),
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
         }
-    }
 
-    /// Enter this scope. Returns a `Guard` instance that should be dropped
-    /// when leaving the scope.
-    fn enter(&mut self) -> Guard {
-        Guard::enter()
+        {
+            let _gizmos = editor.enter("gizmos");
+        }
+        editor.write(&mut EnteringWriter(editor.clone())).unwrap();
+        assert_eq!(names(editor.snapshot()), vec!["gizmos"]);
     }
 
-    /// Leave this scope. Called automatically by the `Guard` instance.
-    fn leave(&mut self, duration: Duration) {
-        self.num_calls += 1;
+    #[test]
+    fn test_measure() {
+        super::reset();
 
-        // Even though this is extremely unlikely, let's not panic on overflow.
-        let duration_sum = self.duration_sum.checked_add(duration);
-        self.duration_sum = duration_sum.unwrap_or(Duration::from_millis(0));
+        let (value, duration) = measure!("a", {
+            profile!("b");
+            42
+        });
 
-        self.duration_min = self.duration_min.min(duration);
-        self.duration_max = self.duration_max.max(duration);
-    }
+        assert_eq!(value, 42);
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
 
-    fn write_recursive<W: io::Write>(
-        &self,
-        out: &mut W,
-        total_duration: Duration,
-        depth: usize,
-    ) -> io::Result<()> {
-        let total_duration_secs = total_duration.as_secs_f64();
-        let duration_sum_secs = self.duration_sum.as_secs_f64();
-        let pred_sum_secs = self.pred.clone().map_or(total_duration_secs, |pred| {
-            pred.borrow().duration_sum.as_secs_f64()
+            assert_eq!(root.name, "a");
+            assert_eq!(root.duration_sum_ns, duration.as_nanos() as u64);
+            assert_eq!(p.scopes[root.succs[0]].name, "b");
         });
+    }
 
-        let percent = duration_sum_secs / pred_sum_secs * 100.0;
+    #[test]
+    fn test_guard_holds_profiler() {
+        super::reset();
 
-        // Write self
-        for _ in 0..depth {
-            write!(out, "  ")?;
-        }
-        writeln!(
-            out,
-            "{}: {:3.2}%, {:>4.2}ms avg, {:>4.2}ms min, {:>4.2}ms max @ {:.2}Hz",
-            self.name,
-            percent,
-            duration_sum_secs * 1000.0 / (self.num_calls as f64),
-            self.duration_min.as_secs_f64() * 1000.0,
-            self.duration_max.as_secs_f64() * 1000.0,
-            self.num_calls as f64 / total_duration_secs,
-        )?;
+        let guard = super::enter("a");
+        super::PROFILER.with(|p| {
+            assert!(std::rc::Rc::ptr_eq(guard.profiler.as_ref().unwrap(), p));
+        });
+        guard.end();
 
-        // Write children
-        for succ in &self.succs {
-            succ.borrow()
-                .write_recursive(out, total_duration, depth + 1)?;
-        }
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
 
-        Ok(())
+            assert!(p.current.is_none());
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 1);
+        });
     }
-}
 
-/// A guard that is created when entering a scope and dropped when leaving it.
-pub struct Guard {
-    enter_time: Instant,
-}
+    #[test]
+    fn test_wide_tree() {
+        const NAMES: [&str; 4] = ["w", "x", "y", "z"];
 
-impl Guard {
-    fn enter() -> Self {
-        Self {
-            enter_time: Instant::now(),
+        super::reset();
+
+        for _ in 0..3 {
+            profile!("a");
+            for &name in NAMES.iter() {
+                profile!(name);
+            }
         }
-    }
-}
 
-impl Drop for Guard {
-    fn drop(&mut self) {
-        let duration = self.enter_time.elapsed();
-        PROFILER.with(|p| p.borrow_mut().leave(duration));
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
+
+            assert_eq!(root.succs.len(), NAMES.len());
+            for (&succ, &name) in root.succs.iter().zip(NAMES.iter()) {
+                assert_eq!(p.scopes[succ].name, name);
+                assert_eq!(p.scopes[succ].num_calls, 3);
+                assert_eq!(root.succs_by_name[name], succ);
+            }
+        });
     }
-}
 
-/// A `Profiler` stores the scope tree and keeps track of the currently active
-/// scope.
-///
-/// Note that there is a global thread-local instance of `Profiler` in
-/// [`PROFILER`](constant.PROFILER.html), so it is not possible to manually
-/// create an instance of `Profiler`.
-pub struct Profiler {
-    roots: Vec<Rc<RefCell<Scope>>>,
-    current: Option<Rc<RefCell<Scope>>>,
-    start_time: Instant,
-}
+    #[test]
+    fn test_callsite_cache() {
+        super::reset();
 
-impl Profiler {
-    fn new() -> Profiler {
-        Profiler {
-            roots: Vec::new(),
-            current: None,
-            start_time: Instant::now(),
+        for i in 0..4 {
+            profile!("a");
+            for &name in ["x", "y"].iter() {
+                profile!(name);
+                if i % 2 == 0 {
+                    profile!("b");
+                }
+            }
         }
-    }
 
-    /// Enter a scope. Returns a [`Guard`](struct.Guard.html) that should be
-    /// dropped upon leaving the scope.
-    ///
-    /// Usually, this method will be called by the
-    /// [`profile`](macro.profile.html) macro, so it does not need to be used
-    /// directly.
-    pub fn enter(&mut self, name: &'static str) -> Guard {
-        // Check if we have already registered `name` at the current point in
-        // the tree.
-        let succ = if let Some(current) = self.current.as_ref() {
-            // We are currently in some scope.
-            let existing_succ = current
-                .borrow()
-                .succs
-                .iter()
-                .find(|succ| succ.borrow().name == name)
-                .cloned();
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            let root = &p.scopes[p.roots[0]];
 
-            existing_succ.unwrap_or_else(|| {
-                // Add new successor node to the current node.
-                let new_scope = Scope::new(name, Some(current.clone()));
-                let succ = Rc::new(RefCell::new(new_scope));
+            assert_eq!(p.scopes.len(), 5);
+            assert_eq!(root.num_calls, 4);
+            for &succ in root.succs.iter() {
+                let succ = &p.scopes[succ];
+                assert_eq!(succ.num_calls, 4);
+                assert_eq!(p.scopes[succ.succs[0]].num_calls, 2);
+            }
+        });
+    }
 
-                current.borrow_mut().succs.push(succ.clone());
+    #[test]
+    fn test_nested_path() {
+        super::reset();
 
-                succ
-            })
-        } else {
-            // We are currently not within any scope. Check if `name` already
-            // is a root.
-            let existing_root = self
-                .roots
-                .iter()
-                .find(|root| root.borrow().name == name)
-                .cloned();
+        for _ in 0..2 {
+            profile!("render");
+            {
+                profile!("shadows/cascade0");
+                assert_eq!(super::active_path(), vec!["render", "shadows", "cascade0"]);
+            }
+            profile!("shadows/cascade1");
+        }
+        assert!(super::active_path().is_empty());
 
-            existing_root.unwrap_or_else(|| {
-                // Add a new root node.
-                let new_scope = Scope::new(name, None);
-                let succ = Rc::new(RefCell::new(new_scope));
+        {
+            let _guard = super::enter("render/post");
+        }
 
-                self.roots.push(succ.clone());
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert_eq!(p.roots.len(), 1);
 
-                succ
-            })
-        };
+            let render = &p.scopes[p.roots[0]];
+            assert_eq!(render.num_calls, 3);
+            assert_eq!(render.succs.len(), 2);
 
-        let guard = succ.borrow_mut().enter();
+            let shadows = &p.scopes[render.succs[0]];
+            assert_eq!(shadows.name, "shadows");
 
-        self.current = Some(succ);
+            // Entering `shadows` again during the same call of `render`
+            // continues its previous call.
+            assert_eq!(shadows.num_calls, 2);
+            let cascades_ns: u64 = shadows
+                .succs
+                .iter()
+                .map(|&succ| p.scopes[succ].duration_sum_ns)
+                .sum();
+            assert!(shadows.duration_sum_ns >= cascades_ns);
 
-        guard
+            let names: Vec<_> = shadows
+                .succs
+                .iter()
+                .map(|&succ| (p.scopes[succ].name, p.scopes[succ].num_calls))
+                .collect();
+            assert_eq!(names, vec![("cascade0", 2), ("cascade1", 2)]);
+            assert_eq!(p.scopes[render.succs[1]].name, "post");
+        });
     }
 
-    /// Completely reset profiling data.
-    fn reset(&mut self) {
-        self.roots.clear();
-        self.start_time = Instant::now();
+    #[test]
+    fn test_mode() {
+        super::reset();
 
-        // Note that we could now still be anywhere in the previous profiling
-        // tree, so we can not simply reset `self.current`. However, as the
-        // frame comes to an end we will eventually leave a root node, at which
-        // point `self.current` will be set to `None`.
+        super::set_mode(super::Mode::Off);
+        {
+            profile!("a");
+            profile!("b");
+        }
+        let mut out = Vec::new();
+        super::write(&mut out).unwrap();
+        assert!(out.is_empty());
+        super::PROFILER.with(|p| assert!(p.borrow().scopes.is_empty()));
+
+        super::set_mode(super::Mode::Summary);
+        {
+            profile!("a");
+            profile!("b");
+        }
+        super::write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("a "));
+        assert!(out.contains("% > b "));
+        assert_eq!(out.lines().count(), 1);
     }
 
-    /// Leave the current scope.
-    fn leave(&mut self, duration: Duration) {
-        self.current = if let Some(current) = self.current.as_ref() {
-            current.borrow_mut().leave(duration);
+    #[test]
+    fn test_deterministic() {
+        fn run() -> String {
+            super::reset();
+            for i in 0..3 {
+                profile!("frame");
+                {
+                    profile!("render");
+                }
+                if i % 2 == 0 {
+                    profile!("physics");
+                    std::thread::sleep(std::time::Duration::from_micros(i * 100));
+                }
+            }
 
-            // Set current scope back to the parent node (if any).
-            current.borrow().pred.as_ref().cloned()
-        } else {
-            // This should not happen with proper usage.
-            log::error!("Called coarse_prof::leave() while not in any scope");
+            let options = super::ReportOptions::new()
+                .deterministic(true)
+                .tree_style(super::TreeStyle::Unicode)
+                .min_percent(50.0);
+            super::snapshot().report(&options).to_string()
+        }
 
-            None
-        };
+        let report = run();
+        assert_eq!(
+            report,
+            "frame: 3 calls\n\
+             ├─ physics: 2 calls\n\
+             └─ render: 3 calls\n"
+        );
+        assert_eq!(run(), report);
     }
 
-    fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
-        let total_duration = Instant::now().duration_since(self.start_time);
+    #[test]
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_configure() {
+        use super::{Config, Sort, Unit};
 
-        for root in self.roots.iter() {
-            root.borrow().write_recursive(out, total_duration, 0)?;
+        super::reset();
+        super::configure(
+            Config::new()
+                .sort(Sort::TotalTime)
+                .min_percent(10.0)
+                .time_unit(Unit::Micros)
+                .warmup(1),
+        );
+
+        for _ in 0..2 {
+            profile!("frame");
+            {
+                profile!("small");
+            }
+            {
+                profile!("large");
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            }
         }
 
-        out.flush()
+        let mut out = Vec::new();
+        super::write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<_> = out.lines().skip(1).collect();
+        assert_eq!(lines.len(), 2, "{}", out);
+        assert!(lines[0].starts_with("frame: "));
+        assert!(lines[0].contains("us avg"));
+        assert!(lines[1].starts_with("  large: "));
+
+        super::PROFILER.with(|p| {
+            let p = p.borrow();
+            assert_eq!(p.scopes[p.roots[0]].num_calls, 1);
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn test_multiple_roots() {
+    #[cfg_attr(
+        all(feature = "coarse-clock", not(feature = "cycles")),
+        ignore = "needs a clock that advances within the test"
+    )]
+    fn test_scope_enabled() {
         super::reset();
+        super::set_scope_enabled("frame/ai/**", false);
 
-        for i in 0..=5 {
-            if i == 5 {
-                profile!("a");
+        for i in 0..2 {
+            profile!("frame");
+            {
+                profile!("ai");
+                profile!("pathfinding");
+                if i == 0 {
+                    assert_eq!(super::active_path(), vec!["frame"]);
+                } else {
+                    assert_eq!(super::active_path(), vec!["frame", "ai"]);
+                }
             }
             {
-                profile!("b");
+                profile!("physics");
+                let guard = super::enter("debug_draw");
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                assert_eq!(guard.elapsed() == std::time::Duration::new(0, 0), i == 1);
+                assert_eq!(guard.end() == std::time::Duration::new(0, 0), i == 1);
             }
+
+            super::set_scope_enabled("**/debug_*", false);
+            super::set_scope_enabled("frame/ai", true);
         }
 
         super::PROFILER.with(|p| {
             let p = p.borrow();
+            let frame = &p.scopes[p.roots[0]];
+            let names: Vec<_> = frame
+                .succs
+                .iter()
+                .map(|&succ| (p.scopes[succ].name, p.scopes[succ].num_calls))
+                .collect();
+            assert_eq!(names, vec![("ai", 1), ("physics", 2)]);
 
-            assert_eq!(p.roots.len(), 2);
+            let physics = &p.scopes[frame.succs[1]];
+            assert_eq!(p.scopes[physics.succs[0]].num_calls, 1);
 
-            for root in p.roots.iter() {
-                assert!(root.borrow().pred.is_none());
-                assert!(root.borrow().succs.is_empty());
-            }
+            let ai = &p.scopes[frame.succs[0]];
+            assert_eq!(p.scopes[ai.succs[0]].num_calls, 0);
+            assert_eq!(p.muted_depth, 0);
+        });
+    }
 
-            assert_eq!(p.roots[0].borrow().name, "b");
-            assert_eq!(p.roots[1].borrow().name, "a");
+    #[test]
+    fn test_attr() {
+        super::reset();
 
-            assert_eq!(p.roots[0].borrow().num_calls, 6);
-            assert_eq!(p.roots[1].borrow().num_calls, 1);
-        });
+        for &n in [3.0, 5.0, 10.0].iter() {
+            profile!("update");
+            super::attr("entities", n);
+        }
+        super::attr("ignored", 1.0);
+
+        let snapshot = super::snapshot();
+        let attrs = &snapshot.roots[0].attrs;
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(attrs[0].key, "entities");
+        assert_eq!(attrs[0].count, 3);
+        assert_eq!(attrs[0].mean(), 6.0);
+        assert_eq!(attrs[0].min, 3.0);
+        assert_eq!(attrs[0].max, 10.0);
+
+        assert!(snapshot
+            .to_string()
+            .ends_with(", entities: 6.00 avg, 3.00 min, 10.00 max\n"));
+
+        let mut json = Vec::new();
+        snapshot.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .contains("\"attrs\":{\"entities\":{\"count\":3,\"mean\":6,\"min\":3,\"max\":10}}"));
     }
 
     #[test]
-    fn test_succ_reuse() {
-        use std::ptr;
+    #[cfg(all(feature = "cpu-time", unix))]
+    fn test_cpu_time() {
+        super::reset();
+        super::set_measure_cpu_time(true);
+
+        {
+            profile!("sleep");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let snapshot = super::snapshot();
+        let root = &snapshot.roots[0];
+        assert!(root.cpu_time_sum.unwrap() < root.duration_sum / 2);
+        assert!(snapshot.to_string().contains("ms cpu"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    fn test_perf_counters() {
+        use super::PerfCounter;
 
         super::reset();
+        if super::set_perf_counters(&[PerfCounter::Instructions]).is_err() {
+            // Counters are often not available in containers.
+            return;
+        }
 
-        for i in 0..=5 {
-            profile!("a");
-            if i > 2 {
-                profile!("b");
-            }
+        {
+            profile!("sum");
+            assert!((0..10_000u64).map(std::hint::black_box).sum::<u64>() > 0);
         }
+        super::set_perf_counters(&[]).unwrap();
 
-        assert_eq!(super::PROFILER.with(|p| p.borrow().roots.len()), 1);
+        let snapshot = super::snapshot();
+        let attrs = &snapshot.roots[0].attrs;
+        assert_eq!(attrs[0].key, "instructions");
+        assert!(attrs[0].min > 10_000.0);
+    }
 
-        super::PROFILER.with(|p| {
-            let p = p.borrow();
+    #[test]
+    #[cfg(all(feature = "rusage", unix))]
+    fn test_rusage() {
+        super::reset();
+        super::set_measure_rusage(true);
 
-            assert_eq!(p.roots.len(), 1);
+        {
+            profile!("sleep");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
 
-            let root = p.roots[0].borrow();
-            assert_eq!(root.name, "a");
-            assert!(root.pred.is_none());
-            assert_eq!(root.succs.len(), 1);
-            assert_eq!(root.num_calls, 6);
+        let snapshot = super::snapshot();
+        let keys: Vec<_> = snapshot.roots[0]
+            .attrs
+            .iter()
+            .map(|attr| attr.key.as_str())
+            .collect();
+        assert_eq!(keys, super::rusage::NAMES);
 
-            let succ = root.succs[0].borrow();
-            assert_eq!(succ.name, "b");
-            assert!(ptr::eq(
-                succ.pred.as_ref().unwrap().as_ref(),
-                p.roots[0].as_ref()
-            ));
-            assert!(succ.succs.is_empty());
-            assert_eq!(succ.num_calls, 3);
-        });
+        // Sleeping requires giving up the CPU.
+        #[cfg(target_os = "linux")]
+        assert!(snapshot.roots[0].attrs[0].max >= 1.0);
     }
 
+    #[cfg(feature = "alloc")]
+    #[global_allocator]
+    static GLOBAL: super::CoarseProfAlloc = super::CoarseProfAlloc(std::alloc::System);
+
     #[test]
-    fn test_reset_during_frame() {
+    #[cfg(feature = "alloc")]
+    fn test_alloc() {
+        // Make sure that the allocator has been used.
+        drop(vec![0u8; 1]);
         super::reset();
+        super::set_measure_allocs(true);
 
-        for i in 0..=5 {
-            profile!("a");
-            profile!("b");
-            {
-                profile!("c");
-                if i == 5 {
-                    super::reset();
-                }
+        for _ in 0..2 {
+            profile!("alloc");
+            let v = std::hint::black_box(vec![0u8; 1000]);
+            drop(v);
+        }
 
-                assert!(super::PROFILER.with(|p| p.borrow().current.is_some()));
+        let snapshot = super::snapshot();
+        let attrs = &snapshot.roots[0].attrs;
+        assert_eq!(attrs[0].key, "allocs");
+        assert!(attrs[0].min >= 1.0);
+        assert_eq!(attrs[1].key, "alloc_bytes");
+        assert!(attrs[1].min >= 1000.0);
+    }
 
-                profile!("d");
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn test_publish_metrics() {
+        use std::sync::{Arc, Mutex};
+
+        /// A recorder that only keeps the values of histograms.
+        #[derive(Default)]
+        struct Recorder(Arc<Mutex<Vec<(String, f64)>>>);
+
+        struct Histogram(String, Arc<Mutex<Vec<(String, f64)>>>);
+
+        impl metrics::HistogramFn for Histogram {
+            fn record(&self, value: f64) {
+                self.1.lock().unwrap().push((self.0.clone(), value));
             }
         }
 
-        super::PROFILER.with(|p| {
-            let p = p.borrow();
+        impl metrics::Recorder for Recorder {
+            fn describe_counter(
+                &self,
+                _: metrics::KeyName,
+                _: Option<metrics::Unit>,
+                _: metrics::SharedString,
+            ) {
+            }
+            fn describe_gauge(
+                &self,
+                _: metrics::KeyName,
+                _: Option<metrics::Unit>,
+                _: metrics::SharedString,
+            ) {
+            }
+            fn describe_histogram(
+                &self,
+                _: metrics::KeyName,
+                _: Option<metrics::Unit>,
+                _: metrics::SharedString,
+            ) {
+            }
 
-            assert!(p.roots.is_empty());
-            assert!(p.current.is_none());
+            fn register_counter(
+                &self,
+                _: &metrics::Key,
+                _: &metrics::Metadata,
+            ) -> metrics::Counter {
+                metrics::Counter::noop()
+            }
+
+            fn register_gauge(&self, _: &metrics::Key, _: &metrics::Metadata) -> metrics::Gauge {
+                metrics::Gauge::noop()
+            }
+
+            fn register_histogram(
+                &self,
+                key: &metrics::Key,
+                _: &metrics::Metadata,
+            ) -> metrics::Histogram {
+                metrics::Histogram::from_arc(Arc::new(Histogram(
+                    key.name().to_string(),
+                    self.0.clone(),
+                )))
+            }
+        }
+
+        super::reset();
+        super::set_publish_metrics(true);
+
+        let recorder = Recorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            for _ in 0..2 {
+                profile!("frame");
+                profile!("physics");
+            }
         });
+
+        let values = recorder.0.lock().unwrap();
+        let names: Vec<_> = values.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["frame/physics", "frame", "frame/physics", "frame"]
+        );
+        assert!(values.iter().all(|&(_, value)| value >= 0.0));
+    }
+
+    #[test]
+    fn test_variance() {
+        let mut scope = super::Scope::new("a", None, 0);
+        for &duration_ns in [2_000_000, 4_000_000, 6_000_000].iter() {
+            scope.leave(duration_ns, None, 0);
+        }
+
+        let stats = scope.stats(&[], None);
+        assert!((stats.duration_variance.unwrap() - 4e-6).abs() < 1e-15);
+        assert_eq!(
+            stats.duration_std_dev(),
+            Some(std::time::Duration::from_millis(2))
+        );
+    }
+
+    #[test]
+    fn test_sum_saturates() {
+        let mut scope = super::Scope::new("a", None, 0);
+        scope.duration_sum_ns = u64::MAX - 1;
+
+        scope.leave(10, None, 0);
+
+        assert_eq!(scope.num_calls, 1);
+        assert_eq!(scope.duration_sum_ns, u64::MAX);
     }
 }
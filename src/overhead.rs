@@ -0,0 +1,232 @@
+//! Estimation of the cost of profiling itself.
+
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::ScopeStats;
+
+/// Number of calls that are measured for estimating the overhead.
+const NUM_CALLS: u32 = 10_000;
+
+/// Returns the estimated cost of entering and leaving a scope once.
+///
+/// The cost is measured once per process, on a separate thread, so that the
+/// tree of the calling thread is not affected, and cached afterwards. It
+/// covers a scope that is nested below a root scope, which is the common
+/// case. The benchmarks in the `benches` directory of the repository measure
+/// the cost for other shapes of the tree in more detail.
+///
+/// With the `coarse-clock` feature, the cost is far below the resolution of
+/// the clock and is not measured, so the estimate is zero.
+///
+/// Multiplying the estimate with the number of calls of a scope gives an idea
+/// of how much of its duration is caused by the profiler, which
+/// [`ReportOptions::show_overhead`](struct.ReportOptions.html#method.show_overhead)
+/// does for the whole report.
+///
+/// # Example
+///
+/// ```
+/// let overhead = coarse_prof::estimated_overhead();
+/// println!("entering a scope costs about {:?}", overhead);
+/// ```
+pub fn estimated_overhead() -> Duration {
+    static OVERHEAD: OnceLock<Duration> = OnceLock::new();
+
+    *OVERHEAD.get_or_init(|| on_separate_thread(measure_enter_leave).unwrap_or_default())
+}
+
+/// The measured cost of reading the clock and of entering and leaving a
+/// scope, which is subtracted from the durations of scopes in calibrated
+/// means.
+///
+/// Created by [`calibrate`](fn.calibrate.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    /// Cost of reading the clock once.
+    pub timer: Duration,
+
+    /// Cost of entering and leaving an empty scope.
+    pub enter_leave: Duration,
+}
+
+impl Calibration {
+    /// Returns the average duration of `scope` per call, minus the cost of
+    /// reading the clock and the cost of entering and leaving the
+    /// descendants of `scope`, which are included in its measured
+    /// durations. Returns zero if the scope has not been called.
+    pub fn calibrated_mean(&self, scope: &ScopeStats) -> Duration {
+        fn num_calls(scopes: &[ScopeStats]) -> u128 {
+            scopes
+                .iter()
+                .map(|scope| scope.num_calls as u128 + num_calls(&scope.children))
+                .sum()
+        }
+
+        if scope.num_calls == 0 {
+            return Duration::new(0, 0);
+        }
+
+        // Compute in nanoseconds, since the number of calls can exceed `u32`.
+        let overhead = self
+            .timer
+            .as_nanos()
+            .saturating_mul(scope.num_calls as u128)
+            .saturating_add(
+                self.enter_leave
+                    .as_nanos()
+                    .saturating_mul(num_calls(&scope.children)),
+            );
+        let mean = scope.duration_sum.as_nanos().saturating_sub(overhead) / scope.num_calls as u128;
+        Duration::new((mean / 1_000_000_000) as u64, (mean % 1_000_000_000) as u32)
+    }
+}
+
+/// The calibration stored by `calibrate`.
+static CALIBRATION: Mutex<Option<Calibration>> = Mutex::new(None);
+
+/// Measure the cost of reading the clock and of entering and leaving an
+/// empty scope, and store it for showing calibrated means in reports, see
+/// [`ReportOptions::show_calibrated`](struct.ReportOptions.html#method.show_calibrated).
+///
+/// Every measured duration includes the time of reading the clock, and the
+/// durations of scopes with many children also include the cost of entering
+/// and leaving the children. Subtracting these costs gives more accurate
+/// means for short scopes. The measurement takes a few milliseconds and is
+/// done on a separate thread, so that the tree of the calling thread is not
+/// affected. Calling this again measures again, e.g. after the CPU frequency
+/// has settled.
+///
+/// With the `coarse-clock` feature, both costs are far below the resolution
+/// of the clock and are not measured. They are zero, so calibrated means
+/// equal the plain means.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::ReportOptions;
+///
+/// let calibration = coarse_prof::calibrate();
+/// println!("reading the clock costs {:?}", calibration.timer);
+///
+/// let options = ReportOptions::new().show_calibrated(true);
+/// coarse_prof::write_with(&mut std::io::stdout(), &options).unwrap();
+/// ```
+pub fn calibrate() -> Calibration {
+    let calibration = on_separate_thread(|| Calibration {
+        timer: measure_timer(),
+        enter_leave: measure_enter_leave(),
+    })
+    .unwrap_or(Calibration {
+        timer: Duration::new(0, 0),
+        enter_leave: Duration::new(0, 0),
+    });
+
+    *CALIBRATION.lock().unwrap_or_else(PoisonError::into_inner) = Some(calibration);
+
+    calibration
+}
+
+/// Returns the calibration that has been stored by `calibrate`, if any.
+pub(crate) fn calibration() -> Option<Calibration> {
+    *CALIBRATION.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Run `f` on a new thread, so that its profiler is separate from the one of
+/// the current thread. Returns `None` if the thread can not be spawned.
+fn on_separate_thread<R: Send + 'static>(f: fn() -> R) -> Option<R> {
+    std::thread::Builder::new()
+        .name("coarse_prof overhead".to_string())
+        .spawn(f)
+        .ok()
+        .and_then(|handle| handle.join().ok())
+}
+
+/// Measure the cost of reading the clock.
+fn measure_timer() -> Duration {
+    if clock::IS_COARSE {
+        return Duration::new(0, 0);
+    }
+
+    let start = Instant::now();
+    let mut last = start;
+    for _ in 0..NUM_CALLS {
+        last = Instant::now();
+    }
+
+    last.duration_since(start) / NUM_CALLS
+}
+
+/// Measure the cost of entering and leaving a scope in the profiler of the
+/// current thread.
+fn measure_enter_leave() -> Duration {
+    if clock::IS_COARSE {
+        return Duration::new(0, 0);
+    }
+
+    let _root = crate::enter("root");
+
+    // Add the scope to the tree before measuring.
+    drop(crate::enter("scope"));
+
+    let start = Instant::now();
+    for _ in 0..NUM_CALLS {
+        let _guard = crate::enter("scope");
+    }
+
+    start.elapsed() / NUM_CALLS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Calibration;
+    use crate::profile;
+
+    #[test]
+    fn test_calibrated_mean() {
+        crate::reset();
+        for _ in 0..2 {
+            profile!("a");
+            for _ in 0..3 {
+                profile!("b");
+            }
+        }
+
+        let snapshot = crate::snapshot();
+        let a = &snapshot.roots[0];
+        let calibration = Calibration {
+            timer: Duration::from_nanos(1),
+            enter_leave: Duration::from_nanos(10),
+        };
+        assert_eq!(
+            calibration.calibrated_mean(a),
+            a.duration_sum.saturating_sub(Duration::from_nanos(2 + 60)) / 2
+        );
+
+        let huge = Calibration {
+            timer: Duration::from_secs(1),
+            enter_leave: Duration::new(0, 0),
+        };
+        assert_eq!(huge.calibrated_mean(a), Duration::new(0, 0));
+
+        // More calls than fit into `u32`.
+        let mut many = a.clone();
+        many.num_calls = 1 << 33;
+        many.duration_sum = Duration::from_nanos(3 << 33);
+        many.children.clear();
+        assert_eq!(calibration.calibrated_mean(&many), Duration::from_nanos(2));
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let calibration = super::calibrate();
+        assert_eq!(
+            calibration.enter_leave == Duration::new(0, 0),
+            crate::clock::IS_COARSE
+        );
+        assert_eq!(super::calibration(), Some(calibration));
+    }
+}
@@ -0,0 +1,58 @@
+//! Matching scope paths against glob patterns.
+
+/// Returns whether `path`, consisting of names separated by `/`, matches
+/// `pattern`.
+///
+/// In the pattern, `*` matches any part of a single name, and `**` matches
+/// any number of names, including none.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<_> = pattern.split('/').collect();
+    let path: Vec<_> = path.split('/').collect();
+
+    matches_names(&pattern, &path)
+}
+
+fn matches_names(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_names(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                matches_name(first.as_bytes(), name.as_bytes()) && matches_names(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| matches_name(rest, &name[skip..])),
+        Some((c, rest)) => name.first() == Some(c) && matches_name(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("frame/ai", "frame/ai"));
+        assert!(!matches("frame/ai", "frame/ai/path"));
+        assert!(!matches("frame/ai", "frame"));
+
+        assert!(matches("frame/ai/**", "frame/ai"));
+        assert!(matches("frame/ai/**", "frame/ai/path/astar"));
+        assert!(!matches("frame/ai/**", "frame/physics"));
+
+        assert!(matches("**/debug_*", "debug_draw"));
+        assert!(matches("**/debug_*", "frame/render/debug_draw"));
+        assert!(!matches("**/debug_*", "frame/render"));
+
+        assert!(matches("frame/*/update", "frame/ai/update"));
+        assert!(matches("*ai*", "ai"));
+        assert!(!matches("frame/*/update", "frame/ai/x/update"));
+    }
+}
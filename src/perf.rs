@@ -0,0 +1,146 @@
+//! Hardware performance counters via Linux' `perf_event_open`.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// A hardware counter that can be measured for every scope with
+/// [`set_perf_counters`](fn.set_perf_counters.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfCounter {
+    /// Number of retired instructions.
+    Instructions,
+
+    /// Number of cache misses, usually of the last level cache.
+    CacheMisses,
+
+    /// Number of mispredicted branches.
+    BranchMisses,
+}
+
+/// Maximal number of counters that can be measured at the same time.
+pub const MAX_COUNTERS: usize = 3;
+
+impl PerfCounter {
+    /// Name of the counter, which is used as the key of its attribute.
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfCounter::Instructions => "instructions",
+            PerfCounter::CacheMisses => "cache_misses",
+            PerfCounter::BranchMisses => "branch_misses",
+        }
+    }
+
+    fn config(self) -> u64 {
+        match self {
+            PerfCounter::Instructions => PERF_COUNT_HW_INSTRUCTIONS,
+            PerfCounter::CacheMisses => PERF_COUNT_HW_CACHE_MISSES,
+            PerfCounter::BranchMisses => PERF_COUNT_HW_BRANCH_MISSES,
+        }
+    }
+}
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// The first version of `struct perf_event_attr`, which is accepted by all
+/// kernels that support `perf_event_open`.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// Open counters of the current thread.
+pub struct Counters {
+    counters: Vec<(PerfCounter, RawFd)>,
+}
+
+impl Counters {
+    /// Start counting `counters` for the current thread.
+    pub fn open(counters: &[PerfCounter]) -> io::Result<Counters> {
+        if counters.len() > MAX_COUNTERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many perf counters",
+            ));
+        }
+
+        let mut result = Counters {
+            counters: Vec::new(),
+        };
+        for &counter in counters {
+            let attr = PerfEventAttr {
+                type_: PERF_TYPE_HARDWARE,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config: counter.config(),
+                flags: EXCLUDE_KERNEL | EXCLUDE_HV,
+                ..PerfEventAttr::default()
+            };
+
+            // Safety: `attr` is a valid `perf_event_attr` of the given size.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0 as libc::pid_t,
+                    -1 as libc::c_int,
+                    -1 as libc::c_int,
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                // Counters that have already been opened are closed on drop.
+                return Err(io::Error::last_os_error());
+            }
+
+            result.counters.push((counter, fd as RawFd));
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the counters that are being measured.
+    pub fn iter(&self) -> impl Iterator<Item = PerfCounter> + '_ {
+        self.counters.iter().map(|&(counter, _)| counter)
+    }
+
+    /// Read the current values of the counters, in the order in which they
+    /// have been opened.
+    pub fn read(&self) -> [u64; MAX_COUNTERS] {
+        let mut values = [0; MAX_COUNTERS];
+        for (value, &(_, fd)) in values.iter_mut().zip(self.counters.iter()) {
+            // Safety: `value` is valid for writing 8 bytes. If reading fails,
+            // the value stays zero.
+            unsafe {
+                libc::read(fd, value as *mut u64 as *mut libc::c_void, 8);
+            }
+        }
+        values
+    }
+}
+
+impl Drop for Counters {
+    fn drop(&mut self) {
+        for &(_, fd) in &self.counters {
+            // Safety: We own `fd`.
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Export to the protobuf format of [pprof](https://github.com/google/pprof).
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::clock;
+use crate::{ProfileSnapshot, ScopeStats};
+
+/// Wire type of varint fields.
+const VARINT: u64 = 0;
+
+/// Wire type of length-delimited fields.
+const LEN: u64 = 2;
+
+/// A protobuf message that is being encoded.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint((field << 3) | wire_type);
+    }
+
+    fn uint(&mut self, field: u64, value: u64) {
+        self.key(field, VARINT);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.key(field, LEN);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn packed(&mut self, field: u64, values: &[u64]) {
+        let mut packed = Message::default();
+        for &value in values {
+            packed.varint(value);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
+
+/// Builds the pprof profile of a snapshot.
+struct Builder {
+    profile: Message,
+    strings: HashMap<String, u64>,
+    string_table: Vec<String>,
+    functions: HashMap<String, u64>,
+}
+
+impl Builder {
+    fn string(&mut self, value: &str) -> u64 {
+        if let Some(&index) = self.strings.get(value) {
+            return index;
+        }
+
+        let index = self.string_table.len() as u64;
+        self.strings.insert(value.to_string(), index);
+        self.string_table.push(value.to_string());
+        index
+    }
+
+    fn value_type(&mut self, field: u64, type_: &str, unit: &str) {
+        let mut value_type = Message::default();
+        value_type.uint(1, self.string(type_));
+        value_type.uint(2, self.string(unit));
+        self.profile.bytes(field, &value_type.0);
+    }
+
+    /// Returns the id of the function and location of scopes called `name`.
+    fn function(&mut self, name: &str) -> u64 {
+        if let Some(&id) = self.functions.get(name) {
+            return id;
+        }
+
+        // Ids must be nonzero.
+        let id = self.functions.len() as u64 + 1;
+        self.functions.insert(name.to_string(), id);
+
+        let mut function = Message::default();
+        function.uint(1, id);
+        function.uint(2, self.string(name));
+        function.uint(3, self.string(name));
+        self.profile.bytes(5, &function.0);
+
+        let mut line = Message::default();
+        line.uint(1, id);
+        let mut location = Message::default();
+        location.uint(1, id);
+        location.bytes(4, &line.0);
+        self.profile.bytes(4, &location.0);
+
+        id
+    }
+
+    fn scope(&mut self, scope: &ScopeStats, stack: &mut Vec<u64>) {
+        let id = self.function(&scope.name);
+        stack.push(id);
+
+        // The stack is written starting with the innermost scope.
+        let locations: Vec<_> = stack.iter().rev().copied().collect();
+        let mut sample = Message::default();
+        sample.packed(1, &locations);
+        sample.packed(
+            2,
+            &[
+                scope.num_calls as u64,
+                clock::as_nanos(scope.self_duration()),
+            ],
+        );
+        self.profile.bytes(2, &sample.0);
+
+        for child in &scope.children {
+            self.scope(child, stack);
+        }
+
+        stack.pop();
+    }
+}
+
+/// Encode `snapshot` as a pprof profile.
+pub fn write<W: io::Write>(snapshot: &ProfileSnapshot, out: &mut W) -> io::Result<()> {
+    let mut builder = Builder {
+        profile: Message::default(),
+        strings: HashMap::new(),
+        string_table: Vec::new(),
+        functions: HashMap::new(),
+    };
+
+    // The first string must be empty.
+    builder.string("");
+
+    builder.value_type(1, "calls", "count");
+    builder.value_type(1, "time", "nanoseconds");
+
+    for root in &snapshot.roots {
+        builder.scope(root, &mut Vec::new());
+    }
+
+    builder
+        .profile
+        .uint(10, clock::as_nanos(snapshot.total_duration));
+    builder.value_type(11, "time", "nanoseconds");
+
+    for string in std::mem::take(&mut builder.string_table) {
+        builder.profile.bytes(6, string.as_bytes());
+    }
+
+    out.write_all(&builder.profile.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::snapshot::tests::stats;
+    use crate::ProfileSnapshot;
+
+    /// Decode the fields of a message as pairs of field number and either a
+    /// varint or bytes.
+    fn decode(mut bytes: &[u8]) -> Vec<(u64, Result<u64, Vec<u8>>)> {
+        fn varint(bytes: &mut &[u8]) -> u64 {
+            let mut value = 0;
+            let mut shift = 0;
+            loop {
+                let byte = bytes[0];
+                *bytes = &bytes[1..];
+                value |= u64::from(byte & 0x7f) << shift;
+                shift += 7;
+                if byte < 0x80 {
+                    return value;
+                }
+            }
+        }
+
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let value = if key & 7 == 0 {
+                Ok(varint(&mut bytes))
+            } else {
+                let len = varint(&mut bytes) as usize;
+                let value = bytes[..len].to_vec();
+                bytes = &bytes[len..];
+                Err(value)
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    #[test]
+    fn test_write_pprof() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![stats("physics", 4, Vec::new())])],
+        };
+
+        let mut bytes = Vec::new();
+        snapshot.write_pprof(&mut bytes).unwrap();
+        let fields = decode(&bytes);
+
+        let strings: Vec<_> = fields
+            .iter()
+            .filter(|(field, _)| *field == 6)
+            .map(|(_, value)| String::from_utf8(value.clone().unwrap_err()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        assert!(strings.contains(&"frame".to_string()));
+        assert!(strings.contains(&"physics".to_string()));
+
+        let samples: Vec<_> = fields
+            .iter()
+            .filter(|(field, _)| *field == 2)
+            .map(|(_, value)| decode(value.as_ref().unwrap_err()))
+            .collect();
+        assert_eq!(samples.len(), 2);
+
+        // The sample of `physics` has the stack `physics`, `frame`, two calls
+        // and 8ms of self time.
+        assert_eq!(samples[1][0].1, Err(vec![2, 1]));
+        let mut values = vec![2];
+        values.extend_from_slice(&[0x80, 0xa4, 0xe8, 0x03]);
+        assert_eq!(samples[1][1].1, Err(values));
+    }
+}
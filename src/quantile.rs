@@ -0,0 +1,150 @@
+//! Streaming estimation of quantiles.
+
+/// Estimates a quantile of a stream of values with the P² algorithm of Jain
+/// and Chlamtac, which keeps only five markers instead of all values.
+pub struct P2Quantile {
+    /// The quantile to estimate, between 0 and 1.
+    p: f64,
+
+    /// Number of values that have been added.
+    count: usize,
+
+    /// Heights of the markers. Until five values have been added, these are
+    /// the values themselves.
+    heights: [f64; 5],
+
+    /// Actual positions of the markers, starting at 1.
+    positions: [f64; 5],
+
+    /// Desired positions of the markers.
+    desired: [f64; 5],
+
+    /// How much the desired positions move with every value.
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    pub fn new(p: f64) -> P2Quantile {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell that contains `x`, extending the extreme markers if
+        // necessary.
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < self.heights[i + 1]).unwrap_or(3)
+        };
+
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        // Move the middle markers towards their desired positions.
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let s = d.signum();
+                let height = self.parabolic(i, s);
+                self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {
+                    height
+                } else {
+                    self.linear(i, s)
+                };
+                self.positions[i] += s;
+            }
+        }
+    }
+
+    /// Returns the estimated quantile, or `None` if no values have been
+    /// added.
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=4 => {
+                let mut values = self.heights[..self.count].to_vec();
+                values.sort_by(f64::total_cmp);
+                Some(values[((self.count - 1) as f64 * self.p).round() as usize])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+
+    fn parabolic(&self, i: usize, s: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+
+        q[i] + s / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + s) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - s) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, s: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        let j = if s > 0.0 { i + 1 } else { i - 1 };
+
+        q[i] + s * (q[j] - q[i]) / (n[j] - n[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::P2Quantile;
+
+    #[test]
+    fn test_few_values() {
+        let mut median = P2Quantile::new(0.5);
+        assert_eq!(median.estimate(), None);
+
+        for x in [3.0, 1.0, 2.0] {
+            median.add(x);
+        }
+        assert_eq!(median.estimate(), Some(2.0));
+    }
+
+    #[test]
+    fn test_median() {
+        // Pseudo-random values between 0 and 1000, with a few large outliers
+        // that skew the mean.
+        let mut median = P2Quantile::new(0.5);
+        let mut state: u32 = 1;
+        for _ in 0..1000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            median.add((state % 1000) as f64);
+        }
+        for _ in 0..10 {
+            median.add(1e6);
+        }
+
+        let estimate = median.estimate().unwrap();
+        assert!((estimate - 500.0).abs() < 50.0, "{}", estimate);
+    }
+}
@@ -0,0 +1,1217 @@
+//! Formatting of the text report.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::time::Duration;
+
+use crate::{Calibration, ProfileSnapshot, ScopeStats, FRAME_TIME_BUCKETS};
+
+/// Options for formatting the text report.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::ReportOptions;
+///
+/// let options = ReportOptions::new().show_active(true);
+/// coarse_prof::write_with(&mut std::io::stdout(), &options).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    pub(crate) show_active: bool,
+    pub(crate) frame_scope: Option<String>,
+    pub(crate) precision: usize,
+    pub(crate) width: usize,
+    pub(crate) calls: Option<Notation>,
+    pub(crate) tree_style: TreeStyle,
+    pub(crate) sort: Sort,
+    pub(crate) min_percent: f64,
+    pub(crate) time_unit: Unit,
+    pub(crate) show_confidence: bool,
+    pub(crate) show_self: bool,
+    pub(crate) show_idle: bool,
+    pub(crate) show_overhead: bool,
+    pub(crate) show_calibrated: bool,
+    pub(crate) show_total: bool,
+    pub(crate) deterministic: bool,
+    pub(crate) sparkline: usize,
+    pub(crate) exclude_wait: bool,
+}
+
+/// Order of sibling scopes in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    /// The order in which the scopes have been entered for the first time.
+    Insertion,
+
+    /// Descending total time spent in the scopes.
+    TotalTime,
+
+    /// Ascending names of the scopes. Unlike the other orders, this does not
+    /// depend on how the program ran, so successive reports can be compared
+    /// line by line with standard tools such as `diff`.
+    Alphabetical,
+}
+
+/// Unit of the durations in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Seconds, written as `s`.
+    Secs,
+
+    /// Milliseconds, written as `ms`.
+    Millis,
+
+    /// Microseconds, written as `us`.
+    Micros,
+
+    /// Nanoseconds, written as `ns`.
+    Nanos,
+
+    /// The largest of the other units in which the average of a row is at
+    /// least 1, chosen separately for every row, so that a tree with both
+    /// microsecond and millisecond scopes shows meaningful digits for both.
+    Auto,
+}
+
+impl Unit {
+    /// Returns the unit for a row whose average is `mean`, resolving `Auto`.
+    pub(crate) fn resolve(self, mean: Duration) -> FixedUnit {
+        match self {
+            Unit::Secs => FixedUnit::Secs,
+            Unit::Millis => FixedUnit::Millis,
+            Unit::Micros => FixedUnit::Micros,
+            Unit::Nanos => FixedUnit::Nanos,
+            Unit::Auto if mean >= Duration::from_secs(1) => FixedUnit::Secs,
+            Unit::Auto if mean >= Duration::from_millis(1) => FixedUnit::Millis,
+            Unit::Auto if mean >= Duration::from_micros(1) => FixedUnit::Micros,
+            Unit::Auto => FixedUnit::Nanos,
+        }
+    }
+}
+
+/// A [`Unit`](enum.Unit.html) other than `Auto`, in which durations are
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FixedUnit {
+    Secs,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl FixedUnit {
+    /// Convert a duration to this unit.
+    pub(crate) fn convert(self, duration: Duration) -> f64 {
+        let secs = duration.as_secs_f64();
+        match self {
+            FixedUnit::Secs => secs,
+            FixedUnit::Millis => secs * 1e3,
+            FixedUnit::Micros => secs * 1e6,
+            FixedUnit::Nanos => secs * 1e9,
+        }
+    }
+
+    /// Returns the abbreviation of this unit.
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            FixedUnit::Secs => "s",
+            FixedUnit::Millis => "ms",
+            FixedUnit::Micros => "us",
+            FixedUnit::Nanos => "ns",
+        }
+    }
+}
+
+/// How the hierarchy of scopes is drawn in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeStyle {
+    /// Indent each level by two spaces.
+    Indent,
+
+    /// Draw the tree with box-drawing characters, e.g. `├─` and `└─`.
+    Unicode,
+}
+
+/// How numbers in a column of the report are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// Plain notation, e.g. `1200`.
+    Plain,
+
+    /// Scientific notation, e.g. `1.20e3`.
+    Scientific,
+
+    /// Exact integers with thousands separators, e.g. `1,200`. For numbers
+    /// that are not integers, this is the same as `Plain`.
+    Exact,
+}
+
+impl Default for ReportOptions {
+    fn default() -> ReportOptions {
+        ReportOptions {
+            show_active: false,
+            frame_scope: None,
+            precision: 2,
+            width: 4,
+            calls: None,
+            tree_style: TreeStyle::Indent,
+            sort: Sort::Insertion,
+            min_percent: 0.0,
+            time_unit: Unit::Millis,
+            show_confidence: false,
+            show_self: false,
+            show_idle: false,
+            show_overhead: false,
+            show_calibrated: false,
+            show_total: false,
+            deterministic: false,
+            sparkline: 0,
+            exclude_wait: false,
+        }
+    }
+}
+
+impl ReportOptions {
+    /// Create the default options, which are used by
+    /// [`write`](fn.write.html).
+    pub fn new() -> ReportOptions {
+        ReportOptions::default()
+    }
+
+    /// Mark scopes that are currently being visited, and include the time
+    /// spent in them so far in their statistics. This helps with finding out
+    /// why a scope is still open. Defaults to `false`.
+    pub fn show_active(mut self, show_active: bool) -> ReportOptions {
+        self.show_active = show_active;
+        self
+    }
+
+    /// Designate the root scope called `name` as the frame.
+    ///
+    /// For all scopes below the frame, the report then additionally shows
+    /// their average cost per frame, i.e. their total duration divided by the
+    /// number of frames. In contrast to the average per call, this also
+    /// accounts for scopes that do not run every frame, which is how frame
+    /// budgets are usually planned. Below the frame, a histogram of frame
+    /// times is shown, e.g. how many frames took longer than 16.7ms and thus
+    /// missed 60 fps. Defaults to the scope set with
+    /// [`set_frame_scope`](fn.set_frame_scope.html), if any.
+    pub fn frame_scope<S: Into<String>>(mut self, name: S) -> ReportOptions {
+        self.frame_scope = Some(name.into());
+        self
+    }
+
+    /// Number of decimal places of percentages, durations and frequencies.
+    /// Defaults to 2.
+    pub fn precision(mut self, precision: usize) -> ReportOptions {
+        self.precision = precision;
+        self
+    }
+
+    /// Minimal width of the duration columns, so that they line up. Defaults
+    /// to 4.
+    pub fn width(mut self, width: usize) -> ReportOptions {
+        self.width = width;
+        self
+    }
+
+    /// Show how often each scope has been called, written in the given
+    /// notation. Defaults to `None`, i.e. the column is not shown.
+    pub fn calls(mut self, calls: Option<Notation>) -> ReportOptions {
+        self.calls = calls;
+        self
+    }
+
+    /// How to draw the hierarchy of scopes. Drawing the tree with
+    /// [`TreeStyle::Unicode`](enum.TreeStyle.html#variant.Unicode) keeps deep
+    /// hierarchies readable. Defaults to
+    /// [`TreeStyle::Indent`](enum.TreeStyle.html#variant.Indent).
+    pub fn tree_style(mut self, tree_style: TreeStyle) -> ReportOptions {
+        self.tree_style = tree_style;
+        self
+    }
+
+    /// Order of sibling scopes. Defaults to
+    /// [`Sort::Insertion`](enum.Sort.html#variant.Insertion).
+    pub fn sort(mut self, sort: Sort) -> ReportOptions {
+        self.sort = sort;
+        self
+    }
+
+    /// Hide scopes, including their children, whose percentage in the report
+    /// is below `min_percent`. Note that the percentages of scopes other than
+    /// roots are relative to their parent. Defaults to zero, i.e. all scopes
+    /// are shown.
+    pub fn min_percent(mut self, min_percent: f64) -> ReportOptions {
+        self.min_percent = min_percent;
+        self
+    }
+
+    /// Unit of durations. Defaults to
+    /// [`Unit::Millis`](enum.Unit.html#variant.Millis).
+    pub fn time_unit(mut self, time_unit: Unit) -> ReportOptions {
+        self.time_unit = time_unit;
+        self
+    }
+
+    /// Show the sample standard deviation of the durations and the margin of
+    /// the 95% confidence interval of the mean, e.g. `±0.12ms (95%)`, so that
+    /// comparisons between measurements can be judged. Defaults to `false`.
+    pub fn show_confidence(mut self, show_confidence: bool) -> ReportOptions {
+        self.show_confidence = show_confidence;
+        self
+    }
+
+    /// For every scope with children, show the time that is not spent in any
+    /// of the children as an additional `<self>` row below them, so that
+    /// unattributed time inside big scopes becomes explicit. The row shows
+    /// its percentage of the scope's time and its average per call of the
+    /// scope. Defaults to `false`.
+    pub fn show_self(mut self, show_self: bool) -> ReportOptions {
+        self.show_self = show_self;
+        self
+    }
+
+    /// Below every root scope that has been entered more than once, show the
+    /// time between leaving it and entering it again as an `<idle>` row. For
+    /// a frame scope, this makes vsync waits and scheduling gaps visible,
+    /// which would otherwise silently be missing from the percentages. The
+    /// row shows its percentage of the total time and its average per call
+    /// of the root scope. Defaults to `false`.
+    pub fn show_idle(mut self, show_idle: bool) -> ReportOptions {
+        self.show_idle = show_idle;
+        self
+    }
+
+    /// Below the report, show the estimated cost of profiling, see
+    /// [`estimated_overhead`](fn.estimated_overhead.html), multiplied with
+    /// the total number of calls of all scopes, e.g. `overhead: ~45ns per
+    /// call, 0.54ms total (0.05%)`. This shows whether the profiler
+    /// noticeably distorts the measurements. Defaults to `false`.
+    pub fn show_overhead(mut self, show_overhead: bool) -> ReportOptions {
+        self.show_overhead = show_overhead;
+        self
+    }
+
+    /// Next to the average duration of every scope, show the average minus
+    /// the overhead of profiling that has been measured with
+    /// [`calibrate`](fn.calibrate.html), e.g. `0.48ms calibrated`, see
+    /// [`Calibration::calibrated_mean`](struct.Calibration.html#method.calibrated_mean).
+    /// Nothing is shown until `calibrate` has been called. Defaults to
+    /// `false`.
+    pub fn show_calibrated(mut self, show_calibrated: bool) -> ReportOptions {
+        self.show_calibrated = show_calibrated;
+        self
+    }
+
+    /// Show the total time spent in every scope since the last reset, e.g.
+    /// `600.00ms total`. Unlike percentages and averages, totals can be
+    /// compared directly between runs of different lengths. Defaults to
+    /// `false`.
+    pub fn show_total(mut self, show_total: bool) -> ReportOptions {
+        self.show_total = show_total;
+        self
+    }
+
+    /// Only show the structure of the tree and the number of calls of every
+    /// scope, e.g. `physics: 1200 calls`, omitting all values that depend
+    /// on timing, such as durations, percentages and frequencies. Siblings
+    /// are sorted by name and no scopes are hidden by
+    /// [`min_percent`](#method.min_percent). The output of a deterministic
+    /// program is then stable enough to snapshot-test its instrumentation in
+    /// CI. Defaults to `false`.
+    pub fn deterministic(mut self, deterministic: bool) -> ReportOptions {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Start the report with a sparkline of the durations of the last `len`
+    /// calls of every root scope, e.g. `frame: ▁▁▂▁▁█▁▁▂▁`, which shows at a
+    /// glance whether there was stutter during the measurement. The bars are
+    /// scaled to the slowest of the calls. At most the last 256 calls are
+    /// kept. Defaults to zero, which shows no sparklines.
+    pub fn sparkline(mut self, len: usize) -> ReportOptions {
+        self.sparkline = len;
+        self
+    }
+
+    /// Hide the scopes that have been marked as waiting with
+    /// [`profile_wait`](macro.profile_wait.html), and exclude their time from
+    /// their ancestors, so that percentages, averages and self times only
+    /// account for work, see
+    /// [`ProfileSnapshot::without_wait`](struct.ProfileSnapshot.html#method.without_wait).
+    /// Otherwise, waiting scopes are shown with a `[wait]` flag. Defaults to
+    /// `false`.
+    pub fn exclude_wait(mut self, exclude_wait: bool) -> ReportOptions {
+        self.exclude_wait = exclude_wait;
+        self
+    }
+}
+
+/// Connector of a scope that is followed by siblings.
+const BRANCH: &str = "├─ ";
+
+/// Connector of the last child of a scope.
+const LAST: &str = "└─ ";
+
+/// Write `n` with commas separating groups of thousands.
+fn write_grouped(f: &mut fmt::Formatter, n: usize) -> fmt::Result {
+    let digits = n.to_string();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", digit)?;
+    }
+
+    Ok(())
+}
+
+/// Returns `part` relative to `total` in percent, or zero if `total` is zero.
+fn percent_of(part: Duration, total: Duration) -> f64 {
+    if total > Duration::new(0, 0) {
+        part.as_secs_f64() / total.as_secs_f64() * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Returns the flag that is shown after the name of waiting scopes.
+fn wait_flag(scope: &ScopeStats) -> &'static str {
+    if scope.wait {
+        " [wait]"
+    } else {
+        ""
+    }
+}
+
+/// Returns one bar per duration, scaled to the longest duration.
+fn sparkline(durations: &[Duration]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let max = durations.iter().max().copied().unwrap_or_default();
+    durations
+        .iter()
+        .map(|duration| {
+            let level = if max > Duration::new(0, 0) {
+                duration.as_secs_f64() / max.as_secs_f64() * BARS.len() as f64
+            } else {
+                0.0
+            };
+            BARS[(level.ceil() as usize).clamp(1, BARS.len()) - 1]
+        })
+        .collect()
+}
+
+/// A [`ProfileSnapshot`](struct.ProfileSnapshot.html) formatted with
+/// [`ReportOptions`](struct.ReportOptions.html).
+///
+/// Created by [`ProfileSnapshot::report`](struct.ProfileSnapshot.html#method.report).
+pub struct Report<'a> {
+    pub(crate) snapshot: &'a ProfileSnapshot,
+    pub(crate) options: &'a ReportOptions,
+    pub(crate) window: Option<&'a ProfileSnapshot>,
+
+    /// The calibration for showing calibrated means, if requested.
+    pub(crate) calibration: Option<Calibration>,
+}
+
+impl<'a> Report<'a> {
+    /// Show the statistics of every scope in `window` next to its statistics
+    /// in the report, e.g. `last window: 4.20ms avg, 9.10ms max @ 60.00Hz`.
+    /// Scopes are matched by their path. This is used for comparing the last
+    /// window recorded with [`record_windows`](fn.record_windows.html) with
+    /// the lifetime statistics.
+    pub fn window(mut self, window: &'a ProfileSnapshot) -> Report<'a> {
+        self.window = Some(window);
+        self
+    }
+
+    /// Returns the scopes that should be shown, in the order in which they
+    /// should be shown.
+    fn visible<'s>(&self, scopes: &'s [ScopeStats], pred_sum: Duration) -> Vec<&'s ScopeStats> {
+        let mut visible: Vec<_> = scopes
+            .iter()
+            .filter(|scope| {
+                self.options.min_percent <= 0.0
+                    || self.options.deterministic
+                    || scope.duration_sum.as_secs_f64() / pred_sum.as_secs_f64() * 100.0
+                        >= self.options.min_percent
+            })
+            .collect();
+
+        let sort = if self.options.deterministic {
+            Sort::Alphabetical
+        } else {
+            self.options.sort
+        };
+        match sort {
+            Sort::Insertion => (),
+            Sort::TotalTime => visible.sort_by_key(|scope| Reverse(scope.duration_sum)),
+            Sort::Alphabetical => visible.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+
+        visible
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fmt_scope(
+        &self,
+        f: &mut fmt::Formatter,
+        scope: &ScopeStats,
+        window: Option<&ScopeStats>,
+        pred_sum: Duration,
+        num_frames: Option<usize>,
+        prefix: &str,
+        connector: &str,
+    ) -> fmt::Result {
+        let total_duration_secs = self.snapshot.total_duration.as_secs_f64();
+        let duration_sum_secs = scope.duration_sum.as_secs_f64();
+
+        let percent = duration_sum_secs / pred_sum.as_secs_f64() * 100.0;
+
+        // Write self
+        write!(f, "{}{}", prefix, connector)?;
+        let (p, w) = (self.options.precision, self.options.width);
+        let unit = self.options.time_unit.resolve(scope.duration_mean());
+        let unvisited = scope.num_calls == 0 && scope.duration_sum == Duration::new(0, 0);
+        if unvisited {
+            // A scope that has only been established as a logical parent with
+            // `in_scope_of`, but has not been visited in this tree.
+            writeln!(f, "{}", scope.name)?;
+        } else if self.options.deterministic {
+            writeln!(
+                f,
+                "{}{}: {} calls",
+                scope.name,
+                wait_flag(scope),
+                scope.num_calls
+            )?;
+        } else {
+            write!(
+                f,
+                "{}{}: {:3.p$}%, {:>w$.p$}{u} avg, {:>w$.p$}{u} min, {:>w$.p$}{u} max @ {:.p$}Hz",
+                scope.name,
+                wait_flag(scope),
+                percent,
+                unit.convert(scope.duration_sum) / (scope.num_calls as f64),
+                unit.convert(scope.duration_min),
+                unit.convert(scope.duration_max),
+                scope.num_calls as f64 / total_duration_secs,
+                p = p,
+                w = w,
+                u = unit.suffix(),
+            )?;
+            match self.options.calls {
+                Some(Notation::Plain) => write!(f, ", {} calls", scope.num_calls)?,
+                Some(Notation::Scientific) => {
+                    write!(f, ", {:.p$e} calls", scope.num_calls as f64, p = p)?
+                }
+                Some(Notation::Exact) => {
+                    write!(f, ", ")?;
+                    write_grouped(f, scope.num_calls)?;
+                    write!(f, " calls")?;
+                }
+                None => (),
+            }
+            if self.options.show_total {
+                write!(
+                    f,
+                    ", {:>w$.p$}{} total",
+                    unit.convert(scope.duration_sum),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            if let Some(num_frames) = num_frames {
+                write!(
+                    f,
+                    ", {:>w$.p$}{}/frame",
+                    unit.convert(scope.duration_sum) / (num_frames as f64),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            if let Some(calibration) = self.calibration {
+                write!(
+                    f,
+                    ", {:>w$.p$}{} calibrated",
+                    unit.convert(calibration.calibrated_mean(scope)),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            if let Some(median) = scope.duration_median {
+                write!(
+                    f,
+                    ", {:>w$.p$}{} median",
+                    unit.convert(median),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            if let Some(decayed_mean) = scope.decayed_mean {
+                write!(
+                    f,
+                    ", {:>w$.p$}{} decayed",
+                    unit.convert(decayed_mean),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            if self.options.show_confidence {
+                if let (Some(std_dev), Some((_, high))) = (
+                    scope.duration_std_dev(),
+                    scope.duration_confidence_interval(),
+                ) {
+                    write!(
+                        f,
+                        ", {:>w$.p$}{u} std dev, ±{:.p$}{u} (95%)",
+                        unit.convert(std_dev),
+                        unit.convert(high - scope.duration_mean()),
+                        p = p,
+                        w = w,
+                        u = unit.suffix(),
+                    )?;
+                }
+            }
+            if let Some(cpu_time_sum) = scope.cpu_time_sum {
+                write!(
+                    f,
+                    ", {:>w$.p$}{} cpu",
+                    unit.convert(cpu_time_sum) / (scope.num_calls as f64),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            for attr in &scope.attrs {
+                write!(
+                    f,
+                    ", {}: {:.p$} avg, {:.p$} min, {:.p$} max",
+                    attr.key,
+                    attr.mean(),
+                    attr.min,
+                    attr.max,
+                    p = p,
+                )?;
+            }
+            if let Some(window_snapshot) = self.window {
+                match window {
+                    Some(window) if window.num_calls > 0 => write!(
+                        f,
+                        ", last window: {:>w$.p$}{u} avg, {:>w$.p$}{u} max @ {:.p$}Hz",
+                        unit.convert(window.duration_sum) / (window.num_calls as f64),
+                        unit.convert(window.duration_max),
+                        window.num_calls as f64 / window_snapshot.total_duration.as_secs_f64(),
+                        p = p,
+                        w = w,
+                        u = unit.suffix(),
+                    )?,
+                    _ => write!(f, ", last window: no calls")?,
+                }
+            }
+            if scope.recursions > 0 {
+                write!(f, ", {} recursions", scope.recursions)?;
+            }
+            if self.options.show_active {
+                scope.fmt_active(f)?;
+            }
+            writeln!(f)?;
+        }
+
+        // Write children
+        let is_root = prefix.is_empty() && connector.is_empty();
+        let num_frames = num_frames.or_else(|| {
+            if is_root && self.options.frame_scope.as_deref() == Some(scope.name.as_str()) {
+                Some(scope.num_calls)
+            } else {
+                None
+            }
+        });
+        let child_prefix = match self.options.tree_style {
+            TreeStyle::Indent => format!("{}  ", prefix),
+            TreeStyle::Unicode if is_root => String::new(),
+            TreeStyle::Unicode if connector == LAST => format!("{}   ", prefix),
+            TreeStyle::Unicode => format!("{}│  ", prefix),
+        };
+        let children_sum = if unvisited {
+            pred_sum
+        } else {
+            scope.duration_sum
+        };
+        let children = self.visible(&scope.children, children_sum);
+        let self_duration = scope.self_duration();
+        let self_percent = self_duration.as_secs_f64() / scope.duration_sum.as_secs_f64() * 100.0;
+        let show_self = self.options.show_self
+            && !self.options.deterministic
+            && !unvisited
+            && !scope.children.is_empty()
+            && self_duration > Duration::new(0, 0)
+            && self_percent >= self.options.min_percent;
+        for (i, child) in children.iter().enumerate() {
+            let child_connector = match self.options.tree_style {
+                TreeStyle::Indent => "",
+                TreeStyle::Unicode if i + 1 == children.len() && !show_self => LAST,
+                TreeStyle::Unicode => BRANCH,
+            };
+            let child_window = window.and_then(|window| {
+                window
+                    .children
+                    .iter()
+                    .find(|window_child| window_child.name == child.name)
+            });
+            self.fmt_scope(
+                f,
+                child,
+                child_window,
+                children_sum,
+                num_frames,
+                &child_prefix,
+                child_connector,
+            )?;
+        }
+
+        if show_self {
+            let self_connector = match self.options.tree_style {
+                TreeStyle::Indent => "",
+                TreeStyle::Unicode => LAST,
+            };
+            let unit = self
+                .options
+                .time_unit
+                .resolve(self_duration.div_f64(scope.num_calls.max(1) as f64));
+            write!(
+                f,
+                "{}{}<self>: {:3.p$}%, {:>w$.p$}{u} avg",
+                child_prefix,
+                self_connector,
+                self_percent,
+                unit.convert(self_duration) / (scope.num_calls as f64),
+                p = p,
+                w = w,
+                u = unit.suffix(),
+            )?;
+            if let Some(num_frames) = num_frames {
+                write!(
+                    f,
+                    ", {:>w$.p$}{}/frame",
+                    unit.convert(self_duration) / (num_frames as f64),
+                    unit.suffix(),
+                    p = p,
+                    w = w,
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Report<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.options.exclude_wait {
+            let snapshot = self.snapshot.without_wait();
+            let options = self.options.clone().exclude_wait(false);
+            let report = Report {
+                snapshot: &snapshot,
+                options: &options,
+                window: self.window,
+                calibration: self.calibration,
+            };
+            return report.fmt(f);
+        }
+
+        let snapshot = self.snapshot;
+
+        for (key, value) in &snapshot.metadata {
+            writeln!(f, "# {}: {}", key, value)?;
+        }
+        if !self.options.deterministic {
+            writeln!(
+                f,
+                "# elapsed: {:.2}s",
+                snapshot.total_duration.as_secs_f64()
+            )?;
+        }
+
+        if self.options.sparkline > 0 && !self.options.deterministic {
+            for root in self.visible(&snapshot.roots, snapshot.total_duration) {
+                let durations = &root.recent_durations;
+                if !durations.is_empty() {
+                    let start = durations.len().saturating_sub(self.options.sparkline);
+                    writeln!(f, "{}: {}", root.name, sparkline(&durations[start..]))?;
+                }
+            }
+        }
+
+        for root in self.visible(&snapshot.roots, snapshot.total_duration) {
+            let window = self
+                .window
+                .and_then(|window| window.roots.iter().find(|window| window.name == root.name));
+            self.fmt_scope(f, root, window, snapshot.total_duration, None, "", "")?;
+            if self.options.deterministic {
+                continue;
+            }
+
+            match root.frame_histogram {
+                Some(histogram)
+                    if self.options.frame_scope.as_deref() == Some(root.name.as_str()) =>
+                {
+                    write!(f, "frames:")?;
+                    for (count, bound) in histogram.iter().zip(&FRAME_TIME_BUCKETS) {
+                        let millis = (bound.as_secs_f64() * 1e4).round() / 10.0;
+                        write!(f, " {} <={}ms,", count, millis)?;
+                    }
+                    write!(
+                        f,
+                        " {} >{}ms",
+                        histogram[FRAME_TIME_BUCKETS.len()],
+                        FRAME_TIME_BUCKETS[FRAME_TIME_BUCKETS.len() - 1].as_millis(),
+                    )?;
+                    if let Some(lows) = root.frame_lows {
+                        let (p, unit) = (
+                            self.options.precision,
+                            self.options.time_unit.resolve(lows.one_percent),
+                        );
+                        write!(
+                            f,
+                            ", 1% low: {:.p$}{u}, 0.1% low: {:.p$}{u}",
+                            unit.convert(lows.one_percent),
+                            unit.convert(lows.point_one_percent),
+                            p = p,
+                            u = unit.suffix(),
+                        )?;
+                    }
+                    writeln!(f)?;
+                }
+                _ => (),
+            }
+
+            match root.idle_sum {
+                Some(idle_sum) if self.options.show_idle && idle_sum > Duration::new(0, 0) => {
+                    let (p, w) = (self.options.precision, self.options.width);
+                    let unit = self
+                        .options
+                        .time_unit
+                        .resolve(idle_sum.div_f64(root.num_calls.max(1) as f64));
+                    writeln!(
+                        f,
+                        "<idle>: {:3.p$}%, {:>w$.p$}{u} avg",
+                        percent_of(idle_sum, snapshot.total_duration),
+                        unit.convert(idle_sum) / (root.num_calls as f64),
+                        p = p,
+                        w = w,
+                        u = unit.suffix(),
+                    )?;
+                }
+                _ => (),
+            }
+        }
+
+        if self.options.deterministic {
+            return Ok(());
+        }
+
+        if let Some(overhead) = snapshot.overhead {
+            let (p, w) = (self.options.precision, self.options.width);
+            let unit = self.options.time_unit.resolve(overhead);
+            writeln!(
+                f,
+                "<profiler>: {:3.p$}%, {:>w$.p$}{u} total",
+                percent_of(overhead, snapshot.total_duration),
+                unit.convert(overhead),
+                p = p,
+                w = w,
+                u = unit.suffix(),
+            )?;
+        }
+        if self.options.show_overhead {
+            self.fmt_overhead(f, crate::estimated_overhead())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Report<'a> {
+    /// Write the footer with the total cost of profiling, given the cost of
+    /// a single call.
+    fn fmt_overhead<W: fmt::Write>(&self, f: &mut W, overhead: Duration) -> fmt::Result {
+        fn num_calls(scopes: &[ScopeStats]) -> u128 {
+            scopes
+                .iter()
+                .map(|scope| scope.num_calls as u128 + num_calls(&scope.children))
+                .sum()
+        }
+
+        // Compute in nanoseconds, since the number of calls can exceed `u32`.
+        let total = overhead
+            .as_nanos()
+            .saturating_mul(num_calls(&self.snapshot.roots))
+            .min(Duration::MAX.as_nanos());
+        let total = Duration::new(
+            (total / 1_000_000_000) as u64,
+            (total % 1_000_000_000) as u32,
+        );
+        let (p, unit) = (
+            self.options.precision,
+            self.options.time_unit.resolve(total),
+        );
+        writeln!(
+            f,
+            "overhead: ~{}ns per call, {:.p$}{u} total ({:.p$}%)",
+            overhead.as_nanos(),
+            unit.convert(total),
+            percent_of(total, self.snapshot.total_duration),
+            p = p,
+            u = unit.suffix(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Notation, ReportOptions, Sort, TreeStyle, Unit};
+    use crate::snapshot::tests::stats;
+    use crate::Calibration;
+    use crate::{FrameLows, ProfileSnapshot, ScopeStats};
+
+    fn snapshot() -> ProfileSnapshot {
+        ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![ScopeStats {
+                name: "frame".to_string(),
+                num_calls: 1200,
+                duration_sum: Duration::from_millis(600),
+                duration_min: Duration::from_micros(250),
+                duration_max: Duration::from_millis(2),
+                ..ScopeStats::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_default_format() {
+        assert_eq!(
+            snapshot().to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 60.00%, 0.50ms avg, 0.25ms min, 2.00ms max @ 1200.00Hz\n",
+        );
+    }
+
+    #[test]
+    fn test_precision_and_calls() {
+        let snapshot = snapshot();
+
+        let options = ReportOptions::new()
+            .precision(1)
+            .width(6)
+            .calls(Some(Notation::Scientific));
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 60.0%,    0.5ms avg,    0.2ms min,    2.0ms max @ 1200.0Hz, 1.2e3 calls\n",
+        );
+
+        let options = ReportOptions::new()
+            .precision(0)
+            .calls(Some(Notation::Plain));
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "# elapsed: 1.00s\n\
+             frame:  60%,    0ms avg,    0ms min,    2ms max @ 1200Hz, 1200 calls\n",
+        );
+    }
+
+    #[test]
+    fn test_calibrated() {
+        let snapshot = snapshot();
+        let options = ReportOptions::new();
+        let mut report = snapshot.report(&options);
+        report.calibration = Some(Calibration {
+            timer: Duration::from_micros(50),
+            enter_leave: Duration::new(0, 0),
+        });
+        assert_eq!(
+            report.to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 60.00%, 0.50ms avg, 0.25ms min, 2.00ms max @ 1200.00Hz, 0.45ms calibrated\n",
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut snapshot = snapshot();
+        let template = snapshot.roots[0].clone();
+        for (name, millis) in [("render", 300), ("ai", 100), ("physics", 200)] {
+            let mut child = template.clone();
+            child.name = name.to_string();
+            child.duration_sum = Duration::from_millis(millis);
+            snapshot.roots[0].children.push(child);
+        }
+
+        let names = |sort| {
+            let options = ReportOptions::new().sort(sort);
+            snapshot
+                .report(&options)
+                .to_string()
+                .lines()
+                .skip(2)
+                .map(|line| line.trim().split(':').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(Sort::Insertion), ["render", "ai", "physics"]);
+        assert_eq!(names(Sort::TotalTime), ["render", "physics", "ai"]);
+        assert_eq!(names(Sort::Alphabetical), ["ai", "physics", "render"]);
+    }
+
+    #[test]
+    fn test_auto_unit() {
+        let mut snapshot = snapshot();
+        let mut tiny = snapshot.roots[0].clone();
+        tiny.name = "tiny".to_string();
+        tiny.duration_sum = Duration::from_micros(2400);
+        tiny.duration_min = Duration::from_micros(1);
+        tiny.duration_max = Duration::from_micros(5);
+        snapshot.roots[0].children.push(tiny);
+
+        let options = ReportOptions::new().time_unit(Unit::Auto);
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 60.00%, 500.00us avg, 250.00us min, 2000.00us max @ 1200.00Hz\n\
+             \x20 tiny: 0.40%, 2.00us avg, 1.00us min, 5.00us max @ 1200.00Hz\n",
+        );
+    }
+
+    #[test]
+    fn test_auto_unit_frame_lows() {
+        let mut snapshot = snapshot();
+        snapshot.roots[0].frame_histogram = Some(Default::default());
+        snapshot.roots[0].frame_lows = Some(FrameLows {
+            one_percent: Duration::from_micros(500),
+            point_one_percent: Duration::from_micros(800),
+            num_frames: 1000,
+        });
+
+        let options = ReportOptions::new()
+            .time_unit(Unit::Auto)
+            .frame_scope("frame");
+        let report = snapshot.report(&options).to_string();
+        assert!(
+            report.ends_with(", 1% low: 500.00us, 0.1% low: 800.00us\n"),
+            "{}",
+            report
+        );
+
+        let options = options.time_unit(Unit::Millis);
+        let report = snapshot.report(&options).to_string();
+        assert!(report.ends_with(", 1% low: 0.50ms, 0.1% low: 0.80ms\n"));
+    }
+
+    #[test]
+    fn test_total() {
+        let snapshot = snapshot();
+        let options = ReportOptions::new().show_total(true).time_unit(Unit::Secs);
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 60.00%, 0.00s avg, 0.00s min, 0.00s max @ 1200.00Hz, 0.60s total\n",
+        );
+    }
+
+    #[test]
+    fn test_sparkline() {
+        let mut snapshot = snapshot();
+        snapshot.roots[0].recent_durations = [4, 1, 2, 8, 3, 0]
+            .iter()
+            .map(|&millis| Duration::from_millis(millis))
+            .collect();
+
+        let options = ReportOptions::new().sparkline(5);
+        let report = snapshot.report(&options).to_string();
+        assert_eq!(report.lines().nth(1), Some("frame: ▁▂█▃▁"));
+        assert_eq!(report.lines().count(), 3);
+
+        let options = ReportOptions::new();
+        assert_eq!(snapshot.report(&options).to_string().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_exclude_wait() {
+        let mut snapshot = snapshot();
+        let mut vsync = snapshot.roots[0].clone();
+        vsync.name = "vsync".to_string();
+        vsync.duration_sum = Duration::from_millis(300);
+        vsync.wait = true;
+        snapshot.roots[0].children.push(vsync);
+
+        let options = ReportOptions::new().deterministic(true);
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "frame: 1200 calls\n  vsync [wait]: 1200 calls\n"
+        );
+
+        let options = ReportOptions::new().exclude_wait(true);
+        assert_eq!(
+            snapshot.report(&options).to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 30.00%, 0.25ms avg, 0.25ms min, 2.00ms max @ 1200.00Hz\n",
+        );
+    }
+
+    #[test]
+    fn test_overhead() {
+        let snapshot = snapshot();
+        let options = ReportOptions::new();
+        let mut footer = String::new();
+        snapshot
+            .report(&options)
+            .fmt_overhead(&mut footer, Duration::from_nanos(50))
+            .unwrap();
+        assert_eq!(footer, "overhead: ~50ns per call, 0.06ms total (0.01%)\n");
+
+        let options = ReportOptions::new().show_overhead(true);
+        let report = snapshot.report(&options).to_string();
+        assert!(report.lines().nth(2).unwrap().starts_with("overhead: ~"));
+
+        // More calls than fit into `u32`, in a snapshot without duration.
+        let mut snapshot = snapshot;
+        snapshot.total_duration = Duration::new(0, 0);
+        snapshot.roots[0].num_calls = 1 << 33;
+        footer.clear();
+        snapshot
+            .report(&ReportOptions::new())
+            .fmt_overhead(&mut footer, Duration::from_nanos(50))
+            .unwrap();
+        assert_eq!(
+            footer,
+            "overhead: ~50ns per call, 429496.73ms total (0.00%)\n"
+        );
+    }
+
+    #[test]
+    fn test_exact_calls() {
+        let mut snapshot = snapshot();
+        let options = ReportOptions::new().calls(Some(Notation::Exact));
+
+        for &(num_calls, expected) in &[
+            (0, "0"),
+            (999, "999"),
+            (1200, "1,200"),
+            (123_456, "123,456"),
+            (1_234_567, "1,234,567"),
+        ] {
+            snapshot.roots[0].num_calls = num_calls;
+            let report = snapshot.report(&options).to_string();
+            assert!(
+                report.ends_with(&format!(", {} calls\n", expected)),
+                "{}",
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn test_show_self() {
+        let mut snapshot = snapshot();
+        let mut child = snapshot.roots[0].clone();
+        child.name = "physics".to_string();
+        child.duration_sum = Duration::from_millis(450);
+        snapshot.roots[0].children.push(child);
+
+        let options = ReportOptions::new()
+            .show_self(true)
+            .tree_style(TreeStyle::Unicode);
+        let report = snapshot.report(&options).to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with("├─ physics: 75.00%"), "{}", report);
+        assert_eq!(lines[3], "└─ <self>: 25.00%, 0.12ms avg");
+
+        // Scopes without children have no `<self>` row.
+        snapshot.roots[0].children.clear();
+        assert_eq!(snapshot.report(&options).to_string().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_show_idle() {
+        let mut snapshot = snapshot();
+        snapshot.roots[0].idle_sum = Some(Duration::from_millis(300));
+
+        assert_eq!(snapshot.to_string().lines().count(), 2);
+
+        let options = ReportOptions::new().show_idle(true);
+        let report = snapshot.report(&options).to_string();
+        assert_eq!(
+            report.lines().nth(2),
+            Some("<idle>: 30.00%, 0.25ms avg"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_unvisited_parent() {
+        let mut snapshot = snapshot();
+        let child = snapshot.roots[0].clone();
+        snapshot.roots[0].num_calls = 0;
+        snapshot.roots[0].duration_sum = Duration::new(0, 0);
+        snapshot.roots[0].children.push(child);
+
+        let report = snapshot.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[1], "frame");
+        assert!(lines[2].starts_with("  frame: 60.00%"), "{}", report);
+    }
+
+    #[test]
+    fn test_unicode_tree() {
+        let leaf = |name: &str| stats(name, 1, Vec::new());
+        let node = |name: &str, children| stats(name, 1, children);
+
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![node(
+                "frame",
+                vec![
+                    node("physics", vec![leaf("collisions"), leaf("integrate")]),
+                    node("render", vec![leaf("upload")]),
+                ],
+            )],
+        };
+
+        let options = ReportOptions::new().tree_style(TreeStyle::Unicode);
+        let report = snapshot.report(&options).to_string();
+        let names: Vec<_> = report
+            .lines()
+            .skip(1)
+            .map(|line| line.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "frame",
+                "├─ physics",
+                "│  ├─ collisions",
+                "│  └─ integrate",
+                "└─ render",
+                "   └─ upload",
+            ]
+        );
+    }
+}
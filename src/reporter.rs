@@ -0,0 +1,61 @@
+//! Pluggable sinks for periodic reports.
+
+use std::time::Duration;
+
+use crate::ProfileSnapshot;
+
+/// A sink that receives snapshots of the scope tree periodically.
+///
+/// Reporters are registered with [`add_reporter`](fn.add_reporter.html),
+/// which invokes them according to a [`Schedule`](enum.Schedule.html). This
+/// is the same mechanism that drives [`report_every`](fn.report_every.html)
+/// and [`report_to_files_every`](fn.report_to_files_every.html), so custom
+/// sinks, e.g. for uploading reports to a server, are scheduled in the same
+/// way as the built-in ones.
+///
+/// Reporters are invoked on the profiled thread, between frames, right before
+/// a root scope is entered. The profiler is not in use at that time, so
+/// reporters can enter scopes themselves, which are recorded as root scopes.
+/// Since `report` can not return an error, reporters are expected to handle
+/// errors themselves, e.g. by logging them.
+///
+/// Closures taking a `&ProfileSnapshot` implement this trait.
+///
+/// # Example
+///
+/// ```
+/// use coarse_prof::{ProfileSnapshot, Reporter, Schedule};
+///
+/// struct HotPathLogger;
+///
+/// impl Reporter for HotPathLogger {
+///     fn report(&mut self, snapshot: &ProfileSnapshot) {
+///         println!("{}", snapshot.hottest_path());
+///     }
+/// }
+///
+/// coarse_prof::add_reporter(Schedule::Frames(600), HotPathLogger);
+/// ```
+pub trait Reporter {
+    /// Handle a snapshot of the scope tree.
+    fn report(&mut self, snapshot: &ProfileSnapshot);
+}
+
+impl<F: FnMut(&ProfileSnapshot)> Reporter for F {
+    fn report(&mut self, snapshot: &ProfileSnapshot) {
+        self(snapshot)
+    }
+}
+
+/// When to invoke a [`Reporter`](trait.Reporter.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Whenever a root scope is entered and at least the given time has
+    /// passed since the last report.
+    Interval(Duration),
+
+    /// Whenever a root scope is entered and at least the given number of
+    /// frames, i.e. calls of root scopes, has been completed since the last
+    /// report.
+    Frames(u64),
+}
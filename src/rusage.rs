@@ -0,0 +1,41 @@
+//! Resource usage of the current thread via `getrusage`.
+
+/// Number of values that are sampled.
+pub const NUM_VALUES: usize = 4;
+
+/// Names of the sampled values, which are used as the keys of their
+/// attributes.
+pub const NAMES: [&str; NUM_VALUES] = [
+    "voluntary_switches",
+    "involuntary_switches",
+    "minor_faults",
+    "major_faults",
+];
+
+/// Returns the current values of the context switch and page fault counters
+/// of the current thread, in the order of `NAMES`.
+///
+/// On Linux, the counters are specific to the thread, while on other Unix
+/// platforms, they cover the whole process.
+pub fn sample() -> [u64; NUM_VALUES] {
+    #[cfg(target_os = "linux")]
+    const WHO: libc::c_int = libc::RUSAGE_THREAD;
+    #[cfg(not(target_os = "linux"))]
+    const WHO: libc::c_int = libc::RUSAGE_SELF;
+
+    // Safety: An all-zero `rusage` is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    // Safety: `usage` is a valid pointer to a `rusage`. If the call fails,
+    // all values stay zero.
+    unsafe {
+        libc::getrusage(WHO, &mut usage);
+    }
+
+    [
+        usage.ru_nvcsw as u64,
+        usage.ru_nivcsw as u64,
+        usage.ru_minflt as u64,
+        usage.ru_majflt as u64,
+    ]
+}
@@ -0,0 +1,379 @@
+//! A profiler that is shared between all threads.
+//!
+//! In contrast to the thread-local profiler, there is only one scope tree for
+//! the whole process here. Threads entering the same logical scopes, such as
+//! the workers of a job system, contribute to the same statistics instead of
+//! to separate per-thread trees.
+//!
+//! Statistics are accumulated with atomic operations, spread over multiple
+//! shards to reduce contention between threads. Each thread caches the scopes
+//! that it has visited, so that the shared tree only needs to be locked when
+//! a thread enters a scope for the first time.
+//!
+//! # Example
+//!
+//! ```
+//! use coarse_prof::profile_shared;
+//!
+//! let workers: Vec<_> = (0..4)
+//!     .map(|_| {
+//!         std::thread::spawn(|| {
+//!             profile_shared!("job");
+//!             // ... do some work ...
+//!         })
+//!     })
+//!     .collect();
+//!
+//! for worker in workers {
+//!     worker.join().unwrap();
+//! }
+//!
+//! coarse_prof::shared::write(&mut std::io::stdout()).unwrap();
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::{ProfileSnapshot, ScopeStats};
+
+/// Number of shards that the statistics of each scope are spread over.
+const NUM_SHARDS: usize = 8;
+
+/// Index of a scope in the shared tree.
+type ScopeId = usize;
+
+/// Identifies a scope by its parent and its name.
+type ScopeKey = (Option<ScopeId>, &'static str);
+
+/// Statistics of one shard, padded to avoid false sharing between shards.
+#[repr(align(64))]
+struct Shard {
+    num_calls: AtomicU64,
+    duration_sum_ns: AtomicU64,
+    duration_min_ns: AtomicU64,
+    duration_max_ns: AtomicU64,
+}
+
+impl Shard {
+    fn new() -> Shard {
+        Shard {
+            num_calls: AtomicU64::new(0),
+            duration_sum_ns: AtomicU64::new(0),
+            duration_min_ns: AtomicU64::new(u64::MAX),
+            duration_max_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        self.num_calls.store(0, Ordering::Relaxed);
+        self.duration_sum_ns.store(0, Ordering::Relaxed);
+        self.duration_min_ns.store(u64::MAX, Ordering::Relaxed);
+        self.duration_max_ns.store(0, Ordering::Relaxed);
+    }
+
+    fn record(&self, duration_ns: u64) {
+        self.num_calls.fetch_add(1, Ordering::Relaxed);
+
+        // Saturate instead of wrapping around, like the thread-local
+        // profiler does.
+        let _ = self
+            .duration_sum_ns
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some(sum.saturating_add(duration_ns))
+            });
+        self.duration_min_ns
+            .fetch_min(duration_ns, Ordering::Relaxed);
+        self.duration_max_ns
+            .fetch_max(duration_ns, Ordering::Relaxed);
+    }
+}
+
+/// Statistics of a scope in the shared tree.
+struct Stats {
+    shards: [Shard; NUM_SHARDS],
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            shards: [(); NUM_SHARDS].map(|_| Shard::new()),
+        }
+    }
+}
+
+/// A scope in the shared tree.
+///
+/// Scopes are never removed, so their statistics are leaked once and
+/// referenced without counting. This way, entering and leaving a scope only
+/// touches the shard of the current thread.
+struct Scope {
+    name: &'static str,
+    succs: Vec<ScopeId>,
+    stats: &'static Stats,
+}
+
+/// The shared scope tree.
+struct Tree {
+    scopes: Vec<Scope>,
+    roots: Vec<ScopeId>,
+    by_name: HashMap<ScopeKey, ScopeId>,
+    start_time: Instant,
+}
+
+impl Tree {
+    fn find_or_add(&mut self, pred: Option<ScopeId>, name: &'static str) -> ScopeId {
+        if let Some(&id) = self.by_name.get(&(pred, name)) {
+            return id;
+        }
+
+        let id = self.scopes.len();
+        self.scopes.push(Scope {
+            name,
+            succs: Vec::new(),
+            stats: Box::leak(Box::new(Stats::new())),
+        });
+        self.by_name.insert((pred, name), id);
+
+        match pred {
+            Some(pred) => self.scopes[pred].succs.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    fn stats(&self, id: ScopeId) -> ScopeStats {
+        let scope = &self.scopes[id];
+
+        let mut num_calls = 0;
+        let mut duration_sum_ns = 0u64;
+        let mut duration_min_ns = u64::MAX;
+        let mut duration_max_ns = 0;
+        for shard in scope.stats.shards.iter() {
+            num_calls += shard.num_calls.load(Ordering::Relaxed);
+            duration_sum_ns =
+                duration_sum_ns.saturating_add(shard.duration_sum_ns.load(Ordering::Relaxed));
+            duration_min_ns = duration_min_ns.min(shard.duration_min_ns.load(Ordering::Relaxed));
+            duration_max_ns = duration_max_ns.max(shard.duration_max_ns.load(Ordering::Relaxed));
+        }
+
+        // Shards that have not recorded a call since `reset` still have the
+        // initial minimum.
+        if num_calls == 0 {
+            duration_min_ns = 0;
+        }
+
+        ScopeStats {
+            name: scope.name.to_string(),
+            num_calls: num_calls as usize,
+            duration_sum: Duration::from_nanos(duration_sum_ns),
+            duration_min: Duration::from_nanos(duration_min_ns),
+            duration_max: Duration::from_nanos(duration_max_ns),
+            children: scope.succs.iter().map(|&succ| self.stats(succ)).collect(),
+            ..ScopeStats::default()
+        }
+    }
+}
+
+fn tree() -> MutexGuard<'static, Tree> {
+    static TREE: OnceLock<Mutex<Tree>> = OnceLock::new();
+
+    let tree = TREE.get_or_init(|| {
+        Mutex::new(Tree {
+            scopes: Vec::new(),
+            roots: Vec::new(),
+            by_name: HashMap::new(),
+            start_time: Instant::now(),
+        })
+    });
+
+    // The tree is never left in an inconsistent state, so we can ignore
+    // poisoning.
+    tree.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Per-thread state for the shared profiler.
+struct Local {
+    /// Shard that this thread records its statistics into.
+    shard: usize,
+
+    /// Scopes that this thread has visited, so that we do not need to lock
+    /// the tree again.
+    cache: HashMap<ScopeKey, (ScopeId, &'static Stats)>,
+}
+
+thread_local!(
+    static CURRENT: Cell<Option<ScopeId>> = const { Cell::new(None) };
+    static LOCAL: RefCell<Local> = RefCell::new(Local::new());
+);
+
+impl Local {
+    fn new() -> Local {
+        static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+        Local {
+            shard: NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % NUM_SHARDS,
+            cache: HashMap::new(),
+        }
+    }
+}
+
+/// Enter a scope in the shared profiler.
+///
+/// The returned [`Guard`](struct.Guard.html) should be dropped when leaving
+/// the scope. Usually, it is more convenient to use the
+/// [`profile_shared`](../macro.profile_shared.html) macro.
+pub fn enter(name: &'static str) -> Guard {
+    let pred = CURRENT.with(Cell::get);
+
+    let (id, stats, shard) = LOCAL.with(|local| {
+        let mut local = local.borrow_mut();
+        let shard = local.shard;
+
+        let (id, stats) = *local.cache.entry((pred, name)).or_insert_with(|| {
+            let mut tree = tree();
+            let id = tree.find_or_add(pred, name);
+            (id, tree.scopes[id].stats)
+        });
+
+        (id, stats, shard)
+    });
+
+    CURRENT.with(|current| current.set(Some(id)));
+
+    Guard {
+        pred,
+        stats,
+        shard,
+        enter_time: Instant::now(),
+        _not_send: PhantomData,
+    }
+}
+
+/// Print the shared scope tree, in the same format as
+/// [`write`](../fn.write.html).
+pub fn write<W: io::Write>(out: &mut W) -> io::Result<()> {
+    write!(out, "{}", snapshot())?;
+    out.flush()
+}
+
+/// Take a snapshot of the shared scope tree.
+pub fn snapshot() -> ProfileSnapshot {
+    let tree = tree();
+
+    ProfileSnapshot {
+        total_duration: tree.start_time.elapsed(),
+        metadata: Vec::new(),
+        overhead: None,
+        roots: tree.roots.iter().map(|&root| tree.stats(root)).collect(),
+    }
+}
+
+/// Reset the statistics of the shared profiler.
+///
+/// Since other threads may be caching them, the scopes themselves are kept.
+pub fn reset() {
+    let mut tree = tree();
+
+    for scope in tree.scopes.iter() {
+        for shard in scope.stats.shards.iter() {
+            shard.reset();
+        }
+    }
+    tree.start_time = Instant::now();
+}
+
+/// A guard that is created when entering a scope of the shared profiler and
+/// dropped when leaving it.
+pub struct Guard {
+    pred: Option<ScopeId>,
+    stats: &'static Stats,
+    shard: usize,
+    enter_time: Instant,
+
+    /// The guard restores the current scope of the thread that created it,
+    /// so it must not be sent to another thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let duration_ns = clock::as_nanos(self.enter_time.elapsed());
+
+        self.stats.shards[self.shard].record(duration_ns);
+        CURRENT.with(|current| current.set(self.pred));
+    }
+}
+
+/// Add the current scope to the shared profiler.
+///
+/// This is the counterpart of [`profile`](macro.profile.html) for the
+/// [`shared`](shared/index.html) profiler.
+#[macro_export]
+macro_rules! profile_shared {
+    ($name:expr) => {
+        let _guard = $crate::shared::enter($name);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use super::Shard;
+
+    #[test]
+    fn test_shard_saturates() {
+        let shard = Shard::new();
+        shard.record(u64::MAX - 1);
+        shard.record(5);
+        assert_eq!(shard.duration_sum_ns.load(Ordering::Relaxed), u64::MAX);
+        assert_eq!(shard.num_calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_threads_share_tree() {
+        // Use unique names, since the tree is shared with other tests.
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..10 {
+                        profile_shared!("test_threads_share_tree");
+                        profile_shared!("child");
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let snapshot = super::snapshot();
+        let root = snapshot
+            .roots
+            .iter()
+            .find(|root| root.name == "test_threads_share_tree")
+            .unwrap();
+
+        assert_eq!(root.num_calls, 40);
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.children[0].name, "child");
+        assert_eq!(root.children[0].num_calls, 40);
+    }
+
+    #[test]
+    fn test_unvisited_min() {
+        let id = super::tree().find_or_add(None, "test_unvisited_min");
+        let stats = super::tree().stats(id);
+        assert_eq!(stats.num_calls, 0);
+        assert_eq!(stats.duration_min, std::time::Duration::new(0, 0));
+    }
+}
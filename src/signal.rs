@@ -0,0 +1,36 @@
+//! Writing the report upon receiving `SIGUSR1`.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Has `SIGUSR1` been received since the report was last written?
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(_: libc::c_int) {
+    // Only async-signal-safe operations are allowed here, so we merely set a
+    // flag that the profiled thread checks between frames.
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Install the handler for `SIGUSR1`.
+pub fn install() -> io::Result<()> {
+    // Safety: An all-zero `sigaction` is valid, and the handler only performs
+    // an atomic store.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether `SIGUSR1` has been received since the last call.
+pub fn take_received() -> bool {
+    RECEIVED.swap(false, Ordering::SeqCst)
+}
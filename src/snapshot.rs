@@ -0,0 +1,1700 @@
+//! Owned copies of the profiling data.
+
+use std::backtrace::Backtrace;
+use std::cmp::Reverse;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock;
+use crate::json;
+use crate::report::{Report, ReportOptions};
+
+/// A copy of the scope tree of the profiler at some point in time.
+///
+/// The `Display` implementation formats the snapshot as the report that is
+/// printed by [`write`](fn.write.html).
+///
+/// More fields may be added in the future. To build a snapshot, e.g. for
+/// tests, start from `ProfileSnapshot::default()`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ProfileSnapshot {
+    /// Time since the profiler was created or last reset.
+    pub total_duration: Duration,
+
+    /// Key-value metadata attached to the profiler, in insertion order.
+    pub metadata: Vec<(String, String)>,
+
+    /// Root scopes of the tree.
+    pub roots: Vec<ScopeStats>,
+
+    /// Time spent in the bookkeeping of the profiler itself while entering
+    /// and leaving scopes. This is `None` unless it is measured, see
+    /// [`set_measure_overhead`](fn.set_measure_overhead.html).
+    pub overhead: Option<Duration>,
+}
+
+/// Number of buckets in [`FRAME_TIME_BUCKETS`](constant.FRAME_TIME_BUCKETS.html).
+pub const NUM_FRAME_TIME_BUCKETS: usize = 5;
+
+/// Upper bounds of the buckets of the histogram of the durations of root
+/// scopes, see [`ScopeStats::frame_histogram`](struct.ScopeStats.html#structfield.frame_histogram).
+/// These correspond to frame rates of 120, 60, 30, 20 and 10 Hz.
+pub const FRAME_TIME_BUCKETS: [Duration; NUM_FRAME_TIME_BUCKETS] = [
+    Duration::from_nanos(8_333_333),
+    Duration::from_nanos(16_666_667),
+    Duration::from_nanos(33_333_333),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+];
+
+/// Statistics of a single scope in a [`ProfileSnapshot`](struct.ProfileSnapshot.html).
+///
+/// The `Display` implementation formats the statistics as a single row.
+///
+/// More fields may be added in the future. To build statistics, e.g. for
+/// tests, start from `ScopeStats::default()`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ScopeStats {
+    /// Name of the scope.
+    pub name: String,
+
+    /// Id of the scope, which is the same for the same path in the tree in
+    /// all snapshots of all threads, also after resets, until the process
+    /// exits. This allows tools to track scopes across snapshots without
+    /// comparing their paths. `None` for scopes that have not been recorded,
+    /// e.g. the scopes set with
+    /// [`set_thread_parent`](fn.set_thread_parent.html).
+    pub id: Option<u64>,
+
+    /// How often has this scope been visited?
+    pub num_calls: usize,
+
+    /// In total, how much time has been spent in this scope?
+    pub duration_sum: Duration,
+
+    /// Minimal duration spent in this scope.
+    pub duration_min: Duration,
+
+    /// Maximal duration spent in this scope.
+    pub duration_max: Duration,
+
+    /// If the scope was being visited when taking the snapshot, and active
+    /// scopes were requested, the time spent in it so far. In this case, the
+    /// active call is already included in `num_calls` and `duration_sum`.
+    pub active_for: Option<Duration>,
+
+    /// Sample variance of the durations of the calls, in seconds squared.
+    /// This is `None` if the scope has been called less than twice, or if
+    /// the statistics have been aggregated from multiple scopes.
+    pub duration_variance: Option<f64>,
+
+    /// In total, how much CPU time has the thread consumed in this scope? This
+    /// is only available if CPU time has been measured, see
+    /// [`set_measure_cpu_time`](fn.set_measure_cpu_time.html).
+    pub cpu_time_sum: Option<Duration>,
+
+    /// For root scopes, the total time between leaving the scope and entering
+    /// it again. If the root scope is a frame, this is the time spent outside
+    /// of frames, e.g. waiting for vsync. This is `None` for other scopes.
+    pub idle_sum: Option<Duration>,
+
+    /// For root scopes, how many calls fell into each bucket of
+    /// [`FRAME_TIME_BUCKETS`](constant.FRAME_TIME_BUCKETS.html). The last
+    /// entry counts the calls that took longer than the last bucket. This is
+    /// `None` for other scopes.
+    pub frame_histogram: Option<[usize; NUM_FRAME_TIME_BUCKETS + 1]>,
+
+    /// For the frame scope, the average durations of the slowest frames
+    /// among the recent ones. This is `None` for other scopes, see
+    /// [`set_frame_scope`](fn.set_frame_scope.html).
+    pub frame_lows: Option<FrameLows>,
+
+    /// For root scopes, the durations of the most recent calls, starting
+    /// with the oldest one, for showing a sparkline in the report, see
+    /// [`ReportOptions::sparkline`](struct.ReportOptions.html#method.sparkline).
+    /// This is empty for other scopes.
+    pub recent_durations: Vec<Duration>,
+
+    /// Average duration of the calls, in which the weight of every call
+    /// halves with every half-life of wall time that has passed since it.
+    /// This is `None` unless a half-life has been set with
+    /// [`set_half_life`](fn.set_half_life.html).
+    pub decayed_mean: Option<Duration>,
+
+    /// Approximate median of the durations. This is `None` unless the median
+    /// is estimated, see
+    /// [`set_estimate_median`](fn.set_estimate_median.html).
+    pub duration_median: Option<Duration>,
+
+    /// Backtrace of the call with the maximal duration, captured when the
+    /// call was left. This is `None` unless the `backtrace` feature is
+    /// enabled.
+    pub max_backtrace: Option<Arc<Backtrace>>,
+
+    /// How often the scope has been entered again while it was the innermost
+    /// active scope, e.g. by a recursive function. Such calls are not
+    /// counted in `num_calls`, since their durations are already included in
+    /// the outer call, and their children are recorded as children of this
+    /// scope.
+    pub recursions: usize,
+
+    /// Has the scope been marked as waiting, e.g. for vsync or a lock, with
+    /// [`profile_wait`](macro.profile_wait.html)? Waiting scopes are flagged
+    /// in the report, and their time can be excluded from their ancestors
+    /// with [`without_wait`](struct.ProfileSnapshot.html#method.without_wait).
+    pub wait: bool,
+
+    /// Key-value attributes that have been attached to calls of this scope
+    /// with [`attr`](fn.attr.html).
+    pub attrs: Vec<AttrStats>,
+
+    /// Child scopes in the tree.
+    pub children: Vec<ScopeStats>,
+}
+
+/// The "1% low" and "0.1% low" frame times, which are the standard metric of
+/// stutter in game benchmarks.
+///
+/// See [`ScopeStats::frame_lows`](struct.ScopeStats.html#structfield.frame_lows).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameLows {
+    /// Average duration of the slowest 1% of the frames.
+    pub one_percent: Duration,
+
+    /// Average duration of the slowest 0.1% of the frames.
+    pub point_one_percent: Duration,
+
+    /// Number of recent frames that the lows have been computed from.
+    pub num_frames: usize,
+}
+
+impl FrameLows {
+    /// Compute the lows of the frame durations in `frames_ns`, which must not
+    /// be empty.
+    pub(crate) fn new(frames_ns: impl Iterator<Item = u64>) -> FrameLows {
+        let mut frames_ns: Vec<_> = frames_ns.collect();
+        frames_ns.sort_unstable_by(|a, b| b.cmp(a));
+
+        // Average of the slowest `fraction` of the frames, but at least of the
+        // slowest frame.
+        let low = |fraction: f64| {
+            let n = ((frames_ns.len() as f64 * fraction).ceil() as usize).max(1);
+            let sum: u64 = frames_ns[..n].iter().sum();
+            Duration::from_nanos(sum / n as u64)
+        };
+
+        FrameLows {
+            one_percent: low(0.01),
+            point_one_percent: low(0.001),
+            num_frames: frames_ns.len(),
+        }
+    }
+}
+
+/// Aggregated values of an attribute that has been attached to calls of a
+/// scope with [`attr`](fn.attr.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrStats {
+    /// Key of the attribute.
+    pub key: String,
+
+    /// How many values have been attached?
+    pub count: usize,
+
+    /// Sum of all values.
+    pub sum: f64,
+
+    /// Minimal value.
+    pub min: f64,
+
+    /// Maximal value.
+    pub max: f64,
+}
+
+impl AttrStats {
+    pub(crate) fn new(key: &str) -> AttrStats {
+        AttrStats {
+            key: key.to_string(),
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub(crate) fn record(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &AttrStats) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Average of all values. Returns NaN if no value has been attached.
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+/// The chain of scopes from a root to a leaf that accounts for the most time.
+///
+/// Created by [`ProfileSnapshot::hottest_path`](struct.ProfileSnapshot.html#method.hottest_path).
+/// The `Display` implementation formats the path as a single line, e.g.:
+/// ```text
+/// frame 98.12% > physics 61.40% > collisions 40.03%
+/// ```
+#[derive(Debug, Clone)]
+pub struct HotPath {
+    /// Scopes on the path, starting with the root scope.
+    pub entries: Vec<HotPathEntry>,
+}
+
+/// A scope on a [`HotPath`](struct.HotPath.html).
+#[derive(Debug, Clone)]
+pub struct HotPathEntry {
+    /// Name of the scope.
+    pub name: String,
+
+    /// In total, how much time has been spent in this scope?
+    pub duration_sum: Duration,
+
+    /// Time spent in this scope relative to the total time, in percent.
+    pub percent: f64,
+}
+
+impl ScopeStats {
+    /// Time spent in this scope, but not in any of its children.
+    pub fn self_duration(&self) -> Duration {
+        let children_sum: Duration = self.children.iter().map(|child| child.duration_sum).sum();
+        self.duration_sum.saturating_sub(children_sum)
+    }
+
+    /// Returns a copy of this scope without its children.
+    fn clone_without_children(&self) -> ScopeStats {
+        ScopeStats {
+            name: self.name.clone(),
+            id: self.id,
+            num_calls: self.num_calls,
+            duration_sum: self.duration_sum,
+            duration_min: self.duration_min,
+            duration_max: self.duration_max,
+            active_for: self.active_for,
+            duration_variance: self.duration_variance,
+            cpu_time_sum: self.cpu_time_sum,
+            idle_sum: self.idle_sum,
+            frame_histogram: self.frame_histogram,
+            frame_lows: self.frame_lows,
+            recent_durations: self.recent_durations.clone(),
+            decayed_mean: self.decayed_mean,
+            duration_median: self.duration_median,
+            max_backtrace: self.max_backtrace.clone(),
+            recursions: self.recursions,
+            wait: self.wait,
+            attrs: self.attrs.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Add the statistics of `other`, which is the same scope in another
+    /// tree, to this scope. Children are matched by name.
+    fn merge(&mut self, other: &ScopeStats) {
+        if other.num_calls > 0 {
+            if self.num_calls == 0 || other.duration_max > self.duration_max {
+                self.max_backtrace = other.max_backtrace.clone();
+            }
+            if self.num_calls > 0 {
+                self.duration_min = self.duration_min.min(other.duration_min);
+                self.duration_max = self.duration_max.max(other.duration_max);
+            } else {
+                self.duration_min = other.duration_min;
+                self.duration_max = other.duration_max;
+            }
+        }
+
+        // Combine the variances with the parallel variant of Welford's
+        // algorithm.
+        self.duration_variance = match (self.duration_variance, other.duration_variance) {
+            (Some(variance), Some(other_variance)) => {
+                let n_a = self.num_calls as f64;
+                let n_b = other.num_calls as f64;
+                let n = n_a + n_b;
+                let delta =
+                    other.duration_mean().as_secs_f64() - self.duration_mean().as_secs_f64();
+                let m2 = variance * (n_a - 1.0)
+                    + other_variance * (n_b - 1.0)
+                    + delta * delta * n_a * n_b / n;
+                Some(m2 / (n - 1.0))
+            }
+            (variance, None) if other.num_calls == 0 => variance,
+            (None, other_variance) if self.num_calls == 0 => other_variance,
+            _ => None,
+        };
+
+        self.num_calls += other.num_calls;
+        self.recursions += other.recursions;
+        self.wait |= other.wait;
+        self.recent_durations
+            .extend_from_slice(&other.recent_durations);
+        let excess = self
+            .recent_durations
+            .len()
+            .saturating_sub(crate::NUM_RECENT_DURATIONS);
+        self.recent_durations.drain(..excess);
+        self.duration_sum = self.duration_sum.saturating_add(other.duration_sum);
+        self.active_for = self.active_for.max(other.active_for);
+        if let Some(cpu_time_sum) = other.cpu_time_sum {
+            self.cpu_time_sum = Some(
+                self.cpu_time_sum
+                    .unwrap_or_default()
+                    .saturating_add(cpu_time_sum),
+            );
+        }
+        if let Some(idle_sum) = other.idle_sum {
+            self.idle_sum = Some(self.idle_sum.unwrap_or_default().saturating_add(idle_sum));
+        }
+        if let Some(other_histogram) = other.frame_histogram {
+            let histogram = self
+                .frame_histogram
+                .get_or_insert([0; NUM_FRAME_TIME_BUCKETS + 1]);
+            for (count, other_count) in histogram.iter_mut().zip(other_histogram.iter()) {
+                *count += other_count;
+            }
+        }
+
+        // The lows cannot be combined without the individual frame times.
+        if self.frame_lows.is_none() {
+            self.frame_lows = other.frame_lows;
+        }
+
+        // Neither can the decayed means without knowing their weights.
+        if self.id.is_none() {
+            self.id = other.id;
+        }
+        if self.decayed_mean.is_none() {
+            self.decayed_mean = other.decayed_mean;
+        }
+        if self.duration_median.is_none() {
+            self.duration_median = other.duration_median;
+        }
+
+        for attr in &other.attrs {
+            match self
+                .attrs
+                .iter_mut()
+                .find(|self_attr| self_attr.key == attr.key)
+            {
+                Some(self_attr) => self_attr.merge(attr),
+                None => self.attrs.push(attr.clone()),
+            }
+        }
+
+        merge_scopes(&mut self.children, &other.children);
+    }
+
+    /// Average duration spent in this scope per call. Returns zero if the
+    /// scope has not been visited yet.
+    pub fn duration_mean(&self) -> Duration {
+        if self.num_calls > 0 {
+            self.duration_sum.div_f64(self.num_calls as f64)
+        } else {
+            Duration::new(0, 0)
+        }
+    }
+
+    /// Sample standard deviation of the durations of the calls.
+    pub fn duration_std_dev(&self) -> Option<Duration> {
+        self.duration_variance
+            .map(|variance| Duration::from_secs_f64(variance.sqrt()))
+    }
+
+    /// Standard error of the mean duration, i.e. the standard deviation
+    /// divided by the square root of the number of calls.
+    pub fn duration_std_error(&self) -> Option<Duration> {
+        self.duration_variance
+            .map(|variance| Duration::from_secs_f64((variance / self.num_calls as f64).sqrt()))
+    }
+
+    /// The 95% confidence interval of the mean duration, based on Student's
+    /// t-distribution. Use this for checking whether the difference between
+    /// two measurements, e.g. before and after an optimization, is
+    /// significant.
+    pub fn duration_confidence_interval(&self) -> Option<(Duration, Duration)> {
+        let std_error = self.duration_std_error()?.as_secs_f64();
+        let mean = self.duration_mean().as_secs_f64();
+        let margin = t_95(self.num_calls - 1) * std_error;
+
+        Some((
+            Duration::from_secs_f64((mean - margin).max(0.0)),
+            Duration::from_secs_f64(mean + margin),
+        ))
+    }
+
+    pub(crate) fn fmt_active(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(active_for) = self.active_for {
+            write!(
+                f,
+                " [active for {:.2}ms]",
+                active_for.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a scope that has been written with `write_json`.
+    fn from_json(value: &json::Value) -> Result<ScopeStats, String> {
+        let name = value
+            .get("name")
+            .and_then(json::Value::as_str)
+            .ok_or("missing `name` of scope")?;
+        let count = |key| value.get(key).and_then(json::Value::as_u64);
+
+        let frame_histogram = value
+            .get("frame_histogram")
+            .and_then(json::Value::as_array)
+            .filter(|counts| counts.len() == NUM_FRAME_TIME_BUCKETS + 1)
+            .map(|counts| {
+                let mut histogram = [0; NUM_FRAME_TIME_BUCKETS + 1];
+                for (bucket, count) in histogram.iter_mut().zip(counts) {
+                    *bucket = count.as_u64().unwrap_or(0) as usize;
+                }
+                histogram
+            });
+        let frame_lows = secs(value, "one_percent_low_secs")
+            .zip(secs(value, "point_one_percent_low_secs"))
+            .map(|(one_percent, point_one_percent)| FrameLows {
+                one_percent,
+                point_one_percent,
+                num_frames: 0,
+            });
+        let attrs = value
+            .get("attrs")
+            .and_then(json::Value::as_object)
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, attr)| {
+                let count = attr.get("count").and_then(json::Value::as_u64).unwrap_or(0);
+                let number = |key| attr.get(key).and_then(json::Value::as_f64);
+                AttrStats {
+                    key: key.clone(),
+                    count: count as usize,
+                    sum: number("mean").map_or(0.0, |mean| mean * count as f64),
+                    min: number("min").unwrap_or(f64::INFINITY),
+                    max: number("max").unwrap_or(f64::NEG_INFINITY),
+                }
+            })
+            .collect();
+        let children = value
+            .get("children")
+            .and_then(json::Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(ScopeStats::from_json)
+            .collect::<Result<_, _>>()?;
+
+        Ok(ScopeStats {
+            name: name.to_string(),
+            id: count("id"),
+            num_calls: count("num_calls").ok_or("missing `num_calls` of scope")? as usize,
+            duration_sum: secs(value, "duration_sum_secs").unwrap_or_default(),
+            duration_min: secs(value, "duration_min_secs").unwrap_or_default(),
+            duration_max: secs(value, "duration_max_secs").unwrap_or_default(),
+            active_for: secs(value, "active_for_secs"),
+            duration_variance: secs(value, "duration_std_dev_secs")
+                .map(|std_dev| std_dev.as_secs_f64().powi(2)),
+            cpu_time_sum: secs(value, "cpu_time_sum_secs"),
+            idle_sum: secs(value, "idle_sum_secs"),
+            frame_histogram,
+            frame_lows,
+            decayed_mean: secs(value, "decayed_mean_secs"),
+            duration_median: secs(value, "duration_median_secs"),
+            max_backtrace: None,
+            recursions: count("recursions").unwrap_or(0) as usize,
+            recent_durations: Vec::new(),
+            wait: value.get("wait").and_then(json::Value::as_bool) == Some(true),
+            attrs,
+            children,
+        })
+    }
+
+    fn write_json<W: io::Write>(&self, parent_id: Option<u64>, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"name\":")?;
+        json::write_str(out, &self.name)?;
+        if let Some(id) = self.id {
+            write!(out, ",\"id\":{}", id)?;
+        }
+        if let Some(parent_id) = parent_id {
+            write!(out, ",\"parent_id\":{}", parent_id)?;
+        }
+        write!(out, ",\"num_calls\":{}", self.num_calls)?;
+
+        let (mean, min, max) = if self.num_calls > 0 {
+            (
+                self.duration_mean().as_secs_f64(),
+                self.duration_min.as_secs_f64(),
+                self.duration_max.as_secs_f64(),
+            )
+        } else {
+            (f64::NAN, f64::NAN, f64::NAN)
+        };
+
+        for (key, value) in &[
+            ("duration_sum_secs", self.duration_sum.as_secs_f64()),
+            ("duration_mean_secs", mean),
+            ("duration_min_secs", min),
+            ("duration_max_secs", max),
+        ] {
+            write!(out, ",\"{}\":", key)?;
+            json::write_f64(out, *value)?;
+        }
+
+        if let Some(std_dev) = self.duration_std_dev() {
+            write!(out, ",\"duration_std_dev_secs\":")?;
+            json::write_f64(out, std_dev.as_secs_f64())?;
+        }
+
+        if let Some(cpu_time_sum) = self.cpu_time_sum {
+            write!(out, ",\"cpu_time_sum_secs\":")?;
+            json::write_f64(out, cpu_time_sum.as_secs_f64())?;
+        }
+
+        if let Some(backtrace) = &self.max_backtrace {
+            write!(out, ",\"max_backtrace\":")?;
+            json::write_str(out, &backtrace.to_string())?;
+        }
+
+        if self.recursions > 0 {
+            write!(out, ",\"recursions\":{}", self.recursions)?;
+        }
+        if self.wait {
+            write!(out, ",\"wait\":true")?;
+        }
+
+        if let Some(median) = self.duration_median {
+            write!(out, ",\"duration_median_secs\":")?;
+            json::write_f64(out, median.as_secs_f64())?;
+        }
+
+        if let Some(decayed_mean) = self.decayed_mean {
+            write!(out, ",\"decayed_mean_secs\":")?;
+            json::write_f64(out, decayed_mean.as_secs_f64())?;
+        }
+
+        if let Some(lows) = self.frame_lows {
+            write!(out, ",\"one_percent_low_secs\":")?;
+            json::write_f64(out, lows.one_percent.as_secs_f64())?;
+            write!(out, ",\"point_one_percent_low_secs\":")?;
+            json::write_f64(out, lows.point_one_percent.as_secs_f64())?;
+        }
+
+        if let Some(frame_histogram) = self.frame_histogram {
+            write!(out, ",\"frame_histogram\":[")?;
+            for (i, count) in frame_histogram.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "{}", count)?;
+            }
+            write!(out, "]")?;
+        }
+
+        if let Some(idle_sum) = self.idle_sum {
+            write!(out, ",\"idle_sum_secs\":")?;
+            json::write_f64(out, idle_sum.as_secs_f64())?;
+        }
+
+        if let Some(active_for) = self.active_for {
+            write!(out, ",\"active_for_secs\":")?;
+            json::write_f64(out, active_for.as_secs_f64())?;
+        }
+
+        if !self.attrs.is_empty() {
+            write!(out, ",\"attrs\":{{")?;
+            for (i, attr) in self.attrs.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                json::write_str(out, &attr.key)?;
+                write!(out, ":{{\"count\":{}", attr.count)?;
+                for (key, value) in &[("mean", attr.mean()), ("min", attr.min), ("max", attr.max)] {
+                    write!(out, ",\"{}\":", key)?;
+                    json::write_f64(out, *value)?;
+                }
+                write!(out, "}}")?;
+            }
+            write!(out, "}}")?;
+        }
+
+        write!(out, ",\"children\":[")?;
+        for (i, child) in self.children.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            child.write_json(self.id, out)?;
+        }
+        write!(out, "]}}")
+    }
+}
+
+/// Returns the two-sided 95% critical value of Student's t-distribution with
+/// `df` degrees of freedom.
+fn t_95(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+
+    match df {
+        0 => f64::INFINITY,
+        1..=30 => TABLE[df - 1],
+        31..=60 => 2.000,
+        61..=120 => 1.980,
+        _ => 1.960,
+    }
+}
+
+/// Merge `others` into `scopes`, matching scopes by name.
+fn merge_scopes(scopes: &mut Vec<ScopeStats>, others: &[ScopeStats]) {
+    for other in others {
+        match scopes.iter_mut().find(|scope| scope.name == other.name) {
+            Some(scope) => scope.merge(other),
+            None => scopes.push(other.clone()),
+        }
+    }
+}
+
+impl ProfileSnapshot {
+    /// Add the statistics of `other`, e.g. the snapshot of another thread, to
+    /// this snapshot.
+    ///
+    /// Scopes are matched by their path, so that work which has been
+    /// attributed to the same logical parent with
+    /// [`in_scope_of`](fn.in_scope_of.html) ends up in the same subtree.
+    /// Scopes that only exist in `other` are appended. The total duration is
+    /// the larger one of both snapshots, and metadata keys of `other` that
+    /// are not yet present are added.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut snapshot = coarse_prof::snapshot();
+    ///
+    /// let worker = std::thread::spawn(|| {
+    ///     coarse_prof::profile!("load_assets");
+    ///     coarse_prof::snapshot()
+    /// });
+    /// snapshot.merge(&worker.join().unwrap());
+    /// ```
+    pub fn merge(&mut self, other: &ProfileSnapshot) {
+        self.total_duration = self.total_duration.max(other.total_duration);
+
+        for (key, value) in &other.metadata {
+            if !self.metadata.iter().any(|(other_key, _)| other_key == key) {
+                self.metadata.push((key.clone(), value.clone()));
+            }
+        }
+
+        if let Some(overhead) = other.overhead {
+            self.overhead = Some(self.overhead.unwrap_or_default() + overhead);
+        }
+
+        merge_scopes(&mut self.roots, &other.roots);
+    }
+
+    /// Returns a flat view of the snapshot, in which all scopes with the same
+    /// name are aggregated into a single root scope, regardless of their
+    /// position in the tree.
+    ///
+    /// This shows the combined cost of e.g. a helper that is called from many
+    /// places. The aggregated scopes are sorted by their total duration, in
+    /// descending order. If a scope is nested within a scope of the same name,
+    /// its calls are counted, but its duration and CPU time are not, since
+    /// they are already included in the outer scope.
+    pub fn flat(&self) -> ProfileSnapshot {
+        fn aggregate<'a>(
+            scope: &'a ScopeStats,
+            path: &mut Vec<&'a str>,
+            flat: &mut Vec<ScopeStats>,
+        ) {
+            let index = match flat.iter().position(|other| other.name == scope.name) {
+                Some(index) => index,
+                None => {
+                    flat.push(ScopeStats {
+                        name: scope.name.clone(),
+                        duration_min: Duration::MAX,
+                        ..ScopeStats::default()
+                    });
+                    flat.len() - 1
+                }
+            };
+
+            let entry = &mut flat[index];
+            entry.num_calls += scope.num_calls;
+            entry.wait |= scope.wait;
+            if !path.contains(&scope.name.as_str()) {
+                entry.duration_sum = entry.duration_sum.saturating_add(scope.duration_sum);
+                if let Some(cpu_time_sum) = scope.cpu_time_sum {
+                    entry.cpu_time_sum = Some(
+                        entry
+                            .cpu_time_sum
+                            .unwrap_or_default()
+                            .saturating_add(cpu_time_sum),
+                    );
+                }
+            }
+            entry.duration_min = entry.duration_min.min(scope.duration_min);
+            entry.duration_max = entry.duration_max.max(scope.duration_max);
+            for attr in &scope.attrs {
+                match entry.attrs.iter_mut().find(|other| other.key == attr.key) {
+                    Some(other) => other.merge(attr),
+                    None => entry.attrs.push(attr.clone()),
+                }
+            }
+
+            path.push(&scope.name);
+            for child in &scope.children {
+                aggregate(child, path, flat);
+            }
+            path.pop();
+        }
+
+        let mut flat = Vec::new();
+        for root in &self.roots {
+            aggregate(root, &mut Vec::new(), &mut flat);
+        }
+        flat.sort_by_key(|scope| Reverse(scope.duration_sum));
+
+        ProfileSnapshot {
+            total_duration: self.total_duration,
+            metadata: self.metadata.clone(),
+            overhead: self.overhead,
+            roots: flat,
+        }
+    }
+
+    /// Returns an inverted (bottom-up) view of the snapshot.
+    ///
+    /// The roots of the inverted tree are all scopes, aggregated by name and
+    /// weighted by their self time, i.e. the time not spent in their
+    /// children. The children of a scope in the inverted tree are its
+    /// callers, weighted by the self time that they are responsible for. This
+    /// answers questions such as "who calls `collisions`, and how much does
+    /// each caller contribute?".
+    ///
+    /// Scopes at each level are sorted by their duration, in descending
+    /// order.
+    pub fn inverted(&self) -> ProfileSnapshot {
+        fn find_or_add<'a>(scopes: &'a mut Vec<ScopeStats>, name: &str) -> &'a mut ScopeStats {
+            let index = match scopes.iter().position(|scope| scope.name == name) {
+                Some(index) => index,
+                None => {
+                    scopes.push(ScopeStats {
+                        name: name.to_string(),
+                        duration_min: Duration::MAX,
+                        ..ScopeStats::default()
+                    });
+                    scopes.len() - 1
+                }
+            };
+
+            &mut scopes[index]
+        }
+
+        fn invert<'a>(
+            scope: &'a ScopeStats,
+            path: &mut Vec<&'a ScopeStats>,
+            inverted: &mut Vec<ScopeStats>,
+        ) {
+            let children_sum = scope.children.iter().fold(Duration::ZERO, |sum, child| {
+                sum.saturating_add(child.duration_sum)
+            });
+            let self_duration = scope.duration_sum.saturating_sub(children_sum);
+
+            // Add the self time of `scope` to itself and all of its callers.
+            let mut scopes = &mut *inverted;
+            for caller in std::iter::once(scope).chain(path.iter().rev().copied()) {
+                let entry = find_or_add(scopes, &caller.name);
+                entry.num_calls += scope.num_calls;
+                entry.duration_sum = entry.duration_sum.saturating_add(self_duration);
+                entry.duration_min = entry.duration_min.min(scope.duration_min);
+                entry.duration_max = entry.duration_max.max(scope.duration_max);
+
+                scopes = &mut entry.children;
+            }
+
+            path.push(scope);
+            for child in &scope.children {
+                invert(child, path, inverted);
+            }
+            path.pop();
+        }
+
+        fn sort(scopes: &mut [ScopeStats]) {
+            scopes.sort_by_key(|scope| Reverse(scope.duration_sum));
+            for scope in scopes.iter_mut() {
+                sort(&mut scope.children);
+            }
+        }
+
+        let mut inverted = Vec::new();
+        for root in &self.roots {
+            invert(root, &mut Vec::new(), &mut inverted);
+        }
+        sort(&mut inverted);
+
+        ProfileSnapshot {
+            total_duration: self.total_duration,
+            metadata: self.metadata.clone(),
+            overhead: self.overhead,
+            roots: inverted,
+        }
+    }
+
+    /// Returns the snapshot without the scopes that have been marked as
+    /// waiting with [`profile_wait`](macro.profile_wait.html), whose time is
+    /// subtracted from all of their ancestors.
+    ///
+    /// This separates work from blocking, e.g. so that a frame that waits for
+    /// vsync does not look expensive. The CPU time of the waiting scopes is
+    /// subtracted as well. The minima, maxima and variances of the ancestors
+    /// still include the waiting time, since it is not known how it was
+    /// distributed over their calls. The idle time of root scopes is kept as
+    /// it is, since it is spent outside of the tree.
+    pub fn without_wait(&self) -> ProfileSnapshot {
+        /// Returns the scopes that do not wait, and the total time and CPU
+        /// time spent waiting in `scopes`.
+        fn strip(scopes: &[ScopeStats]) -> (Vec<ScopeStats>, Duration, Duration) {
+            let mut stripped = Vec::new();
+            let mut waited = Duration::new(0, 0);
+            let mut waited_cpu = Duration::new(0, 0);
+            for scope in scopes {
+                if scope.wait {
+                    waited = waited.saturating_add(scope.duration_sum);
+                    waited_cpu = waited_cpu.saturating_add(scope.cpu_time_sum.unwrap_or_default());
+                    continue;
+                }
+
+                let (children, children_waited, children_waited_cpu) = strip(&scope.children);
+                let mut scope = scope.clone_without_children();
+                scope.duration_sum = scope.duration_sum.saturating_sub(children_waited);
+                scope.cpu_time_sum = scope
+                    .cpu_time_sum
+                    .map(|cpu_time_sum| cpu_time_sum.saturating_sub(children_waited_cpu));
+                scope.children = children;
+                stripped.push(scope);
+                waited = waited.saturating_add(children_waited);
+                waited_cpu = waited_cpu.saturating_add(children_waited_cpu);
+            }
+            (stripped, waited, waited_cpu)
+        }
+
+        ProfileSnapshot {
+            total_duration: self.total_duration,
+            metadata: self.metadata.clone(),
+            overhead: self.overhead,
+            roots: strip(&self.roots).0,
+        }
+    }
+
+    /// Returns the chain of scopes that accounts for the most time, found by
+    /// starting at the most expensive root scope and repeatedly descending
+    /// into the most expensive child.
+    pub fn hottest_path(&self) -> HotPath {
+        let total_secs = self.total_duration.as_secs_f64();
+
+        let mut entries = Vec::new();
+        let mut scopes = &self.roots;
+        while let Some(scope) = scopes.iter().max_by_key(|scope| scope.duration_sum) {
+            entries.push(HotPathEntry {
+                name: scope.name.clone(),
+                duration_sum: scope.duration_sum,
+                percent: scope.duration_sum.as_secs_f64() / total_secs * 100.0,
+            });
+            scopes = &scope.children;
+        }
+
+        HotPath { entries }
+    }
+
+    /// Format the snapshot as a report with the given options.
+    ///
+    /// Note that snapshots returned by [`snapshot`](fn.snapshot.html) do not
+    /// include active scopes. Use [`write_with`](fn.write_with.html) for
+    /// showing them.
+    pub fn report<'a>(&'a self, options: &'a ReportOptions) -> Report<'a> {
+        Report {
+            snapshot: self,
+            options,
+            window: None,
+            calibration: if options.show_calibrated {
+                crate::overhead::calibration()
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Write the snapshot in the protobuf format of
+    /// [pprof](https://github.com/google/pprof), uncompressed.
+    ///
+    /// Every scope becomes a sample whose stack is the scope's path in the
+    /// tree. Samples have two values: the number of calls and the self time of
+    /// the scope, i.e. the time not spent in its children, in nanoseconds.
+    pub fn write_pprof<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        crate::pprof::write(self, out)
+    }
+
+    /// Write the snapshot in the folded stacks format that is read by
+    /// flamegraph tools such as
+    /// [inferno](https://github.com/jonhoo/inferno) and
+    /// [FlameGraph](https://github.com/brendangregg/FlameGraph).
+    ///
+    /// Every scope with a nonzero self time becomes one line, consisting of
+    /// its path with `;` as the separator, followed by its self time in
+    /// nanoseconds, e.g. `frame;physics 4000000`.
+    pub fn write_folded<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_folded_stacks(None, out)
+    }
+
+    /// Write the snapshot in the folded stacks format, with `prefix` as the
+    /// outermost frame of every stack, e.g. `main;frame;physics 4000000`.
+    ///
+    /// This matches the output of
+    /// [tracing-flame](https://docs.rs/tracing-flame), which prefixes stacks
+    /// with the thread, so that folded files of both can be merged or fed
+    /// into the same pipelines. See also
+    /// [`write_tracing_flame`](fn.write_tracing_flame.html).
+    pub fn write_folded_with_prefix<W: io::Write>(
+        &self,
+        prefix: &str,
+        out: &mut W,
+    ) -> io::Result<()> {
+        self.write_folded_stacks(Some(prefix), out)
+    }
+
+    fn write_folded_stacks<W: io::Write>(
+        &self,
+        prefix: Option<&str>,
+        out: &mut W,
+    ) -> io::Result<()> {
+        fn push_frame(stack: &mut String, name: &str) {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            // `;` separates frames and the last space separates the count.
+            stack.extend(name.chars().map(|c| match c {
+                ';' => ':',
+                ' ' => '_',
+                c => c,
+            }));
+        }
+
+        fn write_scope<W: io::Write>(
+            scope: &ScopeStats,
+            stack: &mut String,
+            out: &mut W,
+        ) -> io::Result<()> {
+            let len = stack.len();
+            push_frame(stack, &scope.name);
+
+            let self_ns = clock::as_nanos(scope.self_duration());
+            if self_ns > 0 {
+                writeln!(out, "{} {}", stack, self_ns)?;
+            }
+            for child in &scope.children {
+                write_scope(child, stack, out)?;
+            }
+
+            stack.truncate(len);
+            Ok(())
+        }
+
+        let mut stack = String::new();
+        if let Some(prefix) = prefix {
+            push_frame(&mut stack, prefix);
+        }
+        for root in &self.roots {
+            write_scope(root, &mut stack, out)?;
+        }
+        Ok(())
+    }
+
+    /// Render the snapshot as an SVG flamegraph with
+    /// [inferno](https://github.com/jonhoo/inferno).
+    ///
+    /// The widths of the frames are proportional to the time spent in the
+    /// scopes, as in [`write_folded`](#method.write_folded).
+    #[cfg(feature = "inferno")]
+    pub fn write_flamegraph_svg<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut folded = Vec::new();
+        self.write_folded(&mut folded)?;
+        let folded = String::from_utf8(folded).expect("scope names are UTF-8");
+
+        let mut options = inferno::flamegraph::Options::default();
+        options.title = "coarse-prof".to_string();
+        options.count_name = "ns".to_string();
+
+        inferno::flamegraph::from_lines(&mut options, folded.lines(), out).map_err(io::Error::other)
+    }
+
+    /// Write the snapshot in the InfluxDB line protocol, e.g. for piping into
+    /// InfluxDB and Grafana during soak tests.
+    ///
+    /// Every scope that has been visited becomes one line, with the name of
+    /// the scope as the measurement, its path and `thread` as tags, and its
+    /// mean, minimal and maximal duration in seconds, its number of calls and
+    /// its id and the id of its parent as fields, e.g.:
+    /// ```text
+    /// physics,path=frame/physics,thread=main mean_secs=0.004,min_secs=0.003,max_secs=0.006,calls=60i,id=2i,parent_id=1i 1700000000000000000
+    /// ```
+    /// All lines have `timestamp` as their timestamp, in nanoseconds.
+    pub fn write_influx<W: io::Write>(
+        &self,
+        thread: &str,
+        timestamp: SystemTime,
+        out: &mut W,
+    ) -> io::Result<()> {
+        fn write_scope<W: io::Write>(
+            scope: &ScopeStats,
+            parent_id: Option<u64>,
+            path: &mut String,
+            thread: &str,
+            timestamp_ns: u128,
+            out: &mut W,
+        ) -> io::Result<()> {
+            let len = path.len();
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(&scope.name);
+
+            if scope.num_calls > 0 {
+                write_influx_escaped(out, &scope.name, &[',', ' '])?;
+                write!(out, ",path=")?;
+                write_influx_escaped(out, path, &[',', '=', ' '])?;
+                write!(out, ",thread=")?;
+                write_influx_escaped(out, thread, &[',', '=', ' '])?;
+                write!(
+                    out,
+                    " mean_secs={},min_secs={},max_secs={},calls={}i",
+                    scope.duration_mean().as_secs_f64(),
+                    scope.duration_min.as_secs_f64(),
+                    scope.duration_max.as_secs_f64(),
+                    scope.num_calls,
+                )?;
+                if let Some(id) = scope.id {
+                    write!(out, ",id={}i", id)?;
+                }
+                if let Some(parent_id) = parent_id {
+                    write!(out, ",parent_id={}i", parent_id)?;
+                }
+                writeln!(out, " {}", timestamp_ns)?;
+            }
+
+            for child in &scope.children {
+                write_scope(child, scope.id, path, thread, timestamp_ns, out)?;
+            }
+
+            path.truncate(len);
+            Ok(())
+        }
+
+        let timestamp_ns = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        for root in &self.roots {
+            write_scope(root, None, &mut String::new(), thread, timestamp_ns, out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the snapshot as JSON.
+    pub fn write_json<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"total_duration_secs\":")?;
+        json::write_f64(out, self.total_duration.as_secs_f64())?;
+        write!(out, ",\"metadata\":{{")?;
+        for (i, (key, value)) in self.metadata.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            json::write_str(out, key)?;
+            write!(out, ":")?;
+            json::write_str(out, value)?;
+        }
+        write!(out, "}}")?;
+        if let Some(overhead) = self.overhead {
+            write!(out, ",\"overhead_secs\":")?;
+            json::write_f64(out, overhead.as_secs_f64())?;
+        }
+        write!(out, ",\"scopes\":[")?;
+        for (i, root) in self.roots.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            root.write_json(None, out)?;
+        }
+        write!(out, "]}}")
+    }
+
+    /// Read a snapshot that has been written with
+    /// [`write_json`](#method.write_json), e.g. for comparing saved runs.
+    ///
+    /// Backtraces can not be restored, so `max_backtrace` is always `None`,
+    /// and `num_frames` of the frame lows is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use coarse_prof::ProfileSnapshot;
+    ///
+    /// coarse_prof::profile!("frame");
+    ///
+    /// let mut json = Vec::new();
+    /// coarse_prof::snapshot().write_json(&mut json).unwrap();
+    ///
+    /// let snapshot = ProfileSnapshot::read_json(std::str::from_utf8(&json).unwrap()).unwrap();
+    /// assert_eq!(snapshot.roots[0].name, "frame");
+    /// ```
+    pub fn read_json(json: &str) -> io::Result<ProfileSnapshot> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let value = json::parse(json).map_err(invalid)?;
+
+        let metadata = value
+            .get("metadata")
+            .and_then(json::Value::as_object)
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+            .collect();
+        let roots = value
+            .get("scopes")
+            .and_then(json::Value::as_array)
+            .ok_or_else(|| invalid("missing `scopes`".to_string()))?
+            .iter()
+            .map(ScopeStats::from_json)
+            .collect::<Result<_, _>>()
+            .map_err(invalid)?;
+
+        Ok(ProfileSnapshot {
+            total_duration: secs(&value, "total_duration_secs").unwrap_or_default(),
+            metadata,
+            roots,
+            overhead: secs(&value, "overhead_secs"),
+        })
+    }
+}
+
+/// Returns the duration in seconds at `key` of a JSON object, if it is a
+/// number.
+fn secs(value: &json::Value, key: &str) -> Option<Duration> {
+    value
+        .get(key)
+        .and_then(json::Value::as_f64)
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+/// Write `value` with the characters in `special` escaped by a backslash, as
+/// required by the InfluxDB line protocol. Tag values may not be empty, so
+/// empty values are written as `_`.
+fn write_influx_escaped<W: io::Write>(
+    out: &mut W,
+    value: &str,
+    special: &[char],
+) -> io::Result<()> {
+    if value.is_empty() {
+        return write!(out, "_");
+    }
+
+    for c in value.chars() {
+        if special.contains(&c) {
+            write!(out, "\\")?;
+        }
+        write!(out, "{}", c)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for ProfileSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.report(&ReportOptions::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for HotPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, " > ")?;
+            }
+            write!(f, "{} {:.2}%", entry.name, entry.percent)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScopeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:>4.2}ms avg, {:>4.2}ms min, {:>4.2}ms max, {} calls",
+            self.name,
+            self.duration_mean().as_secs_f64() * 1000.0,
+            self.duration_min.as_secs_f64() * 1000.0,
+            self.duration_max.as_secs_f64() * 1000.0,
+            self.num_calls,
+        )?;
+        self.fmt_active(f)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{FrameLows, ProfileSnapshot, ScopeStats};
+
+    /// Statistics of a scope with two calls that took `millis` on average,
+    /// shared by the tests of the modules that consume snapshots.
+    pub(crate) fn stats(name: &str, millis: u64, children: Vec<ScopeStats>) -> ScopeStats {
+        ScopeStats {
+            name: name.to_string(),
+            num_calls: 2,
+            duration_sum: Duration::from_millis(2 * millis),
+            duration_min: Duration::from_millis(millis.saturating_sub(1)),
+            duration_max: Duration::from_millis(millis + 1),
+            children,
+            ..ScopeStats::default()
+        }
+    }
+
+    #[test]
+    fn test_display_scope_stats() {
+        let stats = stats("physics", 4, Vec::new());
+
+        assert_eq!(
+            stats.to_string(),
+            "physics: 4.00ms avg, 3.00ms min, 5.00ms max, 2 calls"
+        );
+    }
+
+    #[test]
+    fn test_display_snapshot() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![stats("physics", 4, Vec::new())])],
+        };
+
+        assert_eq!(
+            snapshot.to_string(),
+            "# elapsed: 1.00s\n\
+             frame: 2.00%, 10.00ms avg, 9.00ms min, 11.00ms max @ 2.00Hz\n  \
+             physics: 40.00%, 4.00ms avg, 3.00ms min, 5.00ms max @ 2.00Hz\n"
+        );
+    }
+
+    #[test]
+    fn test_display_metadata() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_millis(1500),
+            metadata: vec![("map".to_string(), "dust2".to_string())],
+            overhead: None,
+            roots: Vec::new(),
+        };
+
+        assert_eq!(snapshot.to_string(), "# map: dust2\n# elapsed: 1.50s\n");
+
+        let mut json = Vec::new();
+        snapshot.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"total_duration_secs\":1.5,\"metadata\":{\"map\":\"dust2\"},\"scopes\":[]}"
+        );
+    }
+
+    #[test]
+    fn test_flat() {
+        fn with_cpu_time(mut scope: ScopeStats) -> ScopeStats {
+            scope.cpu_time_sum = Some(scope.duration_sum / 2);
+            scope.children = scope.children.into_iter().map(with_cpu_time).collect();
+            scope
+        }
+
+        let mut snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats(
+                "frame",
+                10,
+                vec![
+                    stats("physics", 4, vec![stats("serialize", 1, Vec::new())]),
+                    stats(
+                        "net",
+                        3,
+                        vec![stats(
+                            "serialize",
+                            2,
+                            vec![stats("serialize", 1, Vec::new())],
+                        )],
+                    ),
+                ],
+            )],
+        };
+
+        snapshot.roots = snapshot.roots.into_iter().map(with_cpu_time).collect();
+
+        let flat = snapshot.flat();
+        let names: Vec<_> = flat.roots.iter().map(|scope| scope.name.as_str()).collect();
+        assert_eq!(names, vec!["frame", "physics", "serialize", "net"]);
+
+        let serialize = &flat.roots[2];
+        assert_eq!(serialize.num_calls, 6);
+        assert_eq!(serialize.duration_sum, Duration::from_millis(6));
+        assert_eq!(serialize.cpu_time_sum, Some(Duration::from_millis(3)));
+        assert_eq!(serialize.duration_min, Duration::from_millis(0));
+        assert_eq!(serialize.duration_max, Duration::from_millis(3));
+        assert!(serialize.children.is_empty());
+    }
+
+    #[test]
+    fn test_write_influx() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![stats("net, send", 2, Vec::new())])],
+        };
+
+        let mut out = Vec::new();
+        snapshot
+            .write_influx(
+                "main thread",
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "frame,path=frame,thread=main\\ thread \
+             mean_secs=0.01,min_secs=0.009,max_secs=0.011,calls=2i 1700000000000000000\n\
+             net\\,\\ send,path=frame/net\\,\\ send,thread=main\\ thread \
+             mean_secs=0.002,min_secs=0.001,max_secs=0.003,calls=2i 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: vec![("map".to_string(), "dust2".to_string())],
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![stats("physics", 4, Vec::new())])],
+        };
+
+        let mut placeholder = stats("frame", 1, Vec::new());
+        placeholder.num_calls = 0;
+        placeholder.duration_sum = Duration::new(0, 0);
+        placeholder.duration_min = Duration::MAX;
+        placeholder.duration_max = Duration::new(0, 0);
+        placeholder.children = vec![stats("physics", 2, Vec::new()), stats("ai", 3, Vec::new())];
+        let worker = ProfileSnapshot {
+            total_duration: Duration::from_secs(2),
+            metadata: vec![("map".to_string(), "inferno".to_string())],
+            overhead: None,
+            roots: vec![placeholder, stats("load", 5, Vec::new())],
+        };
+
+        snapshot.merge(&worker);
+
+        assert_eq!(snapshot.total_duration, Duration::from_secs(2));
+        assert_eq!(
+            snapshot.metadata,
+            vec![("map".to_string(), "dust2".to_string())]
+        );
+
+        let names: Vec<_> = snapshot
+            .roots
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["frame", "load"]);
+
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.num_calls, 2);
+        assert_eq!(frame.duration_sum, Duration::from_millis(20));
+        assert_eq!(frame.duration_min, Duration::from_millis(9));
+
+        let physics = &frame.children[0];
+        assert_eq!(physics.num_calls, 4);
+        assert_eq!(physics.duration_sum, Duration::from_millis(12));
+        assert_eq!(physics.duration_min, Duration::from_millis(1));
+        assert_eq!(physics.duration_max, Duration::from_millis(5));
+        assert_eq!(frame.children[1].name, "ai");
+
+        // Sums saturate instead of overflowing.
+        let mut long = stats("frame", 1, Vec::new());
+        long.duration_sum = Duration::MAX;
+        long.idle_sum = Some(Duration::MAX);
+        snapshot.merge(&ProfileSnapshot {
+            roots: vec![long],
+            ..ProfileSnapshot::default()
+        });
+        assert_eq!(snapshot.roots[0].duration_sum, Duration::MAX);
+        assert_eq!(snapshot.roots[0].idle_sum, Some(Duration::MAX));
+    }
+
+    #[test]
+    fn test_without_wait() {
+        let mut vsync = stats("vsync", 3, Vec::new());
+        vsync.wait = true;
+        vsync.cpu_time_sum = Some(Duration::from_millis(1));
+        let mut render = stats("render", 4, vec![vsync]);
+        render.cpu_time_sum = Some(Duration::from_millis(5));
+        let mut frame = stats("frame", 10, vec![render]);
+        frame.cpu_time_sum = Some(Duration::from_millis(12));
+        frame.idle_sum = Some(Duration::from_millis(2));
+
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            roots: vec![frame],
+            ..ProfileSnapshot::default()
+        }
+        .without_wait();
+
+        let frame = &snapshot.roots[0];
+        assert_eq!(frame.duration_sum, Duration::from_millis(14));
+        assert_eq!(frame.cpu_time_sum, Some(Duration::from_millis(11)));
+        assert_eq!(frame.idle_sum, Some(Duration::from_millis(2)));
+
+        let render = &frame.children[0];
+        assert_eq!(render.duration_sum, Duration::from_millis(2));
+        assert_eq!(render.cpu_time_sum, Some(Duration::from_millis(4)));
+        assert!(render.children.is_empty());
+    }
+
+    #[test]
+    fn test_inverted() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats(
+                "frame",
+                10,
+                vec![
+                    stats("physics", 4, vec![stats("serialize", 1, Vec::new())]),
+                    stats("net", 3, vec![stats("serialize", 2, Vec::new())]),
+                ],
+            )],
+        };
+
+        let inverted = snapshot.inverted();
+        let names: Vec<_> = inverted
+            .roots
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["frame", "physics", "serialize", "net"]);
+        assert_eq!(inverted.roots[0].duration_sum, Duration::from_millis(6));
+        assert!(inverted.roots[0].children.is_empty());
+
+        let serialize = &inverted.roots[2];
+        assert_eq!(serialize.num_calls, 4);
+        assert_eq!(serialize.duration_sum, Duration::from_millis(6));
+
+        let callers: Vec<_> = serialize
+            .children
+            .iter()
+            .map(|caller| (caller.name.as_str(), caller.duration_sum))
+            .collect();
+        assert_eq!(
+            callers,
+            vec![
+                ("net", Duration::from_millis(4)),
+                ("physics", Duration::from_millis(2)),
+            ]
+        );
+        assert_eq!(serialize.children[0].children[0].name, "frame");
+        assert_eq!(
+            serialize.children[0].children[0].duration_sum,
+            Duration::from_millis(4)
+        );
+    }
+
+    #[test]
+    fn test_write_folded() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats(
+                "frame",
+                10,
+                vec![
+                    stats("physics", 4, vec![stats("serialize", 4, Vec::new())]),
+                    stats("net; tcp", 3, Vec::new()),
+                ],
+            )],
+        };
+
+        let mut folded = Vec::new();
+        snapshot.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "frame 6000000\n\
+             frame;physics;serialize 8000000\n\
+             frame;net:_tcp 6000000\n"
+        );
+    }
+
+    #[test]
+    fn test_write_folded_with_prefix() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![
+                stats("frame", 10, vec![stats("physics", 4, Vec::new())]),
+                stats("load", 1, Vec::new()),
+            ],
+        };
+
+        let mut folded = Vec::new();
+        snapshot
+            .write_folded_with_prefix("ThreadId(1)-main", &mut folded)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "ThreadId(1)-main;frame 12000000\n\
+             ThreadId(1)-main;frame;physics 8000000\n\
+             ThreadId(1)-main;load 2000000\n"
+        );
+    }
+
+    #[cfg(feature = "inferno")]
+    #[test]
+    fn test_write_flamegraph_svg() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![stats("physics", 4, Vec::new())])],
+        };
+
+        let mut svg = Vec::new();
+        snapshot.write_flamegraph_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("physics"));
+    }
+
+    #[test]
+    fn test_frame_lows() {
+        let frames = (0..1000).map(|i| if i % 100 == 0 { 30 } else { 10 });
+        let lows = FrameLows::new(frames.chain(vec![100]));
+
+        assert_eq!(lows.num_frames, 1001);
+        assert_eq!(lows.point_one_percent, Duration::from_nanos(65));
+        assert_eq!(lows.one_percent, Duration::from_nanos((100 + 10 * 30) / 11));
+    }
+
+    #[test]
+    fn test_confidence_interval() {
+        let mut stats = stats("a", 4, Vec::new());
+        assert_eq!(stats.duration_confidence_interval(), None);
+
+        // Durations of 3ms and 5ms.
+        stats.duration_variance = Some(2e-6);
+        assert_eq!(
+            stats.duration_std_dev(),
+            Some(Duration::from_nanos(1_414_214))
+        );
+        assert_eq!(
+            stats.duration_std_error().unwrap(),
+            Duration::from_millis(1)
+        );
+
+        let (low, high) = stats.duration_confidence_interval().unwrap();
+        assert_eq!(low, Duration::from_secs(0));
+        assert!((high.as_secs_f64() - 0.016706).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hottest_path() {
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_millis(40),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: vec![
+                stats("setup", 1, Vec::new()),
+                stats(
+                    "frame",
+                    10,
+                    vec![
+                        stats("physics", 4, vec![stats("collisions", 1, Vec::new())]),
+                        stats("render", 5, Vec::new()),
+                    ],
+                ),
+            ],
+        };
+
+        let path = snapshot.hottest_path();
+        let names: Vec<_> = path
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["frame", "render"]);
+        assert_eq!(path.to_string(), "frame 50.00% > render 25.00%");
+
+        let empty = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: Vec::new(),
+            overhead: None,
+            roots: Vec::new(),
+        };
+        assert!(empty.hottest_path().entries.is_empty());
+    }
+
+    #[test]
+    fn test_read_json() {
+        let mut child = stats("physics", 4, Vec::new());
+        child.recursions = 3;
+        let snapshot = ProfileSnapshot {
+            total_duration: Duration::from_secs(1),
+            metadata: vec![("map".to_string(), "dust\"2".to_string())],
+            overhead: None,
+            roots: vec![stats("frame", 10, vec![child])],
+        };
+
+        let mut json = Vec::new();
+        snapshot.write_json(&mut json).unwrap();
+        let read = ProfileSnapshot::read_json(std::str::from_utf8(&json).unwrap()).unwrap();
+
+        assert_eq!(read.total_duration, snapshot.total_duration);
+        assert_eq!(read.metadata, snapshot.metadata);
+        assert_eq!(read.to_string(), snapshot.to_string());
+        assert_eq!(read.roots[0].children[0].recursions, 3);
+
+        assert!(ProfileSnapshot::read_json("{\"scopes\": [{}]}").is_err());
+        assert!(ProfileSnapshot::read_json("[").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+//! Export of the durations of scopes in the StatsD format.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use crate::{HistoryRecorder, HistorySample, Scope, ScopeId};
+
+/// Maximal size of a datagram, chosen so that datagrams are not fragmented
+/// on typical networks.
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Sends the totals of every scope per interval to a StatsD server.
+pub(crate) struct StatsdExporter {
+    socket: UdpSocket,
+
+    /// Suffix with DogStatsD tags that is appended to every metric, or an
+    /// empty string if there are no tags.
+    tags: String,
+
+    /// Computes the totals per interval.
+    totals: HistoryRecorder,
+}
+
+impl StatsdExporter {
+    pub fn new<A: ToSocketAddrs>(
+        interval: Duration,
+        addr: A,
+        tags: &[(&str, &str)],
+    ) -> io::Result<StatsdExporter> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+
+        let socket = UdpSocket::bind(local_addr)?;
+        socket.connect(addr)?;
+
+        Ok(StatsdExporter {
+            socket,
+            tags: format_tags(tags),
+            totals: HistoryRecorder::new(interval, 1),
+        })
+    }
+
+    /// Forget the totals of the previous interval, since the scope tree has
+    /// been reset.
+    pub fn reset(&mut self) {
+        self.totals.last_totals.clear();
+    }
+
+    /// Send the totals of the scopes in `roots` if the current interval has
+    /// ended.
+    pub fn send_if_due(&mut self, scopes: &[Scope], roots: &[ScopeId]) {
+        if !self.totals.record_if_due(scopes, roots) {
+            return;
+        }
+
+        if let Some(sample) = self.totals.samples.back() {
+            for packet in packets(sample, &self.tags) {
+                if let Err(err) = self.socket.send(packet.as_bytes()) {
+                    log::warn!("coarse_prof: failed to send StatsD metrics: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Format `tags` in the DogStatsD format, e.g. `|#env:prod,host:a`.
+fn format_tags(tags: &[(&str, &str)]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+
+    let tags: Vec<_> = tags
+        .iter()
+        .map(|(key, value)| format!("{}:{}", sanitize(key), sanitize(value)))
+        .collect();
+    format!("|#{}", tags.join(","))
+}
+
+/// Turn the path of a scope into a metric name, separating the names of the
+/// scopes with `.` and replacing characters that have a meaning in the StatsD
+/// format.
+fn metric_name(path: &str) -> String {
+    path.split('/').map(sanitize).collect::<Vec<_>>().join(".")
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns the metrics of `sample`, split into packets of at most
+/// `MAX_PACKET_SIZE` bytes.
+///
+/// For every scope, the mean duration in milliseconds is sent as a timing and
+/// the number of calls as a counter, e.g.:
+/// ```text
+/// frame.physics:4.2|ms|#env:prod
+/// frame.physics.calls:60|c|#env:prod
+/// ```
+fn packets(sample: &HistorySample, tags: &str) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+
+    for scope in &sample.scopes {
+        let name = metric_name(&scope.path);
+        let mean_ms = scope.duration_sum.as_secs_f64() * 1e3 / scope.num_calls as f64;
+
+        for line in [
+            format!("{}:{}|ms{}", name, mean_ms, tags),
+            format!("{}.calls:{}|c{}", name, scope.num_calls, tags),
+        ] {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+                packets.push(std::mem::take(&mut packet));
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+    }
+
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{HistorySample, HistoryScope};
+
+    fn sample(num_scopes: usize) -> HistorySample {
+        HistorySample {
+            start: Duration::from_secs(0),
+            duration: Duration::from_secs(1),
+            scopes: (0..num_scopes)
+                .map(|i| HistoryScope {
+                    path: format!("frame/physics {}", i),
+                    id: i as u64 + 2,
+                    parent_id: Some(1),
+                    num_calls: 4,
+                    duration_sum: Duration::from_millis(10),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        let tags = super::format_tags(&[("env", "prod"), ("host", "a:b")]);
+        assert_eq!(tags, "|#env:prod,host:a_b");
+
+        assert_eq!(
+            super::packets(&sample(1), &tags),
+            vec![
+                "frame.physics_0:2.5|ms|#env:prod,host:a_b\n\
+                 frame.physics_0.calls:4|c|#env:prod,host:a_b"
+            ]
+        );
+        assert!(super::packets(&sample(0), "").is_empty());
+    }
+
+    #[test]
+    fn test_packet_size() {
+        let packets = super::packets(&sample(100), "");
+        assert!(packets.len() > 1);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.len() <= super::MAX_PACKET_SIZE));
+        assert_eq!(
+            packets
+                .iter()
+                .map(|packet| packet.lines().count())
+                .sum::<usize>(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_send() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        crate::reset();
+        crate::send_statsd_every(Duration::new(0, 0), server.local_addr().unwrap(), &[]).unwrap();
+        {
+            crate::profile!("frame");
+        }
+        {
+            crate::profile!("frame");
+        }
+        crate::stop_sending_statsd();
+
+        let mut buf = [0; super::MAX_PACKET_SIZE];
+        let len = server.recv(&mut buf).unwrap();
+        let packet = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(packet.starts_with("frame:"), "{}", packet);
+        assert!(packet.ends_with("\nframe.calls:1|c"), "{}", packet);
+    }
+}
@@ -0,0 +1,284 @@
+//! Timelines of when scopes ran within a frame.
+
+use std::io;
+use std::time::Duration;
+
+/// Width of the rendered SVG in pixels.
+const WIDTH: f64 = 1200.0;
+
+/// Width of the column with the scope names in pixels.
+const LABEL_WIDTH: f64 = 240.0;
+
+/// Height of every row in pixels.
+const ROW_HEIGHT: f64 = 20.0;
+
+/// Approximate width of a character of the monospace font in pixels, for
+/// deciding whether a name fits into its span.
+const CHAR_WIDTH: f64 = 7.5;
+
+/// When the scopes ran within a single frame.
+///
+/// Created by [`timeline`](fn.timeline.html) if recording has been enabled
+/// with [`set_record_timeline`](fn.set_record_timeline.html).
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    /// Duration of the frame.
+    pub duration: Duration,
+
+    /// One row per scope that ran within the frame, in depth-first order,
+    /// starting with the root scope of the frame.
+    pub rows: Vec<TimelineRow>,
+}
+
+/// The calls of a single scope in a [`Timeline`](struct.Timeline.html).
+#[derive(Debug, Clone)]
+pub struct TimelineRow {
+    /// Name of the scope.
+    pub name: String,
+
+    /// Depth of the scope in the tree, with zero being the root.
+    pub depth: usize,
+
+    /// Start and end of every call, relative to the start of the frame.
+    pub spans: Vec<(Duration, Duration)>,
+}
+
+impl Timeline {
+    /// Render the timeline as an SVG Gantt chart, with one row per scope.
+    pub fn write_svg<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let height = ROW_HEIGHT * self.rows.len() as f64;
+        let scale = if self.duration > Duration::new(0, 0) {
+            (WIDTH - LABEL_WIDTH) / self.duration.as_secs_f64()
+        } else {
+            0.0
+        };
+
+        writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"12\">",
+            WIDTH, height,
+        )?;
+
+        for (i, row) in self.rows.iter().enumerate() {
+            let y = ROW_HEIGHT * i as f64;
+            let color = format!("hsl({}, 60%, 60%)", (row.depth * 47) % 360);
+
+            write!(out, "<text x=\"{}\" y=\"{}\">", 4 + 8 * row.depth, y + 14.0)?;
+            write_escaped(out, &row.name)?;
+            writeln!(out, "</text>")?;
+
+            for &(start, end) in &row.spans {
+                write!(
+                    out,
+                    "<rect x=\"{:.2}\" y=\"{}\" width=\"{:.2}\" height=\"{}\" fill=\"{}\"><title>",
+                    LABEL_WIDTH + start.as_secs_f64() * scale,
+                    y + 2.0,
+                    ((end - start).as_secs_f64() * scale).max(0.5),
+                    ROW_HEIGHT - 4.0,
+                    color,
+                )?;
+                write_escaped(out, &row.name)?;
+                writeln!(
+                    out,
+                    ": {:.3}ms at {:.3}ms</title></rect>",
+                    (end - start).as_secs_f64() * 1000.0,
+                    start.as_secs_f64() * 1000.0,
+                )?;
+            }
+        }
+
+        writeln!(out, "</svg>")
+    }
+
+    /// Render the timeline as an SVG swim-lane chart, with one lane per depth
+    /// of the tree. Calls are labeled with the name of their scope where it
+    /// fits, so the frame can be read like a trace in any browser.
+    pub fn write_lanes_svg<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        write_lanes_svg(&[("", self)], out)
+    }
+}
+
+/// Render the timelines of several threads as one SVG swim-lane chart, with
+/// one lane per thread and depth, labeled with the given thread names.
+///
+/// Timelines are recorded per thread, so collect them with
+/// [`timeline`](fn.timeline.html) on every thread first. Each timeline is
+/// aligned at the start of its own frame, and all of them share the time
+/// scale of the longest frame.
+///
+/// # Example
+///
+/// ```
+/// coarse_prof::set_record_timeline(16);
+/// {
+///     coarse_prof::profile!("frame");
+/// }
+///
+/// let main = coarse_prof::timeline().unwrap();
+/// let worker = std::thread::spawn(|| {
+///     coarse_prof::set_record_timeline(16);
+///     {
+///         coarse_prof::profile!("job");
+///     }
+///     coarse_prof::timeline().unwrap()
+/// })
+/// .join()
+/// .unwrap();
+///
+/// let mut svg = Vec::new();
+/// coarse_prof::write_lanes_svg(&[("main", &main), ("worker", &worker)], &mut svg).unwrap();
+/// ```
+pub fn write_lanes_svg<W: io::Write>(
+    timelines: &[(&str, &Timeline)],
+    out: &mut W,
+) -> io::Result<()> {
+    let total_lanes: usize = timelines
+        .iter()
+        .map(|(_, timeline)| num_lanes(timeline))
+        .sum();
+    let duration = timelines
+        .iter()
+        .map(|(_, timeline)| timeline.duration)
+        .max()
+        .unwrap_or_default();
+    let scale = if duration > Duration::new(0, 0) {
+        (WIDTH - LABEL_WIDTH) / duration.as_secs_f64()
+    } else {
+        0.0
+    };
+
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"monospace\" font-size=\"12\">",
+        WIDTH,
+        ROW_HEIGHT * total_lanes as f64,
+    )?;
+
+    let mut lane_offset = 0;
+    for &(thread, timeline) in timelines {
+        for depth in 0..num_lanes(timeline) {
+            let y = ROW_HEIGHT * (lane_offset + depth) as f64;
+
+            write!(out, "<text x=\"4\" y=\"{}\">", y + 14.0)?;
+            if thread.is_empty() {
+                write!(out, "depth {}", depth)?;
+            } else {
+                write_escaped(out, thread)?;
+                write!(out, " {}", depth)?;
+            }
+            writeln!(out, "</text>")?;
+
+            for row in timeline.rows.iter().filter(|row| row.depth == depth) {
+                let hue = row.name.bytes().fold(0, |hue: usize, b| {
+                    hue.wrapping_mul(31).wrapping_add(b as usize)
+                }) % 360;
+
+                for &(start, end) in &row.spans {
+                    let x = LABEL_WIDTH + start.as_secs_f64() * scale;
+                    let width = ((end - start).as_secs_f64() * scale).max(0.5);
+                    write!(
+                        out,
+                        "<rect x=\"{:.2}\" y=\"{}\" width=\"{:.2}\" height=\"{}\" \
+                         fill=\"hsl({}, 60%, 70%)\"><title>",
+                        x,
+                        y + 2.0,
+                        width,
+                        ROW_HEIGHT - 4.0,
+                        hue,
+                    )?;
+                    write_escaped(out, &row.name)?;
+                    writeln!(
+                        out,
+                        ": {:.3}ms at {:.3}ms</title></rect>",
+                        (end - start).as_secs_f64() * 1000.0,
+                        start.as_secs_f64() * 1000.0,
+                    )?;
+
+                    if width >= CHAR_WIDTH * row.name.chars().count() as f64 + 4.0 {
+                        write!(out, "<text x=\"{:.2}\" y=\"{}\">", x + 2.0, y + 14.0)?;
+                        write_escaped(out, &row.name)?;
+                        writeln!(out, "</text>")?;
+                    }
+                }
+            }
+        }
+        lane_offset += num_lanes(timeline);
+    }
+
+    writeln!(out, "</svg>")
+}
+
+fn num_lanes(timeline: &Timeline) -> usize {
+    timeline
+        .rows
+        .iter()
+        .map(|row| row.depth + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn write_escaped<W: io::Write>(out: &mut W, value: &str) -> io::Result<()> {
+    for c in value.chars() {
+        match c {
+            '&' => write!(out, "&amp;")?,
+            '<' => write!(out, "&lt;")?,
+            '>' => write!(out, "&gt;")?,
+            '"' => write!(out, "&quot;")?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Timeline, TimelineRow};
+
+    fn row(name: &str, depth: usize, spans: &[(u64, u64)]) -> TimelineRow {
+        TimelineRow {
+            name: name.to_string(),
+            depth,
+            spans: spans
+                .iter()
+                .map(|&(start, end)| (Duration::from_millis(start), Duration::from_millis(end)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_write_lanes_svg() {
+        let timeline = Timeline {
+            duration: Duration::from_millis(10),
+            rows: vec![
+                row("frame", 0, &[(0, 10)]),
+                row("physics", 1, &[(0, 4)]),
+                row("a<b", 1, &[(5, 6), (7, 8)]),
+                row("physics_update_loop_with_a_long_name", 2, &[(0, 1)]),
+            ],
+        };
+
+        let mut svg = Vec::new();
+        timeline.write_lanes_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        assert!(svg.contains("height=\"60\""));
+        assert!(svg.contains(">depth 0</text>"));
+        assert!(svg.contains(">depth 1</text>"));
+        assert!(svg.contains(">depth 2</text>"));
+        assert!(!svg.contains("depth 3"));
+        assert_eq!(svg.matches("<rect").count(), 5);
+        assert!(svg.contains(">physics</text>"));
+        assert!(svg.contains("a&lt;b: 1.000ms at 5.000ms"));
+
+        let mut svg = Vec::new();
+        super::write_lanes_svg(&[("main", &timeline), ("worker", &timeline)], &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        assert!(svg.contains("height=\"120\""));
+        assert!(svg.contains(">worker 1</text>"));
+    }
+}
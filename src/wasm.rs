@@ -0,0 +1,28 @@
+//! Functions exported to JavaScript with wasm-bindgen.
+//!
+//! WASM programs usually run on a single thread, so these access the
+//! profiler of that thread, which is the one that the exported functions are
+//! called on. The functions are exported with prefixed names, so that they
+//! do not collide with the exports of other crates.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Returns the scope tree as JSON, in the same format as
+/// [`ProfileSnapshot::write_json`](../struct.ProfileSnapshot.html#method.write_json).
+/// Exported as `coarseProfGetJson`.
+#[wasm_bindgen(js_name = coarseProfGetJson)]
+pub fn get_profile_json() -> String {
+    let mut json = Vec::new();
+    crate::snapshot()
+        .write_json(&mut json)
+        .expect("writing to a Vec can not fail");
+
+    String::from_utf8(json).expect("JSON is valid UTF-8")
+}
+
+/// Reset the profiler, see [`reset`](../fn.reset.html). Exported as
+/// `coarseProfReset`.
+#[wasm_bindgen(js_name = coarseProfReset)]
+pub fn reset() {
+    crate::reset();
+}
@@ -0,0 +1,148 @@
+//! A helper thread that reports scopes that have been active for too long.
+
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::clock::Instant;
+
+/// A scope that has been active for longer than the limit of the watchdog.
+///
+/// Passed to the callback of [`start_watchdog`](fn.start_watchdog.html).
+#[derive(Debug, Clone)]
+pub struct StuckScope {
+    /// Name of the thread that is stuck in the scope, or its id if it has no
+    /// name.
+    pub thread: String,
+
+    /// Path of the scope in the tree, with names separated by `/`.
+    pub path: String,
+
+    /// How long the scope has been active when it was reported.
+    pub active_for: Duration,
+}
+
+/// A scope that is currently being visited by the watched thread.
+struct Entry {
+    name: &'static str,
+    since: Instant,
+
+    /// Has the scope already been reported?
+    reported: bool,
+}
+
+/// The watched thread's end of the watchdog, which publishes the scopes that
+/// the thread is currently visiting.
+pub(crate) struct Watched {
+    active: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl Watched {
+    /// Spawn a watchdog thread for the current thread, which is currently
+    /// visiting the scopes in `active_path`.
+    pub fn spawn<F>(
+        limit: Duration,
+        callback: F,
+        active_path: impl Iterator<Item = (&'static str, Instant)>,
+    ) -> Watched
+    where
+        F: Fn(&StuckScope) + Send + 'static,
+    {
+        let current = thread::current();
+        let thread_name = match current.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", current.id()),
+        };
+
+        let active = Arc::new(Mutex::new(
+            active_path
+                .map(|(name, since)| Entry {
+                    name,
+                    since,
+                    reported: false,
+                })
+                .collect(),
+        ));
+
+        let weak = Arc::downgrade(&active);
+        let poll_interval = (limit / 4).clamp(Duration::from_millis(1), Duration::from_secs(1));
+        let spawned = thread::Builder::new()
+            .name("coarse-prof-watchdog".to_string())
+            .spawn(move || watch(weak, limit, poll_interval, thread_name, callback));
+        if let Err(err) = spawned {
+            log::warn!("coarse_prof: failed to spawn watchdog thread: {}", err);
+        }
+
+        Watched { active }
+    }
+
+    pub fn enter(&self, name: &'static str, since: Instant) {
+        self.lock().push(Entry {
+            name,
+            since,
+            reported: false,
+        });
+    }
+
+    pub fn leave(&self) {
+        self.lock().pop();
+    }
+
+    /// Keep only the `len` outermost active scopes.
+    pub fn truncate(&self, len: usize) {
+        self.lock().truncate(len);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Entry>> {
+        self.active.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Poll the scopes of the watched thread until it stops being watched.
+fn watch<F>(
+    active: Weak<Mutex<Vec<Entry>>>,
+    limit: Duration,
+    poll_interval: Duration,
+    thread_name: String,
+    callback: F,
+) where
+    F: Fn(&StuckScope),
+{
+    loop {
+        thread::sleep(poll_interval);
+
+        let active = match active.upgrade() {
+            Some(active) => active,
+            None => return,
+        };
+
+        let stuck = {
+            let mut entries = active.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+
+            // Report only the innermost stuck scope, since its ancestors are
+            // necessarily stuck as well.
+            let innermost = entries
+                .iter()
+                .rposition(|entry| now.duration_since(entry.since) >= limit);
+            match innermost {
+                Some(i) if !entries[i].reported => {
+                    for entry in &mut entries[..=i] {
+                        entry.reported = true;
+                    }
+                    let path: Vec<_> = entries[..=i].iter().map(|entry| entry.name).collect();
+                    Some(StuckScope {
+                        thread: thread_name.clone(),
+                        path: path.join("/"),
+                        active_for: now.duration_since(entries[i].since),
+                    })
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(stuck) = stuck {
+            callback(&stuck);
+        }
+    }
+}